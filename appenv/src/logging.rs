@@ -0,0 +1,160 @@
+use std::fmt::{self as std_fmt, Display};
+
+use tracing::Subscriber;
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::Layered,
+    prelude::*,
+    registry::LookupSpan,
+    reload, EnvFilter, Layer, Registry,
+};
+
+use crate::{Environment, DEFAULT_LOG_LEVEL};
+
+/// How the binaries write log lines, from LOG_FORMAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// human readable lines
+    #[default]
+    Pretty,
+    /// one JSON object per line, with the timestamp, level, target and fields
+    Json,
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut std_fmt::Formatter<'_>) -> std_fmt::Result {
+        match self {
+            LogFormat::Pretty => write!(f, "pretty"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type FormatLayer = Box<dyn Layer<Filtered> + Send + Sync>;
+
+/// Handles to the installed subscriber's filter and format, to set them from the
+/// environment once it has been read.
+pub struct TracingHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    format: reload::Handle<FormatLayer, Filtered>,
+}
+
+/// Install the tracing subscriber, writing pretty lines at info until apply is
+/// called, so what init logs before the environment is read is not lost.
+pub fn init_tracing() -> TracingHandle {
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::new(DEFAULT_LOG_LEVEL));
+    let (format, format_handle) =
+        reload::Layer::new(format_layer(LogFormat::Pretty, std::io::stdout));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(format)
+        .init();
+    TracingHandle {
+        filter: filter_handle,
+        format: format_handle,
+    }
+}
+
+impl TracingHandle {
+    /// Log as LOG_FORMAT and LOG_LEVEL ask.  LOG_LEVEL is a level name or EnvFilter
+    /// directives such as `conversation=debug,ethers=warn`; an invalid filter keeps
+    /// info with a warning.
+    pub fn apply(&self, env: &Environment) {
+        if let Err(err) = self
+            .format
+            .reload(format_layer(env.log_format, std::io::stdout))
+        {
+            tracing::warn!("LOG_FORMAT not applied: {err}");
+        }
+        match EnvFilter::try_new(&env.log_level) {
+            Ok(filter) => {
+                if let Err(err) = self.filter.reload(filter) {
+                    tracing::warn!("LOG_LEVEL not applied: {err}");
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "invalid LOG_LEVEL {:?}, logging at info: {err}",
+                    env.log_level
+                );
+            }
+        }
+    }
+}
+
+/// The layer writing log lines in a format.
+fn format_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Pretty => fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_target(true)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    /// Collects everything written to it, one writer per log line.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'writer> MakeWriter<'writer> for Capture {
+        type Writer = Capture;
+
+        fn make_writer(&'writer self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn capture(format: LogFormat) -> String {
+        let output = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(format_layer(format, output.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(sent = 3, "run finished");
+        });
+        let output = output.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_json_format() {
+        let output = capture(LogFormat::Json);
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert!(line["timestamp"].is_string());
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "appenv::logging::tests");
+        assert_eq!(line["fields"]["message"], "run finished");
+        assert_eq!(line["fields"]["sent"], 3);
+    }
+
+    #[test]
+    fn test_pretty_format() {
+        let output = capture(LogFormat::Pretty);
+        assert!(output.contains("run finished"));
+        assert!(serde_json::from_str::<serde_json::Value>(output.trim()).is_err());
+    }
+}
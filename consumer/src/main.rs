@@ -1,30 +1,257 @@
 use anyhow::Error;
-use std::cmp::min;
+use clap::{Parser, Subcommand};
+use ethers::{core::rand::thread_rng, signers::LocalWallet, types::U256, utils::format_ether};
+use std::{cmp::min, collections::HashMap, sync::Arc};
 
-use appenv::{init, printenv};
-use conversation::MessageSender;
+use appenv::{init, init_tracing, printenv, printenv_json};
+use conversation::{
+    wallet_from_mnemonic, wallet_key_hex, AesGcmEncryptor, DecodePolicy, Envelope, FileSink,
+    FollowOptions, JsonCodec, LogSink, MessageRewindOrder, MessageSender, MessageSenderConfig,
+    MessageSink, PayloadCodec, StdoutSink, WebhookConfig, WebhookSink, DEFAULT_HD_PATH,
+};
+
+/// Follow conversations and write each message to a sink.
+#[derive(Parser)]
+struct Args {
+    /// POST each message as JSON to this URL instead of using MESSAGE_SINK
+    #[arg(long)]
+    webhook: Option<String>,
+    /// print the resolved configuration as JSON, with secrets redacted
+    #[arg(long)]
+    print_config_json: bool,
+    /// check that rewound messages are JSON envelopes in sequence for each sender
+    #[arg(long)]
+    envelopes: bool,
+    /// log rewound messages newest first instead of oldest first
+    #[arg(long)]
+    newest_first: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// check the configuration, RPC connection, contract and wallet balance, then exit
+    Check,
+}
+
+/// Check rewound messages are envelopes whose sequence numbers increase for each
+/// sender, warning about each one that is not.  Returns the number of invalid messages.
+fn check_envelopes(conversation: &str, messages: &[String]) -> usize {
+    let codec = JsonCodec::<Envelope>::new();
+    let mut last_sequence = HashMap::new();
+    let mut invalid = 0;
+    for (i, message) in messages.iter().enumerate() {
+        match codec.decode(message.as_bytes()) {
+            Ok(envelope) => {
+                let previous = last_sequence.insert(envelope.sender, envelope.sequence);
+                if previous.is_some_and(|previous| previous >= envelope.sequence) {
+                    tracing::warn!(
+                        "{conversation} Message {i}: sequence {} from {:?} is out of order",
+                        envelope.sequence,
+                        envelope.sender
+                    );
+                    invalid += 1;
+                }
+            }
+            Err(err) => {
+                tracing::warn!("{conversation} Message {i}: not an envelope: {err}");
+                invalid += 1;
+            }
+        }
+    }
+    invalid
+}
+
+/// Select the message sink named by MESSAGE_SINK: log (the default), stdout or file:<path>.
+fn message_sink(spec: Option<&str>) -> Result<Box<dyn MessageSink>, Error> {
+    match spec {
+        None | Some("log") => Ok(Box::new(LogSink)),
+        Some("stdout") => Ok(Box::new(StdoutSink)),
+        Some(spec) => match spec.strip_prefix("file:") {
+            Some(path) if !path.is_empty() => Ok(Box::new(FileSink::open(path)?)),
+            _ => Err(anyhow::anyhow!("unknown MESSAGE_SINK: {spec}")),
+        },
+    }
+}
+
+/// Connect and check the chain, contract and wallet without following, printing a
+/// pass or fail line for each.  Exits with status 1 if any check fails.
+async fn check(
+    rpc_url: String,
+    private_key: String,
+    mut config: MessageSenderConfig,
+) -> Result<(), Error> {
+    println!("PASS configuration");
+    // report a missing contract as a failed check rather than a connection error
+    config.verify_contract = false;
+    let report = match MessageSender::new_with_config(rpc_url, private_key, config).await {
+        Ok(sender) => sender.check_setup().await,
+        Err(err) => Err(err),
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(err) => {
+            println!("FAIL provider: {err}");
+            std::process::exit(1);
+        }
+    };
+    println!("PASS connected to chain {}", report.chain_id);
+    let passed = report.contract_deployed();
+    if passed {
+        println!(
+            "PASS contract {:?} deployed, {} bytes, version {}",
+            report.contract_address,
+            report.contract_code_bytes,
+            report.contract_version.as_deref().unwrap_or("unknown")
+        );
+    } else {
+        println!("FAIL no contract deployed at {:?}", report.contract_address);
+    }
+    // following sends nothing, so the balance is only reported
+    println!(
+        "INFO account {:?} balance {} ether",
+        report.account,
+        format_ether(report.balance)
+    );
+    if !passed {
+        println!("check failed");
+        std::process::exit(1);
+    }
+    println!("check passed");
+    Ok(())
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
+    let args = Args::parse();
+    let tracing_handle = init_tracing();
     init();
-    let env = appenv::environment();
+    let env = match appenv::try_environment() {
+        Ok(env) => env,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
+    tracing_handle.apply(&env);
     printenv(&env);
-    let message_sender = MessageSender::new(env.rpc_url, env.private_key).await?;
+    if args.print_config_json {
+        println!("{}", printenv_json(&env));
+    }
+    let mut config = MessageSenderConfig::default();
+    if let Some(contract_address) = &env.contract_address {
+        config.contract_address = contract_address.parse()?;
+    }
+    if let Some(gas_limit) = env.gas_limit {
+        config.gas_limit = gas_limit;
+    }
+    if let Some(confirmations) = env.confirmations {
+        config.required_confirmations = confirmations;
+    }
+    if args.newest_first {
+        config.rewind_order = MessageRewindOrder::NewestFirst;
+    }
+    if let Some(payload_key) = &env.payload_key {
+        config = config.with_encryptor(Arc::new(AesGcmEncryptor::from_hex(payload_key)?));
+    }
+    let webhook = match args.webhook {
+        Some(url) => Some(WebhookSink::new(url, WebhookConfig::default())?),
+        None => None,
+    };
+    let fallback;
+    let sink: &dyn MessageSink = match &webhook {
+        Some(webhook) => webhook,
+        None => {
+            fallback = message_sink(env.message_sink.as_deref())?;
+            fallback.as_ref()
+        }
+    };
+    let private_key = match &env.mnemonic {
+        Some(phrase) => {
+            let hd_path = env.hd_path.as_deref().unwrap_or(DEFAULT_HD_PATH);
+            wallet_key_hex(&wallet_from_mnemonic(phrase, hd_path)?)
+        }
+        None if !env.private_key.is_empty() => env.private_key.clone(),
+        None => {
+            // following only reads the chain, so any key will do
+            tracing::info!("No PRIVATE_KEY or MNEMONIC, following with a throwaway wallet");
+            wallet_key_hex(&LocalWallet::new(&mut thread_rng()))
+        }
+    };
+    if matches!(args.command, Some(Command::Check)) {
+        return check(env.rpc_url, private_key, config).await;
+    }
+    let message_sender = MessageSender::new_with_config(env.rpc_url, private_key, config).await?;
+    if let Some(public_key) = &env.public_key {
+        message_sender.check_address(public_key);
+    }
 
-    let rewind = message_sender
-        .rewind(&env.conversation_id, min(env.message_count, 1000))
-        .await?;
-    for (i, message) in rewind.message.iter().enumerate() {
-        tracing::info!("Message {}: {}", i, message);
+    let mut start_blocks = Vec::with_capacity(env.conversation_ids.len());
+    for conversation_id in env.conversation_ids.iter() {
+        let rewind = message_sender
+            .rewind(conversation_id, min(env.message_count, 1000))
+            .await?;
+        for (i, message) in rewind.message.iter().enumerate() {
+            tracing::info!("{conversation_id} Message {}: {}", i, message);
+        }
+        if args.envelopes {
+            // sequence numbers are checked oldest first
+            let mut messages = rewind.message.clone();
+            if args.newest_first {
+                messages.reverse();
+            }
+            let invalid = check_envelopes(conversation_id, &messages);
+            tracing::info!(
+                "{conversation_id} {} of {} messages are valid envelopes",
+                rewind.message.len() - invalid,
+                rewind.message.len()
+            );
+        }
+        start_blocks.push(rewind.follow_start_block());
     }
 
-    let callback = |s: &String| tracing::info!("Message: {}", s);
-    message_sender
-        .follow_messages(&env.conversation_id, &rewind.last_change, callback)
+    // resume after the newest rewound block so those messages are not delivered twice,
+    // or at the current head when no conversation has any history, unless START_BLOCK
+    // names where to follow from
+    let start_block = match env.start_block {
+        Some(start_block) => {
+            tracing::info!("following from START_BLOCK {start_block}");
+            U256::from(start_block)
+        }
+        None => start_blocks.into_iter().max().unwrap_or_default(),
+    };
+    let options = FollowOptions {
+        max_messages: Some(u64::from(env.message_count)),
+        max_duration: None,
+        // one malformed log must not halt monitoring
+        on_decode_error: DecodePolicy::Skip,
+    };
+    let summary = message_sender
+        .follow_conversations_to_sink(&env.conversation_ids, &start_block, &options, sink)
         .await?;
+    tracing::info!(
+        "Followed {} messages in {:.3}s, skipped {} undecodable, stopped by {:?}",
+        summary.messages,
+        summary.elapsed.as_secs_f64(),
+        summary.skipped,
+        summary.stopped_by
+    );
+    if let Some(webhook) = webhook {
+        let stats = webhook.finish().await;
+        tracing::info!(
+            "Webhook delivered {}, failed {}, dropped {}",
+            stats.delivered,
+            stats.failed,
+            stats.dropped
+        );
+        if stats.failed + stats.dropped > 0 {
+            return Err(anyhow::anyhow!(
+                "{} messages were not delivered to the webhook",
+                stats.failed + stats.dropped
+            ));
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,72 @@
+//! Time rewinds of a long conversation on the fake chain with every message traced
+//! and with one in a hundred traced, with trace logging of this crate enabled and
+//! written to a sink.  Run with
+//! `cargo bench -p conversation --features test-fakechain --bench trace_sampling`.
+//! On a development machine tracing every message took about 20µs per rewound
+//! message and tracing one in a hundred about 16.5µs, a rewind a fifth faster.
+
+use std::time::{Duration, Instant};
+
+use conversation::{
+    fakechain::{FakeChain, FAKE_CHAIN_PRIVATE_KEY},
+    MessageSender, MessageSenderConfig,
+};
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// number of messages in the conversation, all of them rewound
+const MESSAGES: u32 = 500;
+/// number of timed rewinds for each sampling rate
+const ROUNDS: u32 = 20;
+
+async fn connect(chain: &FakeChain, trace_sample_every: u64) -> MessageSender {
+    let config = MessageSenderConfig {
+        provider_interval: Some(Duration::from_millis(10)),
+        trace_sample_every,
+        ..Default::default()
+    };
+    MessageSender::new_with_config(chain.url(), FAKE_CHAIN_PRIVATE_KEY.to_string(), config)
+        .await
+        .unwrap()
+}
+
+fn main() {
+    tracing_subscriber::registry()
+        .with(Targets::new().with_target("conversation", Level::TRACE))
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::sink))
+        .init();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let conversation = String::from("bench");
+        let sender = connect(&chain, 1).await;
+        // one block holds every message, so a rewind is a single log query
+        chain.batch_sends(true);
+        let messages: Vec<String> = (0..MESSAGES).map(|i| format!("message {i}")).collect();
+        let sends = messages
+            .iter()
+            .map(|message| sender.send_message(&conversation, message));
+        for receipt in futures::future::join_all(sends).await {
+            receipt.unwrap();
+        }
+
+        for every in [1, 100] {
+            let sender = connect(&chain, every).await;
+            // warm up the connection before timing
+            sender.rewind(&conversation, MESSAGES).await.unwrap();
+            let started = Instant::now();
+            for _ in 0..ROUNDS {
+                let rewind = sender.rewind(&conversation, MESSAGES).await.unwrap();
+                assert_eq!(rewind.message.len(), MESSAGES as usize);
+            }
+            let per_rewind = started.elapsed() / ROUNDS;
+            println!(
+                "trace_sample_every={every:<3} {per_rewind:?} per rewind of {MESSAGES} messages, {:?} per message",
+                per_rewind / MESSAGES
+            );
+        }
+    });
+}
@@ -0,0 +1,211 @@
+use std::{fmt, time::Duration};
+
+use anyhow::Error;
+use ethers::types::{Address, U256};
+
+use crate::{
+    connect, connect_with_retry, MessageRewindOrder, MessageSender, MessageSenderConfig,
+    RetryPolicy, SpeedUpPolicy,
+};
+
+/// Builds a MessageSender one option at a time.  Options that are not set keep
+/// their MessageSenderConfig defaults; the RPC URL and private key are required.
+#[derive(Clone, Default)]
+pub struct MessageSenderBuilder {
+    rpc_url: Option<String>,
+    private_key: Option<String>,
+    config: MessageSenderConfig,
+    retry: Option<RetryPolicy>,
+}
+
+impl fmt::Debug for MessageSenderBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the RPC URL may carry an API key, so neither it nor the private key is shown
+        let redacted = |value: &Option<String>| value.as_ref().map(|_| "<redacted>");
+        f.debug_struct("MessageSenderBuilder")
+            .field("rpc_url", &redacted(&self.rpc_url))
+            .field("private_key", &redacted(&self.private_key))
+            .field("config", &self.config)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+impl MessageSenderBuilder {
+    /// A builder with every option at its default.
+    pub fn new() -> MessageSenderBuilder {
+        MessageSenderBuilder::default()
+    }
+
+    /// The RPC URL for the chain.
+    pub fn rpc_url(mut self, rpc_url: impl Into<String>) -> MessageSenderBuilder {
+        self.rpc_url = Some(rpc_url.into());
+        self
+    }
+
+    /// The hex encoded private key messages are sent from.
+    pub fn private_key(mut self, private_key: impl Into<String>) -> MessageSenderBuilder {
+        self.private_key = Some(private_key.into());
+        self
+    }
+
+    /// Start from a complete config; setters called afterwards override its fields.
+    pub fn config(mut self, config: MessageSenderConfig) -> MessageSenderBuilder {
+        self.config = config;
+        self
+    }
+
+    /// The address of the XPS MessageSender contract.
+    pub fn contract(mut self, contract_address: Address) -> MessageSenderBuilder {
+        self.config.contract_address = contract_address;
+        self
+    }
+
+    /// The gas limit for message transactions.
+    pub fn gas_limit(mut self, gas_limit: u64) -> MessageSenderBuilder {
+        self.config.gas_limit = gas_limit;
+        self
+    }
+
+    /// The number of confirmations send_message waits for.
+    pub fn confirmations(mut self, confirmations: usize) -> MessageSenderBuilder {
+        self.config.required_confirmations = confirmations;
+        self
+    }
+
+    /// How long a send waits for confirmation, or None to wait indefinitely.
+    pub fn confirmation_timeout(mut self, timeout: Option<Duration>) -> MessageSenderBuilder {
+        self.config.confirmation_timeout = timeout;
+        self
+    }
+
+    /// Replace sends' transactions with higher fee ones when they are not mined in
+    /// time.
+    pub fn speed_up(mut self, speed_up: SpeedUpPolicy) -> MessageSenderBuilder {
+        self.config.speed_up = Some(speed_up);
+        self
+    }
+
+    /// The order rewind returns messages in.
+    pub fn rewind_order(mut self, order: MessageRewindOrder) -> MessageSenderBuilder {
+        self.config.rewind_order = order;
+        self
+    }
+
+    /// Whether connecting checks code is deployed at the contract address.
+    pub fn verify_contract(mut self, verify_contract: bool) -> MessageSenderBuilder {
+        self.config.verify_contract = verify_contract;
+        self
+    }
+
+    /// The priority fee per gas recommended by get_gas_price_suggestion, in wei.
+    pub fn priority_fee_tip(mut self, priority_fee_tip: U256) -> MessageSenderBuilder {
+        self.config.priority_fee_tip = priority_fee_tip;
+        self
+    }
+
+    /// Retry the provider connection with backoff instead of failing on the first
+    /// error.
+    pub fn retry(mut self, retry: RetryPolicy) -> MessageSenderBuilder {
+        self.retry = Some(retry);
+        self
+    }
+
+    /**
+     * Connect and create the MessageSender.
+     * Returns Ok(MessageSender) if the provider connected and the private key is
+     * valid, or an error naming a missing required option.
+     */
+    #[tracing::instrument(name = "new", skip_all, fields(attempt = tracing::field::Empty))]
+    pub async fn build(self) -> Result<MessageSender, Error> {
+        let (rpc_url, private_key) = self.required()?;
+        let provider = match &self.retry {
+            Some(retry) => connect_with_retry(&rpc_url, retry).await?,
+            None => connect(&rpc_url).await?,
+        };
+        MessageSender::with_provider(provider, private_key, self.config).await
+    }
+
+    /*
+     * Check the required options are set.
+     * Returns Ok((rpc_url, private_key)).
+     */
+    fn required(&self) -> Result<(String, String), Error> {
+        let rpc_url = self
+            .rpc_url
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("MessageSenderBuilder requires an rpc_url"))?;
+        let private_key = self
+            .private_key
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("MessageSenderBuilder requires a private_key"))?;
+        Ok((rpc_url, private_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::REQUIRED_CONFIRMATIONS;
+
+    #[test]
+    fn test_builder_sets_config() {
+        let contract = Address::repeat_byte(0x42);
+        let builder = MessageSenderBuilder::new()
+            .rpc_url("ws://localhost:8545")
+            .private_key("0x01")
+            .contract(contract)
+            .gas_limit(500_000)
+            .rewind_order(MessageRewindOrder::NewestFirst)
+            .priority_fee_tip(U256::from(7));
+        assert_eq!(builder.config.contract_address, contract);
+        assert_eq!(builder.config.gas_limit, 500_000);
+        assert_eq!(builder.config.priority_fee_tip, U256::from(7));
+        assert_eq!(builder.config.rewind_order, MessageRewindOrder::NewestFirst);
+        // unset options keep their defaults
+        assert_eq!(
+            builder.config.required_confirmations,
+            REQUIRED_CONFIRMATIONS
+        );
+        assert!(builder.retry.is_none());
+        assert!(builder.required().is_ok());
+    }
+
+    #[test]
+    fn test_builder_setters_override_config() {
+        let config = MessageSenderConfig {
+            gas_limit: 1,
+            required_confirmations: 3,
+            ..Default::default()
+        };
+        let builder = MessageSenderBuilder::new().config(config).confirmations(5);
+        assert_eq!(builder.config.gas_limit, 1);
+        assert_eq!(builder.config.required_confirmations, 5);
+    }
+
+    #[test]
+    fn test_builder_debug_redacts_secrets() {
+        let private_key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let builder = MessageSenderBuilder::new()
+            .rpc_url("wss://eth-sepolia.g.alchemy.com/v2/secret_api_key")
+            .private_key(private_key);
+        let debug = format!("{builder:?}");
+        assert!(!debug.contains(private_key));
+        assert!(!debug.contains(&private_key[..16]));
+        assert!(!debug.contains("secret_api_key"));
+    }
+
+    #[test]
+    fn test_builder_requires_rpc_url_and_private_key() {
+        let err = MessageSenderBuilder::new()
+            .private_key("0x01")
+            .required()
+            .unwrap_err();
+        assert!(err.to_string().contains("rpc_url"));
+        let err = MessageSenderBuilder::new()
+            .rpc_url("ws://localhost:8545")
+            .required()
+            .unwrap_err();
+        assert!(err.to_string().contains("private_key"));
+    }
+}
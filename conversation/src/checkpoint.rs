@@ -0,0 +1,239 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Error;
+use ethers::types::{U256, U64};
+use serde_json::{json, Value};
+
+use crate::MessageRecord;
+
+/// Durable storage for the position of the last message handled by a follow.
+pub trait Checkpoint {
+    /**
+     * Load the stored position.
+     * Returns Ok(Some((block, log_index))) if a position has been stored.
+     */
+    fn load(&self) -> Result<Option<(U64, u64)>, Error>;
+
+    /**
+     * Store the position of the last handled message.
+     * block: the block number of the message
+     * log_index: the log index of the message within the block
+     * Returns Ok(()) if the position was stored.
+     */
+    fn store(&self, block: U64, log_index: u64) -> Result<(), Error>;
+}
+
+/// A checkpoint kept in a small JSON file that is replaced atomically on each store.
+#[derive(Debug, Clone)]
+pub struct FileCheckpoint {
+    path: PathBuf,
+}
+
+impl FileCheckpoint {
+    /**
+     * Create a checkpoint backed by a file.  The file is created on the first store.
+     * path: the checkpoint file
+     * Returns the FileCheckpoint.
+     */
+    pub fn new(path: impl AsRef<Path>) -> FileCheckpoint {
+        FileCheckpoint {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Checkpoint for FileCheckpoint {
+    fn load(&self) -> Result<Option<(U64, u64)>, Error> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let value: Value = serde_json::from_str(&contents)?;
+        match (value["block"].as_u64(), value["log_index"].as_u64()) {
+            (Some(block), Some(log_index)) => Ok(Some((U64::from(block), log_index))),
+            _ => Err(anyhow::anyhow!(
+                "malformed checkpoint {}: {contents}",
+                self.path.display()
+            )),
+        }
+    }
+
+    fn store(&self, block: U64, log_index: u64) -> Result<(), Error> {
+        let value = json!({ "block": block.as_u64(), "log_index": log_index });
+        // write a sibling file and rename it over the checkpoint so a crash never
+        // leaves a partially written file behind
+        let staged = self.path.with_extension("tmp");
+        fs::write(&staged, value.to_string())?;
+        fs::rename(&staged, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Delivers messages after a loaded checkpoint and records each one as it is handled.
+pub(crate) struct Resume<'a> {
+    checkpoint: &'a dyn Checkpoint,
+    from: Option<(U64, u64)>,
+}
+
+impl<'a> Resume<'a> {
+    /**
+     * Load the checkpoint to resume from.
+     * checkpoint: the checkpoint storage
+     * Returns Ok(Resume) if the checkpoint could be loaded.
+     */
+    pub(crate) fn load(checkpoint: &'a dyn Checkpoint) -> Result<Resume<'a>, Error> {
+        let from = checkpoint.load()?;
+        if let Some((block, log_index)) = from {
+            tracing::info!("resuming after block {block} log {log_index}");
+        }
+        Ok(Resume { checkpoint, from })
+    }
+
+    /**
+     * The block to start following from.  The checkpointed block itself is included
+     * because it may hold messages after the checkpointed one.
+     * Returns zero, meaning the current head, when there is no checkpoint.
+     */
+    pub(crate) fn start_block(&self) -> U256 {
+        match self.from {
+            Some((block, _)) => U256::from(block.as_u64()),
+            None => U256::zero(),
+        }
+    }
+
+    /**
+     * Pass a message to the callback unless it was handled before the checkpoint,
+     * then store its position.  The position is stored after the callback returns,
+     * so a message interrupted by a crash is delivered again on restart.
+     * message: the message
+     * callback: the callback function to call with the message
+     * Returns Ok(()) if the message was handled or skipped.
+     */
+    pub(crate) fn deliver(
+        &self,
+        message: &MessageRecord,
+        callback: impl FnOnce(&MessageRecord),
+    ) -> Result<(), Error> {
+        let position = match (message.block_number, message.log_index) {
+            (Some(block), Some(log_index)) => Some((block, log_index.as_u64())),
+            _ => None,
+        };
+        if let (Some(position), Some(from)) = (position, self.from) {
+            if position <= from {
+                tracing::debug!("skipping checkpointed message at {position:?}");
+                return Ok(());
+            }
+        }
+        callback(message);
+        if let Some((block, log_index)) = position {
+            self.checkpoint.store(block, log_index)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("checkpoint_{name}_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn record(block: u64, log_index: u64) -> MessageRecord {
+        MessageRecord {
+            message: format!("{block}:{log_index}"),
+            block_number: Some(U64::from(block)),
+            transaction_hash: None,
+            log_index: Some(U256::from(log_index)),
+            conversation_topic: None,
+            undecoded: None,
+            signed: None,
+            decrypt_error: None,
+            content_type: None,
+        }
+    }
+
+    /// the chain as the follow loop sees it, two messages per block
+    fn chain() -> Vec<MessageRecord> {
+        (10..15)
+            .flat_map(|block| [record(block, 0), record(block, 1)])
+            .collect()
+    }
+
+    #[test]
+    fn test_file_checkpoint_round_trip() {
+        let path = checkpoint_path("round_trip");
+        let checkpoint = FileCheckpoint::new(&path);
+        assert_eq!(checkpoint.load().unwrap(), None);
+        checkpoint.store(U64::from(42), 3).unwrap();
+        checkpoint.store(U64::from(43), 1).unwrap();
+        assert_eq!(
+            FileCheckpoint::new(&path).load().unwrap(),
+            Some((U64::from(43), 1))
+        );
+        assert!(!path.with_extension("tmp").exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_checkpoint_malformed() {
+        let path = checkpoint_path("malformed");
+        fs::write(&path, "{\"block\": 1}").unwrap();
+        assert!(FileCheckpoint::new(&path).load().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resume_without_checkpoint_starts_at_head() {
+        let path = checkpoint_path("fresh");
+        let checkpoint = FileCheckpoint::new(&path);
+        let resume = Resume::load(&checkpoint).unwrap();
+        assert_eq!(resume.start_block(), U256::zero());
+    }
+
+    #[test]
+    fn test_restart_mid_stream_has_no_gaps_or_duplicates() {
+        let path = checkpoint_path("restart");
+        let chain = chain();
+        let handled = RefCell::new(Vec::new());
+        let callback = |message: &MessageRecord| handled.borrow_mut().push(message.clone());
+
+        // the first run is killed after handling the first block and a half
+        {
+            let checkpoint = FileCheckpoint::new(&path);
+            let resume = Resume::load(&checkpoint).unwrap();
+            for message in chain.iter().take(3) {
+                resume.deliver(message, callback).unwrap();
+            }
+        }
+
+        // the restart backfills from the checkpointed block, replaying what it
+        // already handled in that block
+        let checkpoint = FileCheckpoint::new(&path);
+        let resume = Resume::load(&checkpoint).unwrap();
+        assert_eq!(resume.start_block(), U256::from(11));
+        for message in chain.iter().filter(|message| {
+            U256::from(message.block_number.unwrap().as_u64()) >= resume.start_block()
+        }) {
+            resume.deliver(message, callback).unwrap();
+        }
+
+        let handled: Vec<String> = handled
+            .into_inner()
+            .into_iter()
+            .map(|message| message.message)
+            .collect();
+        let expected: Vec<String> = chain.into_iter().map(|message| message.message).collect();
+        assert_eq!(handled, expected);
+        fs::remove_file(&path).unwrap();
+    }
+}
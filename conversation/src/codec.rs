@@ -0,0 +1,111 @@
+use std::marker::PhantomData;
+
+use anyhow::Error;
+use ethers::types::{Address, Bytes};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Converts between a typed value and the payload bytes carried by a message.
+pub trait PayloadCodec<T> {
+    /**
+     * Encode a value as a message payload.
+     * value: the value to encode
+     * Returns Ok(Bytes) the payload.
+     */
+    fn encode(&self, value: &T) -> Result<Bytes, Error>;
+
+    /**
+     * Decode a message payload.
+     * data: the payload
+     * Returns Ok(T) if the payload is a valid encoding.
+     */
+    fn decode(&self, data: &[u8]) -> Result<T, Error>;
+}
+
+/// Payloads as UTF-8 text, as send_message and rewind use them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextCodec;
+
+/// Payloads as JSON documents.
+#[derive(Debug, Clone, Copy)]
+pub struct JsonCodec<T> {
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> JsonCodec<T> {
+    /// A codec for JSON payloads of type T.
+    pub fn new() -> JsonCodec<T> {
+        JsonCodec { value: PhantomData }
+    }
+}
+
+impl<T> Default for JsonCodec<T> {
+    fn default() -> Self {
+        JsonCodec::new()
+    }
+}
+
+impl PayloadCodec<String> for TextCodec {
+    fn encode(&self, value: &String) -> Result<Bytes, Error> {
+        Ok(Bytes::from(value.as_bytes().to_vec()))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<String, Error> {
+        Ok(String::from_utf8(data.to_vec())?)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> PayloadCodec<T> for JsonCodec<T> {
+    fn encode(&self, value: &T) -> Result<Bytes, Error> {
+        Ok(Bytes::from(serde_json::to_vec(value)?))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// A structured message identifying its sender and its place in the sender's stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// the account that sent the message
+    pub sender: Address,
+    /// the sender's sequence number for the message, counting from zero
+    pub sequence: u64,
+    /// when the message was created, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// the message content
+    pub body: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_codec_round_trip() {
+        let payload = TextCodec.encode(&String::from("hello")).unwrap();
+        assert_eq!(payload.to_vec(), b"hello");
+        assert_eq!(TextCodec.decode(&payload).unwrap(), "hello");
+        assert!(TextCodec.decode(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_json_codec_envelope_round_trip() {
+        let envelope = Envelope {
+            sender: Address::repeat_byte(0x11),
+            sequence: 7,
+            timestamp: 1_700_000_000,
+            body: String::from("hello"),
+        };
+        let codec = JsonCodec::<Envelope>::new();
+        let payload = codec.encode(&envelope).unwrap();
+        assert_eq!(codec.decode(&payload).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_json_codec_rejects_invalid_envelope() {
+        let codec = JsonCodec::<Envelope>::new();
+        assert!(codec.decode(b"hello").is_err());
+        assert!(codec.decode(br#"{"sequence": 1}"#).is_err());
+    }
+}
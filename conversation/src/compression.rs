@@ -0,0 +1,109 @@
+use anyhow::Error;
+use ethers::types::Bytes;
+
+use crate::magic::COMPRESSED_MAGIC;
+
+/// Deflate level used when compression is enabled without choosing one.
+#[cfg(feature = "compression")]
+pub(crate) const COMPRESSION_LEVEL: u32 = 6;
+/// Largest payload a compressed payload may expand to.
+#[cfg(feature = "compression")]
+const MAX_DECOMPRESSED_BYTES: u64 = 16 * 1024 * 1024;
+
+/*
+ * Compress a payload with deflate behind the magic prefix, keeping it plain when the
+ * compressed form would be no smaller.
+ * payload: the payload
+ * level: the deflate level, 0 to 9
+ * Returns the payload to send.
+ */
+#[cfg(feature = "compression")]
+pub(crate) fn compress(payload: Bytes, level: u32) -> Result<Bytes, Error> {
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder =
+        DeflateEncoder::new(COMPRESSED_MAGIC.to_vec(), Compression::new(level.min(9)));
+    encoder.write_all(&payload)?;
+    let compressed = encoder.finish()?;
+    if compressed.len() >= payload.len() {
+        tracing::debug!("payload of {} bytes does not compress", payload.len());
+        return Ok(payload);
+    }
+    Ok(Bytes::from(compressed))
+}
+
+/*
+ * Expand a payload carrying the magic prefix, passing any other payload through.
+ * payload: the payload as sent
+ * Returns Ok(Bytes) the original payload.
+ */
+pub(crate) fn decompress(payload: Bytes) -> Result<Bytes, Error> {
+    let compressed = match payload.strip_prefix(&COMPRESSED_MAGIC) {
+        Some(compressed) => compressed,
+        None => return Ok(payload),
+    };
+    inflate(compressed).map(Bytes::from)
+}
+
+/*
+ * Inflate a deflate stream, refusing one that expands beyond MAX_DECOMPRESSED_BYTES.
+ * compressed: the deflate stream after the magic prefix
+ * Returns Ok(Vec<u8>) the expanded payload.
+ */
+#[cfg(feature = "compression")]
+fn inflate(compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    let mut expanded = Vec::new();
+    DeflateDecoder::new(compressed)
+        .take(MAX_DECOMPRESSED_BYTES + 1)
+        .read_to_end(&mut expanded)?;
+    if expanded.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        return Err(anyhow::anyhow!(
+            "compressed payload expands beyond {MAX_DECOMPRESSED_BYTES} bytes"
+        ));
+    }
+    Ok(expanded)
+}
+
+#[cfg(not(feature = "compression"))]
+fn inflate(_compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(anyhow::anyhow!(
+        "payload is compressed but the compression feature is disabled"
+    ))
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_round_trip() {
+        let payload = Bytes::from("lorem ipsum dolor sit amet ".repeat(40).into_bytes());
+        let compressed = compress(payload.clone(), 6).unwrap();
+        assert!(compressed.starts_with(&COMPRESSED_MAGIC));
+        assert!(compressed.len() < payload.len());
+        assert_eq!(decompress(compressed).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_decompress_plain_legacy_payload() {
+        let payload = Bytes::from(b"hello".to_vec());
+        assert_eq!(decompress(payload.clone()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_compress_falls_back_to_plain() {
+        // too short to gain from compression
+        let payload = Bytes::from(b"hi".to_vec());
+        assert_eq!(compress(payload.clone(), 9).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_decompress_corrupt_payload() {
+        let payload = Bytes::from([&COMPRESSED_MAGIC[..], &[0xde, 0xad]].concat());
+        assert!(decompress(payload).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Error;
+use ethers::types::Bytes;
+
+use crate::magic::CONTENT_TYPE_MAGIC;
+
+/// version of the header layout following CONTENT_TYPE_MAGIC
+const CONTENT_HEADER_VERSION: u8 = 1;
+/// magic, version and content type length
+const HEADER_PREFIX_LENGTH: usize = CONTENT_TYPE_MAGIC.len() + 2;
+
+/// The content type of messages sent without one, such as with send_message.
+pub const DEFAULT_CONTENT_TYPE: &str = "text/plain;charset=utf-8";
+
+/*
+ * Prefix a payload with a header naming its content type: the magic, the header
+ * version, the content type length and content type, then the payload length as a
+ * big-endian u32.
+ * content_type: the content type, such as a MIME type, at most 255 bytes
+ * payload: the payload
+ * Returns Ok(Bytes) the typed payload to send.
+ */
+pub(crate) fn seal(content_type: &str, payload: &[u8]) -> Result<Bytes, Error> {
+    let type_length = u8::try_from(content_type.len())
+        .map_err(|_| anyhow::anyhow!("content type longer than 255 bytes: {content_type:?}"))?;
+    let payload_length = u32::try_from(payload.len())
+        .map_err(|_| anyhow::anyhow!("payload of {} bytes is too long", payload.len()))?;
+    Ok(Bytes::from(
+        [
+            &CONTENT_TYPE_MAGIC[..],
+            &[CONTENT_HEADER_VERSION, type_length],
+            content_type.as_bytes(),
+            &payload_length.to_be_bytes(),
+            payload,
+        ]
+        .concat(),
+    ))
+}
+
+/*
+ * Strip a content type header, passing any other payload through.
+ * payload: the payload as sent
+ * Returns Ok((payload, content_type)) with the inner payload and its content type,
+ * if it has one.
+ */
+pub(crate) fn open(payload: Bytes) -> Result<(Bytes, Option<String>), Error> {
+    if !payload.starts_with(&CONTENT_TYPE_MAGIC) {
+        return Ok((payload, None));
+    }
+    let malformed = |reason: &str| anyhow::anyhow!("malformed content type header: {reason}");
+    if payload.len() < HEADER_PREFIX_LENGTH {
+        return Err(malformed("truncated"));
+    }
+    let version = payload[CONTENT_TYPE_MAGIC.len()];
+    if version != CONTENT_HEADER_VERSION {
+        return Err(malformed(&format!("unknown version {version}")));
+    }
+    let type_end = HEADER_PREFIX_LENGTH + payload[HEADER_PREFIX_LENGTH - 1] as usize;
+    let length_end = type_end + 4;
+    let (content_type, length) = match (
+        payload.get(HEADER_PREFIX_LENGTH..type_end),
+        payload.get(type_end..length_end),
+    ) {
+        (Some(content_type), Some(length)) => (content_type, length),
+        _ => return Err(malformed("truncated")),
+    };
+    let content_type = std::str::from_utf8(content_type)
+        .map_err(|_| malformed("content type is not UTF-8"))?
+        .to_string();
+    let length = u32::from_be_bytes(length.try_into()?) as usize;
+    if payload.len() - length_end != length {
+        return Err(malformed(&format!(
+            "payload length {length} does not match the {} bytes sent",
+            payload.len() - length_end
+        )));
+    }
+    Ok((
+        Bytes::from(payload.0.slice(length_end..)),
+        Some(content_type),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open() {
+        let sealed = seal("application/json", br#"{"a":1}"#).unwrap();
+        assert!(sealed.starts_with(&CONTENT_TYPE_MAGIC));
+        let (payload, content_type) = open(sealed).unwrap();
+        assert_eq!(payload, Bytes::from(br#"{"a":1}"#.to_vec()));
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+
+        let (payload, content_type) = open(seal("", &[]).unwrap()).unwrap();
+        assert!(payload.is_empty());
+        assert_eq!(content_type.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_open_untyped_payload() {
+        let (payload, content_type) = open(Bytes::from(b"hello".to_vec())).unwrap();
+        assert_eq!(payload, Bytes::from(b"hello".to_vec()));
+        assert!(content_type.is_none());
+    }
+
+    #[test]
+    fn test_open_malformed_header() {
+        let sealed = seal("text/csv", b"a,b").unwrap();
+        assert!(open(Bytes::from(sealed[..sealed.len() - 1].to_vec())).is_err());
+        assert!(open(Bytes::from([&sealed[..], b"c"].concat())).is_err());
+        assert!(open(Bytes::from(sealed[..6].to_vec())).is_err());
+        assert!(open(Bytes::from(CONTENT_TYPE_MAGIC.to_vec())).is_err());
+        let mut future = sealed.to_vec();
+        future[2] = 2;
+        assert!(open(Bytes::from(future)).is_err());
+    }
+
+    #[test]
+    fn test_seal_rejects_long_content_type() {
+        assert!(seal(&"x".repeat(256), b"").is_err());
+        assert!(seal(&"x".repeat(255), b"").is_ok());
+    }
+}
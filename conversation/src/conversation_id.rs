@@ -0,0 +1,288 @@
+use std::fmt;
+
+use ethers::types::H256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha3::{Digest, Sha3_256};
+
+/// The on chain identifier of a conversation: the SHA3-256 hash of its name.  It is
+/// the indexed topic of the conversation's PayloadSent logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConversationId(pub [u8; 32]);
+
+impl ConversationId {
+    /// The conversation ID as a log topic.
+    pub fn as_topic(&self) -> H256 {
+        H256::from(self.0)
+    }
+
+    /// The raw 32 bytes of the conversation ID.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<&str> for ConversationId {
+    /// Resolve a conversation as resolve does under IdScheme::Sha3Plain: 0x-prefixed
+    /// 32 byte hex is a raw ID, anything else a label that is hashed.
+    fn from(conversation: &str) -> Self {
+        resolve(conversation, IdScheme::Sha3Plain)
+    }
+}
+
+impl From<&String> for ConversationId {
+    fn from(conversation: &String) -> Self {
+        ConversationId::from(conversation.as_str())
+    }
+}
+
+impl From<ConversationId> for [u8; 32] {
+    fn from(conversation_id: ConversationId) -> Self {
+        conversation_id.0
+    }
+}
+
+impl fmt::Display for ConversationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl Serialize for ConversationId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_topic().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ConversationId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(ConversationId(H256::deserialize(deserializer)?.0))
+    }
+}
+
+/// How a conversation label is turned into its on chain ID.  Raw IDs are used as is
+/// under every scheme, so a single call can use another scheme by passing the ID from
+/// IdScheme::derive as 0x-prefixed hex.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdScheme {
+    /// SHA3-256 of the label exactly as given
+    #[default]
+    Sha3Plain,
+    /// SHA3-256 of the label as an XMTP v2 content topic, matching the topics XMTP
+    /// clients derive.  The label is trimmed of whitespace, and unless it is already a
+    /// full topic starting with /xmtp/0/ it is wrapped as /xmtp/0/<label>/proto after
+    /// removing any leading or trailing slashes.
+    XmtpV2,
+}
+
+/// The prefix of every XMTP v2 content topic.
+const XMTP_TOPIC_PREFIX: &str = "/xmtp/0/";
+/// The suffix of an XMTP v2 content topic carrying protobuf envelopes.
+const XMTP_TOPIC_SUFFIX: &str = "/proto";
+
+impl IdScheme {
+    /**
+     * Derive the conversation ID of a label under this scheme.
+     * label: the conversation name
+     * Returns the ConversationId.
+     */
+    pub fn derive(&self, label: &str) -> ConversationId {
+        match self {
+            IdScheme::Sha3Plain => sha3(label),
+            IdScheme::XmtpV2 => sha3(&xmtp_topic(label)),
+        }
+    }
+}
+
+/*
+ * Hash a label into a conversation ID.
+ * label: the label, already normalized by its scheme
+ * Returns the SHA3-256 of the label.
+ */
+fn sha3(label: &str) -> ConversationId {
+    let mut hasher = Sha3_256::default();
+    hasher.update(label.as_bytes());
+    ConversationId(hasher.finalize().into())
+}
+
+/*
+ * Normalize a label to an XMTP v2 content topic.
+ * label: the conversation name or full topic
+ * Returns the content topic.
+ */
+fn xmtp_topic(label: &str) -> String {
+    let label = label.trim();
+    if label.starts_with(XMTP_TOPIC_PREFIX) {
+        return label.to_string();
+    }
+    format!(
+        "{XMTP_TOPIC_PREFIX}{}{XMTP_TOPIC_SUFFIX}",
+        label.trim_matches('/')
+    )
+}
+
+/// A conversation as callers name it: either a label that is hashed into its ID, or
+/// an ID already hashed by other tooling and written as 0x-prefixed 32 byte hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversationRef {
+    /// a raw conversation ID, used as is
+    Raw(H256),
+    /// a conversation name, hashed with SHA3-256
+    Label(String),
+}
+
+impl ConversationRef {
+    /// The conversation ID referred to, deriving a label with IdScheme::Sha3Plain.
+    pub fn id(&self) -> ConversationId {
+        self.id_with(IdScheme::Sha3Plain)
+    }
+
+    /// The conversation ID referred to, deriving a label with the given scheme.
+    pub fn id_with(&self, scheme: IdScheme) -> ConversationId {
+        match self {
+            ConversationRef::Raw(topic) => ConversationId(topic.0),
+            ConversationRef::Label(label) => scheme.derive(label),
+        }
+    }
+}
+
+impl From<&str> for ConversationRef {
+    /// A 66 character 0x-prefixed hex string is a raw ID, anything else a label.
+    fn from(conversation: &str) -> Self {
+        let is_raw = conversation.len() == 66
+            && conversation.starts_with("0x")
+            && conversation[2..]
+                .bytes()
+                .all(|byte| byte.is_ascii_hexdigit());
+        match conversation.parse::<H256>() {
+            Ok(topic) if is_raw => ConversationRef::Raw(topic),
+            _ => ConversationRef::Label(conversation.to_string()),
+        }
+    }
+}
+
+impl From<&String> for ConversationRef {
+    fn from(conversation: &String) -> Self {
+        ConversationRef::from(conversation.as_str())
+    }
+}
+
+/*
+ * Resolve a conversation string to its ID, taking 0x-prefixed 32 byte hex as a raw ID.
+ * conversation: the conversation label or raw ID
+ * scheme: how a label is derived
+ * Returns the ConversationId.
+ */
+pub(crate) fn resolve(conversation: &str, scheme: IdScheme) -> ConversationId {
+    ConversationRef::from(conversation).id_with(scheme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ID: &str = "0x36f028580bb02cc8272a9a020f4200e346e276ae664e45ee80745574e2f5ab80";
+
+    #[test]
+    fn test_display() {
+        assert_eq!(ConversationId::from("test").to_string(), TEST_ID);
+    }
+
+    #[test]
+    fn test_from_takes_raw_ids() {
+        assert_eq!(ConversationId::from(TEST_ID), ConversationId::from("test"));
+        assert_eq!(
+            ConversationId::from(TEST_ID),
+            resolve(TEST_ID, IdScheme::XmtpV2)
+        );
+    }
+
+    #[test]
+    fn test_as_topic() {
+        let conversation_id = ConversationId::from("test");
+        assert_eq!(conversation_id.as_topic(), TEST_ID.parse::<H256>().unwrap());
+        assert_eq!(conversation_id.as_topic().0, *conversation_id.as_bytes());
+    }
+
+    #[test]
+    fn test_ref_label_and_raw_resolve_to_same_topic() {
+        let label = ConversationRef::from("test");
+        assert_eq!(label, ConversationRef::Label(String::from("test")));
+        let raw = ConversationRef::from(TEST_ID);
+        assert!(matches!(raw, ConversationRef::Raw(_)));
+        assert_eq!(label.id().as_topic(), raw.id().as_topic());
+        assert_eq!(
+            resolve("test", IdScheme::Sha3Plain),
+            resolve(TEST_ID, IdScheme::Sha3Plain)
+        );
+        // upper case hex is the same raw ID
+        assert_eq!(
+            resolve(
+                &TEST_ID.to_uppercase().replacen("0X", "0x", 1),
+                IdScheme::Sha3Plain
+            ),
+            resolve(TEST_ID, IdScheme::Sha3Plain)
+        );
+    }
+
+    #[test]
+    fn test_ref_near_miss_is_label() {
+        // too short, missing prefix, or not hex: hashed as today
+        for conversation in [&TEST_ID[..65], &TEST_ID[2..], &TEST_ID.replace('a', "g")] {
+            assert_eq!(
+                ConversationRef::from(conversation),
+                ConversationRef::Label(conversation.to_string())
+            );
+        }
+    }
+
+    // test vectors for checking other clients byte for byte
+    const XMTP_TEST_ID: &str = "0x072871e840fb5e02347e5b46cbc66d023e7b17c9bb60667548c0082c8818782f";
+    const XMTP_MESSAGE_TOPIC_ID: &str =
+        "0xd6e81bb65ad3e33e1a6ffbe826003a6d6458270c3a2d5ef4759082981ab1c03a";
+
+    #[test]
+    fn test_id_scheme_sha3_plain_vector() {
+        assert_eq!(IdScheme::default(), IdScheme::Sha3Plain);
+        assert_eq!(IdScheme::Sha3Plain.derive("test").to_string(), TEST_ID);
+    }
+
+    #[test]
+    fn test_id_scheme_xmtp_v2_vectors() {
+        // a bare label is wrapped as /xmtp/0/test/proto
+        for label in ["test", " test\n", "/test/", "/xmtp/0/test/proto"] {
+            assert_eq!(xmtp_topic(label), "/xmtp/0/test/proto");
+            assert_eq!(IdScheme::XmtpV2.derive(label).to_string(), XMTP_TEST_ID);
+        }
+        assert_eq!(
+            IdScheme::XmtpV2.derive("m-0123abcd").to_string(),
+            XMTP_MESSAGE_TOPIC_ID
+        );
+        assert_eq!(
+            IdScheme::XmtpV2
+                .derive("/xmtp/0/m-0123abcd/proto")
+                .to_string(),
+            XMTP_MESSAGE_TOPIC_ID
+        );
+    }
+
+    #[test]
+    fn test_id_scheme_raw_id_is_scheme_independent() {
+        assert_eq!(
+            resolve(XMTP_TEST_ID, IdScheme::Sha3Plain),
+            resolve("test", IdScheme::XmtpV2)
+        );
+        assert_ne!(
+            resolve("test", IdScheme::Sha3Plain),
+            resolve("test", IdScheme::XmtpV2)
+        );
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let conversation_id = ConversationId::from("test");
+        let json = serde_json::to_string(&conversation_id).unwrap();
+        assert_eq!(json, format!("\"{TEST_ID}\""));
+        let decoded: ConversationId = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, conversation_id);
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashSet, VecDeque};
+
+use ethers::types::{Log, H256, U256};
+
+/// The identity of a log: its transaction hash and log index.
+pub(crate) type LogKey = (H256, U256);
+
+/// A bounded set of recently delivered logs, used to drop logs delivered twice
+/// across reconnects or the backfill/subscribe seam.
+pub(crate) struct RecentLogs {
+    window: usize,
+    seen: HashSet<LogKey>,
+    order: VecDeque<LogKey>,
+}
+
+impl RecentLogs {
+    /**
+     * Create a new RecentLogs.
+     * window: the number of logs to remember, zero disables deduplication
+     */
+    pub(crate) fn new(window: usize) -> RecentLogs {
+        RecentLogs {
+            window,
+            seen: HashSet::with_capacity(window),
+            order: VecDeque::with_capacity(window),
+        }
+    }
+
+    /**
+     * Record a log, evicting the oldest remembered log when the window is full.
+     * log: the log
+     * Returns true if the log has not been seen within the window.
+     */
+    pub(crate) fn insert(&mut self, log: &Log) -> bool {
+        let key = match (log.transaction_hash, log.log_index) {
+            (Some(transaction_hash), Some(log_index)) => (transaction_hash, log_index),
+            _ => return true,
+        };
+        if self.window == 0 {
+            return true;
+        }
+        if self.seen.contains(&key) {
+            return false;
+        }
+        if self.order.len() == self.window {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(key);
+        self.order.push_back(key);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_with(transaction: u8, index: u64) -> Log {
+        Log {
+            transaction_hash: Some(H256::repeat_byte(transaction)),
+            log_index: Some(U256::from(index)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_insert_duplicate() {
+        let mut recent = RecentLogs::new(4);
+        assert!(recent.insert(&log_with(1, 0)));
+        assert!(!recent.insert(&log_with(1, 0)));
+        assert!(recent.insert(&log_with(1, 1)));
+        assert!(recent.insert(&log_with(2, 0)));
+    }
+
+    #[test]
+    fn test_insert_evicts_oldest() {
+        let mut recent = RecentLogs::new(2);
+        assert!(recent.insert(&log_with(1, 0)));
+        assert!(recent.insert(&log_with(2, 0)));
+        assert!(recent.insert(&log_with(3, 0)));
+        assert!(recent.insert(&log_with(1, 0)));
+        assert!(!recent.insert(&log_with(3, 0)));
+    }
+
+    #[test]
+    fn test_insert_disabled() {
+        let mut recent = RecentLogs::new(0);
+        assert!(recent.insert(&log_with(1, 0)));
+        assert!(recent.insert(&log_with(1, 0)));
+    }
+
+    #[test]
+    fn test_insert_pending_log() {
+        let mut recent = RecentLogs::new(4);
+        assert!(recent.insert(&Log::default()));
+        assert!(recent.insert(&Log::default()));
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+
+use anyhow::Error;
+use ethers::types::Bytes;
+
+use crate::{magic::ENCRYPTED_MAGIC, ConversationError};
+
+/// Encrypts payloads before they are sent and decrypts them when they are read.
+pub trait Encryptor: fmt::Debug + Send + Sync {
+    /**
+     * Encrypt a payload.
+     * plaintext: the payload
+     * Returns Ok(Vec<u8>) the ciphertext, carrying whatever the decryption needs.
+     */
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /**
+     * Decrypt a payload.
+     * ciphertext: the output of encrypt
+     * Returns Ok(Vec<u8>) the payload, or an error if it was not encrypted with
+     * this key or has been altered.
+     */
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// AES-256-GCM with a random 96 bit nonce prefixed to each ciphertext.
+#[cfg(feature = "encryption")]
+pub struct AesGcmEncryptor {
+    cipher: aes_gcm::Aes256Gcm,
+}
+
+#[cfg(feature = "encryption")]
+impl AesGcmEncryptor {
+    /// length of the nonce prefixed to each ciphertext
+    const NONCE_LENGTH: usize = 12;
+
+    /**
+     * Create an encryptor from a key.
+     * key: the 32 byte key
+     * Returns the AesGcmEncryptor.
+     */
+    pub fn new(key: &[u8; 32]) -> AesGcmEncryptor {
+        use aes_gcm::KeyInit;
+        AesGcmEncryptor {
+            cipher: aes_gcm::Aes256Gcm::new(key.into()),
+        }
+    }
+
+    /**
+     * Create an encryptor from a hex encoded key, such as one read from the
+     * environment.
+     * key: 64 hex characters, optionally prefixed with 0x
+     * Returns Ok(AesGcmEncryptor) if the key is 32 bytes of hex.
+     */
+    pub fn from_hex(key: &str) -> Result<AesGcmEncryptor, Error> {
+        let key = key.trim();
+        let bytes = hex::decode(key.strip_prefix("0x").unwrap_or(key))
+            .map_err(|err| anyhow::anyhow!("payload key is not hex: {err}"))?;
+        let key: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            anyhow::anyhow!("payload key is {} bytes, expected 32", bytes.len())
+        })?;
+        Ok(AesGcmEncryptor::new(&key))
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl fmt::Debug for AesGcmEncryptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never print the key
+        f.write_str("AesGcmEncryptor")
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Encryptor for AesGcmEncryptor {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        use aes_gcm::{
+            aead::{Aead, AeadCore, OsRng},
+            Aes256Gcm,
+        };
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|err| anyhow::anyhow!("payload encryption failed: {err}"))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        use aes_gcm::{aead::Aead, Nonce};
+        if ciphertext.len() < Self::NONCE_LENGTH {
+            return Err(anyhow::anyhow!(
+                "ciphertext of {} bytes has no nonce",
+                ciphertext.len()
+            ));
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(Self::NONCE_LENGTH);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|err| anyhow::anyhow!("payload decryption failed: {err}"))
+    }
+}
+
+/*
+ * Encrypt a payload behind the magic prefix.
+ * encryptor: the encryptor
+ * payload: the payload
+ * Returns Ok(Bytes) the payload to send.
+ */
+pub(crate) fn seal(encryptor: &dyn Encryptor, payload: &[u8]) -> Result<Bytes, Error> {
+    let ciphertext = encryptor.encrypt(payload)?;
+    Ok(Bytes::from([&ENCRYPTED_MAGIC[..], &ciphertext].concat()))
+}
+
+/*
+ * Decrypt a payload carrying the magic prefix, passing any other payload through.
+ * payload: the payload as sent
+ * encryptor: the encryptor, if one is configured
+ * Returns Ok(Bytes) the plaintext, or ConversationError::DecryptFailed if the payload
+ * is encrypted and cannot be decrypted.
+ */
+pub(crate) fn open(payload: Bytes, encryptor: Option<&dyn Encryptor>) -> Result<Bytes, Error> {
+    let ciphertext = match payload.strip_prefix(&ENCRYPTED_MAGIC) {
+        Some(ciphertext) => ciphertext,
+        None => return Ok(payload),
+    };
+    let encryptor = encryptor.ok_or_else(|| ConversationError::DecryptFailed {
+        reason: String::from("payload is encrypted and no encryptor is configured"),
+    })?;
+    match encryptor.decrypt(ciphertext) {
+        Ok(plaintext) => Ok(Bytes::from(plaintext)),
+        Err(err) => Err(ConversationError::DecryptFailed {
+            reason: err.to_string(),
+        }
+        .into()),
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_aes_gcm_round_trip() {
+        let encryptor = AesGcmEncryptor::from_hex(KEY).unwrap();
+        let sealed = seal(&encryptor, b"hello").unwrap();
+        assert!(sealed.starts_with(&ENCRYPTED_MAGIC));
+        assert!(!sealed.windows(5).any(|window| window == b"hello"));
+        assert_eq!(
+            open(sealed, Some(&encryptor)).unwrap(),
+            Bytes::from(b"hello".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_aes_gcm_nonce_is_random() {
+        let encryptor = AesGcmEncryptor::from_hex(KEY).unwrap();
+        assert_ne!(
+            encryptor.encrypt(b"hello").unwrap(),
+            encryptor.encrypt(b"hello").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_open_fails_with_wrong_key_or_tampering() {
+        let encryptor = AesGcmEncryptor::from_hex(KEY).unwrap();
+        let other = AesGcmEncryptor::new(&[7; 32]);
+        let sealed = seal(&encryptor, b"hello").unwrap();
+        let err = open(sealed.clone(), Some(&other)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConversationError>(),
+            Some(ConversationError::DecryptFailed { .. })
+        ));
+        let mut tampered = sealed.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(Bytes::from(tampered), Some(&encryptor)).is_err());
+        assert!(open(sealed, None).is_err());
+    }
+
+    #[test]
+    fn test_open_plain_payload() {
+        let payload = Bytes::from(b"hello".to_vec());
+        assert_eq!(open(payload.clone(), None).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_from_hex_rejects_bad_keys() {
+        assert!(AesGcmEncryptor::from_hex(&format!("0x{KEY}")).is_ok());
+        assert!(AesGcmEncryptor::from_hex(&KEY[2..]).is_err());
+        assert!(AesGcmEncryptor::from_hex("not hex").is_err());
+    }
+}
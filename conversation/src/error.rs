@@ -0,0 +1,40 @@
+use ethers::types::{Address, H256};
+use thiserror::Error;
+
+/// Errors raised by the conversation crate.
+#[derive(Debug, Error)]
+pub enum ConversationError {
+    /// The private key could not be parsed into a wallet.
+    #[error("invalid private key ({key_length} characters supplied): {reason}")]
+    InvalidPrivateKey { reason: String, key_length: usize },
+    /// The mnemonic phrase or derivation path could not be turned into a wallet.
+    #[error("invalid mnemonic ({word_count} words supplied): {reason}")]
+    InvalidMnemonic { reason: String, word_count: usize },
+    /// The configured public key is not the address of the private key.
+    #[error("public key {configured} does not match the private key's address {derived}")]
+    KeyMismatch { configured: String, derived: String },
+    /// PayloadSent event data could not be decoded as (bytes, uint256).
+    #[error("invalid PayloadSent data ({data_length} bytes supplied): {reason}")]
+    InvalidPayloadData { reason: String, data_length: usize },
+    /// A PayloadSent message was expected to be text but is not valid UTF-8.
+    #[error("PayloadSent message is not valid UTF-8: {reason}")]
+    PayloadNotUtf8 { reason: String },
+    /// An encrypted payload could not be decrypted with the configured encryptor.
+    #[error("PayloadSent message could not be decrypted: {reason}")]
+    DecryptFailed { reason: String },
+    /// A contract call or message transaction reverted.
+    #[error("contract call reverted: {reason}")]
+    Reverted { reason: String },
+    /// A sent transaction was not confirmed in time.  It may still be mined, so
+    /// resubmit with a higher fee at the same nonce to replace it.
+    #[error("transaction {tx_hash:?} was not confirmed in time")]
+    ConfirmationTimeout { tx_hash: H256 },
+    /// No code is deployed at the contract address, usually because the provider is
+    /// on a different network than the contract.
+    #[error("no contract deployed at {address:?} on chain {chain_id}, check the RPC URL points at the right network")]
+    ContractNotDeployed { address: Address, chain_id: u64 },
+    /// The provider would not open a log subscription, for example because it does
+    /// not support eth_subscribe or rejects the filter.
+    #[error("failed to subscribe to logs, follow_messages_poll or poll_on_subscribe_error work without eth_subscribe: {reason}")]
+    Subscription { reason: String },
+}
@@ -0,0 +1,788 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Error;
+use ethers::{
+    abi::{self, AbiDecode, Token},
+    contract::EthEvent,
+    types::{
+        Address, Block, BlockNumber, Bytes, FeeHistory, Filter, FilterBlockOption, Log,
+        Transaction, TransactionReceipt, ValueOrArray, H256, U256, U64,
+    },
+    utils::{keccak256, rlp},
+};
+use futures::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    PayloadSentFilter, SendMessageCall, XPSSenderCalls, EXPECTED_CONTRACT_VERSION, SENDER_CONTRACT,
+};
+
+/// chain ID the fake chain reports
+pub const FAKE_CHAIN_ID: u64 = 31337;
+/// private key of a well known development account, funded on the fake chain
+pub const FAKE_CHAIN_PRIVATE_KEY: &str =
+    "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+/// base fee per gas of every fake block, in wei
+const BASE_FEE: u64 = 1_000_000_000;
+/// gas used by every fake message transaction
+const GAS_USED: u64 = 50_000;
+/// balance every account holds, in wei
+const BALANCE: u128 = 1_000_000_000_000_000_000_000;
+/// selector of the Error(string) revert a require raises
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// A sendMessage call reverting with a require message.
+#[derive(Debug, thiserror::Error)]
+#[error("execution reverted: {0}")]
+struct Revert(String);
+
+/// An in-memory chain serving the JSON-RPC calls a MessageSender makes over a
+/// local WebSocket, for testing sends, rewinds and follows without a node.  It
+/// stands in for the XPS Sender contract at SENDER_CONTRACT: each sendMessage
+/// transaction is mined once its nonce is next, in a block of its own unless
+/// batch_sends is set, emits a PayloadSent log pointing back to the conversation's
+/// previous message block, and is pushed to matching log subscriptions.  A
+/// transaction whose nonce is ahead waits in the mempool behind the gap, as on a
+/// real node.  No block is mined otherwise, so senders should wait for a single
+/// confirmation.  Each send still takes the provider's polling interval to see its
+/// receipt, so tests should set a short MessageSenderConfig::provider_interval.
+pub struct FakeChain {
+    address: SocketAddr,
+    state: Arc<Mutex<ChainState>>,
+    /// whether log queries are held unanswered
+    hold_logs: watch::Sender<bool>,
+    server: JoinHandle<()>,
+}
+
+impl FakeChain {
+    /**
+     * Start a fake chain listening on a free local port.
+     * Returns Ok(FakeChain) once it is accepting connections.
+     */
+    pub async fn start() -> Result<FakeChain, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ChainState::new()));
+        let (hold_logs, held) = watch::channel(false);
+        let server = tokio::spawn(serve(listener, state.clone(), held));
+        tracing::debug!("fake chain listening on {address}");
+        Ok(FakeChain {
+            address,
+            state,
+            hold_logs,
+            server,
+        })
+    }
+
+    /// The WebSocket URL to connect a MessageSender to.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.address)
+    }
+
+    /// The number of the newest block.
+    pub fn block_number(&self) -> u64 {
+        self.state.lock().unwrap().head
+    }
+
+    /// The PayloadSent logs emitted so far, oldest first.
+    pub fn logs(&self) -> Vec<Log> {
+        self.state.lock().unwrap().logs.clone()
+    }
+
+    /// Mine sends into a shared block, as a busy chain does: while batching, the next
+    /// send opens a new block and the sends after it are mined into the same block.
+    pub fn batch_sends(&self, batch: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.batch = batch;
+        state.batch_open = false;
+    }
+
+    /// Keep sends pending in the mempool instead of mining them, as a congested
+    /// chain does.  Releasing mines the pending transactions whose nonces are next.
+    pub fn hold_sends(&self, hold: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.hold = hold;
+        state.mine_ready();
+    }
+
+    /// Make sendMessage revert with a require message, or succeed again with None.
+    /// A reverted send is still mined, with a failed receipt and no log, and calls
+    /// replaying it revert with the message.
+    pub fn revert_sends(&self, reason: Option<&str>) {
+        self.state.lock().unwrap().revert_reason = reason.map(String::from);
+    }
+
+    /// Make sendMessage revert with a require message for one conversation only, as
+    /// revert_sends does for all of them.
+    pub fn revert_conversation(&self, conversation_id: H256, reason: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .reverted_conversations
+            .insert(conversation_id, reason.to_string());
+    }
+
+    /// Evict every pending transaction from the mempool, as a node dropping
+    /// underpriced transactions does.
+    pub fn drop_pending(&self) {
+        self.state.lock().unwrap().pool.clear();
+    }
+
+    /// Refuse the next send at a nonce before it reaches the mempool, as a node
+    /// rejecting a transaction does.
+    pub fn reject_nonce(&self, nonce: u64) {
+        self.state.lock().unwrap().rejected_nonces.push(nonce);
+    }
+
+    /// Leave eth_getLogs queries unanswered until released, as a slow node does, so
+    /// messages can be sent while a reader is fetching history.  A held query blocks
+    /// the rest of its connection; released queries see the logs as of their release.
+    pub fn hold_logs(&self, hold: bool) {
+        self.hold_logs.send_replace(hold);
+    }
+
+    /// Stop pushing logs to the open subscriptions without closing them, as a node
+    /// silently dropping a subscription does.  Later subscriptions are pushed to.
+    pub fn stall_subscriptions(&self) {
+        let mut state = self.state.lock().unwrap();
+        for subscription in state.subscriptions.iter_mut() {
+            subscription.stalled = true;
+        }
+    }
+
+    /// The number of open log subscriptions that are not stalled.
+    pub fn live_subscriptions(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.subscriptions.iter().filter(|s| !s.stalled).count()
+    }
+
+    /// The number of eth_getLogs queries waiting on hold_logs.
+    pub fn held_log_queries(&self) -> usize {
+        self.state.lock().unwrap().held_log_queries
+    }
+
+    /// The transactions sent but not mined, oldest first.
+    pub fn pending(&self) -> Vec<Transaction> {
+        let state = self.state.lock().unwrap();
+        state.pool.iter().map(|(tx, _)| tx.clone()).collect()
+    }
+}
+
+impl Drop for FakeChain {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// A log subscription and the connection to notify.
+struct Subscription {
+    id: U256,
+    filter: Filter,
+    sender: mpsc::UnboundedSender<String>,
+    /// whether logs are no longer pushed to it
+    stalled: bool,
+}
+
+/// The fake chain's blocks, transactions and subscriptions.
+struct ChainState {
+    contract: Address,
+    head: u64,
+    logs: Vec<Log>,
+    transactions: Vec<(Transaction, TransactionReceipt)>,
+    /// the mempool, transactions sent but not mined with the calls they make
+    pool: Vec<(Transaction, SendMessageCall)>,
+    /// whether sends are kept in the mempool
+    hold: bool,
+    /// nonces whose next send is refused
+    rejected_nonces: Vec<u64>,
+    /// the number of log queries waiting to be released
+    held_log_queries: usize,
+    /// the require message sendMessage reverts with, if it reverts
+    revert_reason: Option<String>,
+    /// the require messages sendMessage reverts with for single conversations
+    reverted_conversations: HashMap<H256, String>,
+    subscriptions: Vec<Subscription>,
+    next_subscription: u64,
+    /// whether sends are mined into a shared block
+    batch: bool,
+    /// whether the head block was opened by a batched send and takes more
+    batch_open: bool,
+}
+
+/*
+ * Accept connections until the server task is aborted.
+ */
+async fn serve(listener: TcpListener, state: Arc<Mutex<ChainState>>, held: watch::Receiver<bool>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let state = state.clone();
+        let held = held.clone();
+        tokio::spawn(async move {
+            if let Err(err) = connection(stream, state, held).await {
+                tracing::debug!("fake chain connection closed: {err}");
+            }
+        });
+    }
+}
+
+/*
+ * Answer one connection's requests until it closes.  Responses and subscription
+ * notifications share a queue so they go out in the order they were produced.
+ */
+async fn connection(
+    stream: TcpStream,
+    state: Arc<Mutex<ChainState>>,
+    mut held: watch::Receiver<bool>,
+) -> Result<(), Error> {
+    let socket = tokio_tungstenite::accept_async(stream).await?;
+    let (mut write, mut read) = socket.split();
+    let (sender, mut outgoing) = mpsc::unbounded_channel::<String>();
+    let writer = tokio::spawn(async move {
+        while let Some(text) = outgoing.recv().await {
+            if write.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(message) = read.next().await {
+        match message? {
+            Message::Text(text) => {
+                if text.contains("\"eth_getLogs\"") {
+                    wait_for_logs(&state, &mut held).await;
+                }
+                let response = state.lock().unwrap().handle(&text, &sender);
+                if sender.send(response).is_err() {
+                    break;
+                }
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    writer.abort();
+    Ok(())
+}
+
+/*
+ * Wait while log queries are held, counting the query as held meanwhile.
+ */
+async fn wait_for_logs(state: &Mutex<ChainState>, held: &mut watch::Receiver<bool>) {
+    if !*held.borrow() {
+        return;
+    }
+    state.lock().unwrap().held_log_queries += 1;
+    // the sender lives as long as the chain, which outlives its connections
+    let _ = held.wait_for(|held| !*held).await;
+    state.lock().unwrap().held_log_queries -= 1;
+}
+
+impl ChainState {
+    fn new() -> ChainState {
+        ChainState {
+            contract: SENDER_CONTRACT.parse().unwrap(),
+            head: 0,
+            logs: Vec::new(),
+            transactions: Vec::new(),
+            pool: Vec::new(),
+            hold: false,
+            rejected_nonces: Vec::new(),
+            held_log_queries: 0,
+            revert_reason: None,
+            reverted_conversations: HashMap::new(),
+            subscriptions: Vec::new(),
+            next_subscription: 1,
+            batch: false,
+            batch_open: false,
+        }
+    }
+
+    /*
+     * Answer a JSON-RPC request.
+     * text: the request
+     * sender: the connection's queue, kept by subscriptions
+     * Returns the JSON-RPC response.
+     */
+    fn handle(&mut self, text: &str, sender: &mpsc::UnboundedSender<String>) -> String {
+        let request: Value = serde_json::from_str(text).unwrap_or_default();
+        let method = request["method"].as_str().unwrap_or_default();
+        let params = request["params"].as_array().cloned().unwrap_or_default();
+        let response = match self.call(method, &params, sender) {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+            Err(err) => {
+                tracing::debug!("fake chain {method} failed: {err}");
+                // a revert carries its reason as Error(string) data, as nodes return it
+                let error = match err.downcast_ref::<Revert>() {
+                    Some(Revert(reason)) => json!({
+                        "code": 3,
+                        "message": format!("execution reverted: {reason}"),
+                        "data": Bytes::from(
+                            [
+                                &ERROR_SELECTOR[..],
+                                &abi::encode(&[Token::String(reason.clone())]),
+                            ]
+                            .concat()
+                        ),
+                    }),
+                    None => json!({"code": -32000, "message": err.to_string()}),
+                };
+                json!({"jsonrpc": "2.0", "id": request["id"], "error": error})
+            }
+        };
+        response.to_string()
+    }
+
+    /*
+     * Run a JSON-RPC method.
+     * Returns Ok(Value) the result, or an error for unsupported methods and calls.
+     */
+    fn call(
+        &mut self,
+        method: &str,
+        params: &[Value],
+        sender: &mpsc::UnboundedSender<String>,
+    ) -> Result<Value, Error> {
+        let result = match method {
+            "eth_chainId" | "net_version" => json!(U64::from(FAKE_CHAIN_ID)),
+            "eth_blockNumber" => json!(U64::from(self.head)),
+            "eth_gasPrice" => json!(U256::from(BASE_FEE)),
+            "eth_estimateGas" => json!(U256::from(GAS_USED)),
+            "eth_getBalance" => json!(U256::from(BALANCE)),
+            "eth_getTransactionCount" => {
+                let account: Address = param(params, 0)?;
+                let pending = params.get(1) == Some(&json!("pending"));
+                json!(U256::from(self.transaction_count(account, pending)))
+            }
+            "eth_getCode" => {
+                let address: Address = param(params, 0)?;
+                // any code will do, nothing is executed
+                let code = if address == self.contract {
+                    Bytes::from(vec![0x60, 0x80, 0x60, 0x40, 0x52])
+                } else {
+                    Bytes::new()
+                };
+                json!(code)
+            }
+            "eth_getBlockByNumber" => {
+                let number = self.block_number(&params.first().cloned().unwrap_or_default());
+                json!(self.block(number))
+            }
+            "eth_feeHistory" => json!(FeeHistory {
+                base_fee_per_gas: vec![U256::from(BASE_FEE)],
+                gas_used_ratio: vec![0.5],
+                oldest_block: U256::from(self.head),
+                reward: vec![vec![U256::from(BASE_FEE)]],
+            }),
+            "eth_call" => {
+                let data = params
+                    .first()
+                    .and_then(|tx| tx.get("input").or_else(|| tx.get("data")))
+                    .cloned()
+                    .unwrap_or_default();
+                let data: Bytes = serde_json::from_value(data)?;
+                let at = self.block_number(&params.get(1).cloned().unwrap_or_default());
+                let output = match XPSSenderCalls::decode(&data)? {
+                    XPSSenderCalls::LastMessage(call) => {
+                        abi::encode(&[Token::Uint(self.last_message(call.conversation_id, at))])
+                    }
+                    XPSSenderCalls::Version(_) => {
+                        abi::encode(&[Token::String(EXPECTED_CONTRACT_VERSION.to_string())])
+                    }
+                    XPSSenderCalls::SendMessage(call) => match self.reverts(&call) {
+                        Some(reason) => return Err(Revert(reason).into()),
+                        None => Vec::new(),
+                    },
+                };
+                json!(Bytes::from(output))
+            }
+            "eth_sendRawTransaction" => {
+                let raw: Bytes = param(params, 0)?;
+                json!(self.submit(&raw)?)
+            }
+            "eth_getTransactionByHash" => {
+                let hash: H256 = param(params, 0)?;
+                let pending = self
+                    .pool
+                    .iter()
+                    .map(|(tx, _)| tx)
+                    .find(|tx| tx.hash == hash);
+                json!(self.transaction(hash).map(|(tx, _)| tx).or(pending))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: H256 = param(params, 0)?;
+                json!(self.transaction(hash).map(|(_, receipt)| receipt))
+            }
+            "eth_getLogs" => {
+                let filter: Filter = param(params, 0)?;
+                let logs: Vec<&Log> = self
+                    .logs
+                    .iter()
+                    .filter(|log| self.in_range(&filter, log) && matches(&filter, log))
+                    .collect();
+                json!(logs)
+            }
+            "eth_subscribe" => {
+                let kind: String = param(params, 0)?;
+                if kind != "logs" {
+                    anyhow::bail!("fake chain only supports log subscriptions");
+                }
+                let id = U256::from(self.next_subscription);
+                self.next_subscription += 1;
+                self.subscriptions.push(Subscription {
+                    id,
+                    filter: param(params, 1)?,
+                    sender: sender.clone(),
+                    stalled: false,
+                });
+                json!(id)
+            }
+            "eth_unsubscribe" => {
+                let id: U256 = param(params, 0)?;
+                let before = self.subscriptions.len();
+                self.subscriptions
+                    .retain(|subscription| subscription.id != id);
+                json!(self.subscriptions.len() < before)
+            }
+            _ => anyhow::bail!("method {method} is not supported by the fake chain"),
+        };
+        Ok(result)
+    }
+
+    /*
+     * Accept a signed sendMessage transaction into the mempool, replacing a pending
+     * one at the same nonce if it pays a higher fee, and mine what is ready.
+     * raw: the signed transaction
+     * Returns Ok(H256) the transaction hash.
+     */
+    fn submit(&mut self, raw: &Bytes) -> Result<H256, Error> {
+        let mut tx: Transaction = rlp::decode(raw)?;
+        let from = tx.recover_from_mut()?;
+        if tx.to != Some(self.contract) {
+            anyhow::bail!("execution reverted: no contract at {:?}", tx.to);
+        }
+        let call = match XPSSenderCalls::decode(&tx.input)? {
+            XPSSenderCalls::SendMessage(call) => call,
+            _ => anyhow::bail!("execution reverted: not a sendMessage transaction"),
+        };
+        let nonce = tx.nonce.as_u64();
+        if let Some(rejected) = self.rejected_nonces.iter().position(|n| *n == nonce) {
+            self.rejected_nonces.remove(rejected);
+            anyhow::bail!("transaction at nonce {nonce} rejected");
+        }
+        if nonce < self.transaction_count(from, false) {
+            anyhow::bail!("nonce too low");
+        }
+        if let Some(queued) = self
+            .pool
+            .iter()
+            .position(|(queued, _)| queued.from == from && queued.nonce == tx.nonce)
+        {
+            if fee(&tx) <= fee(&self.pool[queued].0) {
+                anyhow::bail!("replacement transaction underpriced");
+            }
+            self.pool.remove(queued);
+        }
+        let hash = tx.hash;
+        self.pool.push((tx, call));
+        self.mine_ready();
+        Ok(hash)
+    }
+
+    /*
+     * Mine the pending transactions whose nonces are next, unless sends are held.
+     */
+    fn mine_ready(&mut self) {
+        while !self.hold {
+            let ready = self
+                .pool
+                .iter()
+                .position(|(tx, _)| tx.nonce.as_u64() == self.transaction_count(tx.from, false));
+            match ready {
+                Some(ready) => {
+                    let (tx, call) = self.pool.remove(ready);
+                    self.mine(tx, call);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /*
+     * The number of transactions an account has had mined, and with pending, those
+     * in the mempool that follow them without a gap.
+     */
+    fn transaction_count(&self, account: Address, pending: bool) -> u64 {
+        let mut count = self
+            .transactions
+            .iter()
+            .filter(|(tx, _)| tx.from == account)
+            .count() as u64;
+        while pending
+            && self
+                .pool
+                .iter()
+                .any(|(tx, _)| tx.from == account && tx.nonce.as_u64() == count)
+        {
+            count += 1;
+        }
+        count
+    }
+
+    /*
+     * The require message a sendMessage call reverts with, if it reverts.
+     */
+    fn reverts(&self, call: &SendMessageCall) -> Option<String> {
+        self.reverted_conversations
+            .get(&H256::from(call.conversation_id))
+            .or(self.revert_reason.as_ref())
+            .cloned()
+    }
+
+    /*
+     * Mine a sendMessage transaction in a new block, or the open batch block,
+     * emitting its PayloadSent log to matching subscriptions.
+     * tx: the transaction
+     * call: the sendMessage call it makes
+     */
+    fn mine(&mut self, mut tx: Transaction, call: SendMessageCall) {
+        let from = tx.from;
+        let previous = self.last_message(call.conversation_id, self.head);
+        if !self.batch_open {
+            self.head += 1;
+        }
+        self.batch_open = self.batch;
+        let block_number = U64::from(self.head);
+        let block_hash = block_hash(self.head);
+        let index = self
+            .transactions
+            .iter()
+            .filter(|(tx, _)| tx.block_number == Some(block_number))
+            .count();
+        let log_index = self
+            .logs
+            .iter()
+            .filter(|log| log.block_number == Some(block_number))
+            .count();
+        let log = Log {
+            address: self.contract,
+            topics: vec![
+                PayloadSentFilter::signature(),
+                H256::from(call.conversation_id),
+            ],
+            data: Bytes::from(abi::encode(&[
+                Token::Bytes(call.payload.to_vec()),
+                Token::Uint(previous),
+            ])),
+            block_hash: Some(block_hash),
+            block_number: Some(block_number),
+            transaction_hash: Some(tx.hash),
+            transaction_index: Some(U64::from(index)),
+            log_index: Some(U256::from(log_index)),
+            transaction_log_index: Some(U256::zero()),
+            log_type: None,
+            removed: Some(false),
+        };
+        let tip = tx.max_priority_fee_per_gas.unwrap_or_default();
+        let max_fee = tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default();
+        let receipt = TransactionReceipt {
+            transaction_hash: tx.hash,
+            transaction_index: U64::from(index),
+            block_hash: Some(block_hash),
+            block_number: Some(block_number),
+            from,
+            to: tx.to,
+            cumulative_gas_used: U256::from(GAS_USED) * (index + 1),
+            gas_used: Some(U256::from(GAS_USED)),
+            logs: vec![log.clone()],
+            status: Some(U64::one()),
+            effective_gas_price: Some(max_fee.min(U256::from(BASE_FEE) + tip)),
+            transaction_type: tx.transaction_type,
+            ..Default::default()
+        };
+        tx.block_hash = Some(block_hash);
+        tx.block_number = Some(block_number);
+        tx.transaction_index = Some(U64::from(index));
+        if self.reverts(&call).is_some() {
+            // a reverted transaction is mined but emits nothing
+            let receipt = TransactionReceipt {
+                logs: Vec::new(),
+                status: Some(U64::zero()),
+                ..receipt
+            };
+            self.transactions.push((tx, receipt));
+            return;
+        }
+        self.transactions.push((tx, receipt));
+        self.notify(&log);
+        self.logs.push(log);
+    }
+
+    /*
+     * Push a log to the subscriptions it matches, dropping closed connections'.
+     */
+    fn notify(&mut self, log: &Log) {
+        self.subscriptions.retain(|subscription| {
+            if subscription.stalled || !matches(&subscription.filter, log) {
+                return true;
+            }
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "eth_subscription",
+                "params": {"subscription": subscription.id, "result": log},
+            });
+            subscription.sender.send(notification.to_string()).is_ok()
+        });
+    }
+
+    /*
+     * The contract's lastMessage for a conversation: the block of its newest
+     * message at or before a block, or zero if it has none.
+     */
+    fn last_message(&self, conversation_id: [u8; 32], at: u64) -> U256 {
+        let topic = H256::from(conversation_id);
+        self.logs
+            .iter()
+            .filter(|log| log.topics.get(1) == Some(&topic))
+            .filter_map(|log| log.block_number)
+            .filter(|block| block.as_u64() <= at)
+            .max()
+            .map(|block| U256::from(block.as_u64()))
+            .unwrap_or_default()
+    }
+
+    fn transaction(&self, hash: H256) -> Option<&(Transaction, TransactionReceipt)> {
+        self.transactions.iter().find(|(tx, _)| tx.hash == hash)
+    }
+
+    fn block(&self, number: u64) -> Block<H256> {
+        Block {
+            hash: Some(block_hash(number)),
+            parent_hash: block_hash(number.saturating_sub(1)),
+            number: Some(U64::from(number)),
+            timestamp: U256::from(number),
+            gas_limit: U256::from(30_000_000),
+            base_fee_per_gas: Some(U256::from(BASE_FEE)),
+            ..Default::default()
+        }
+    }
+
+    /*
+     * Resolve a block parameter, a tag or a number, to a block number.  Anything
+     * else, including a missing parameter, is the newest block.
+     */
+    fn block_number(&self, param: &Value) -> u64 {
+        match serde_json::from_value::<BlockNumber>(param.clone()) {
+            Ok(BlockNumber::Number(number)) => number.as_u64().min(self.head),
+            Ok(BlockNumber::Earliest) => 0,
+            _ => self.head,
+        }
+    }
+
+    /*
+     * Check a log lies in a filter's block range or block.
+     */
+    fn in_range(&self, filter: &Filter, log: &Log) -> bool {
+        match &filter.block_option {
+            FilterBlockOption::AtBlockHash(hash) => log.block_hash == Some(*hash),
+            FilterBlockOption::Range {
+                from_block,
+                to_block,
+            } => {
+                let block = log.block_number.unwrap_or_default().as_u64();
+                let from = from_block.map(|from| self.resolve(from)).unwrap_or(0);
+                let to = to_block.map(|to| self.resolve(to)).unwrap_or(self.head);
+                from <= block && block <= to
+            }
+        }
+    }
+
+    fn resolve(&self, block: BlockNumber) -> u64 {
+        match block {
+            BlockNumber::Number(number) => number.as_u64(),
+            BlockNumber::Earliest => 0,
+            _ => self.head,
+        }
+    }
+}
+
+/*
+ * Check a log matches a filter's address and topics, ignoring its block range.
+ */
+fn matches(filter: &Filter, log: &Log) -> bool {
+    let address = match &filter.address {
+        Some(ValueOrArray::Value(address)) => *address == log.address,
+        Some(ValueOrArray::Array(addresses)) => {
+            addresses.is_empty() || addresses.contains(&log.address)
+        }
+        None => true,
+    };
+    address
+        && filter.topics.iter().enumerate().all(|(i, topic)| {
+            let wanted: Vec<H256> = match topic {
+                Some(ValueOrArray::Value(Some(topic))) => vec![*topic],
+                Some(ValueOrArray::Array(topics)) => topics.iter().flatten().copied().collect(),
+                _ => Vec::new(),
+            };
+            wanted.is_empty() || log.topics.get(i).is_some_and(|t| wanted.contains(t))
+        })
+}
+
+/*
+ * Read a positional JSON-RPC parameter.
+ */
+fn param<T: DeserializeOwned>(params: &[Value], index: usize) -> Result<T, Error> {
+    let value = params
+        .get(index)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("missing parameter {index}"))?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/*
+ * The fee per gas a transaction offers, to rank replacements.
+ */
+fn fee(tx: &Transaction) -> U256 {
+    tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default()
+}
+
+fn block_hash(number: u64) -> H256 {
+    H256::from(keccak256(number.to_be_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_conversation_topics() {
+        let conversation = H256::repeat_byte(0x11);
+        let log = Log {
+            address: SENDER_CONTRACT.parse().unwrap(),
+            topics: vec![PayloadSentFilter::signature(), conversation],
+            ..Default::default()
+        };
+        let filter = Filter::new()
+            .address(log.address)
+            .topic0(PayloadSentFilter::signature());
+        assert!(matches(&filter.clone().topic1(conversation), &log));
+        assert!(matches(
+            &filter
+                .clone()
+                .topic1(vec![H256::repeat_byte(0x22), conversation]),
+            &log
+        ));
+        assert!(!matches(
+            &filter.clone().topic1(H256::repeat_byte(0x22)),
+            &log
+        ));
+        assert!(!matches(&filter.address(Address::zero()), &log));
+    }
+}
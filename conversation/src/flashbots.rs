@@ -0,0 +1,104 @@
+use anyhow::Error;
+use ethers::{
+    prelude::Signer,
+    types::{Bytes, H256, U64},
+    utils::{hash_message, keccak256},
+};
+use serde_json::{json, Value};
+
+use crate::WalletType;
+
+/// Flashbots relay accepting private transactions on Ethereum mainnet
+pub const FLASHBOTS_RELAY: &str = "https://relay.flashbots.net";
+/// number of blocks the relay tries to include a private transaction in before
+/// dropping it
+pub(crate) const MAX_BLOCKS: u64 = 25;
+
+/*
+ * Send a signed transaction privately with eth_sendPrivateTransaction, so it is
+ * only seen by block builders and cannot be front-run from the public mempool.
+ * relay: the relay URL
+ * signer: the wallet signing the request, which identifies the sender to the relay
+ * raw: the signed transaction
+ * block: the current block number
+ * Returns Ok(H256) the transaction hash once the relay has accepted it.
+ */
+pub(crate) async fn send_private_transaction(
+    relay: &str,
+    signer: &WalletType,
+    raw: &Bytes,
+    block: U64,
+) -> Result<H256, Error> {
+    let body = request_body(raw, block + MAX_BLOCKS).to_string();
+    let response: Value = reqwest::Client::new()
+        .post(relay)
+        .header("Content-Type", "application/json")
+        .header("X-Flashbots-Signature", signature_header(signer, &body)?)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if let Some(error) = response.get("error") {
+        return Err(anyhow::anyhow!(
+            "flashbots relay rejected the transaction: {}",
+            error["message"]
+        ));
+    }
+    Ok(serde_json::from_value(response["result"].clone())?)
+}
+
+/*
+ * Build the eth_sendPrivateTransaction request.
+ * raw: the signed transaction
+ * max_block: the last block the relay may include the transaction in
+ */
+fn request_body(raw: &Bytes, max_block: U64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_sendPrivateTransaction",
+        "params": [{"tx": raw, "maxBlockNumber": max_block}],
+    })
+}
+
+/*
+ * Sign a request body for the X-Flashbots-Signature header: the signer's address
+ * and its personal signature of the hex keccak256 hash of the body.
+ */
+fn signature_header(signer: &WalletType, body: &str) -> Result<String, Error> {
+    let digest = format!("0x{}", hex::encode(keccak256(body.as_bytes())));
+    let signature = signer.sign_hash(hash_message(digest))?;
+    Ok(format!("{:?}:0x{signature}", signer.address()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Signature;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_request_body() {
+        let body = request_body(&Bytes::from(vec![0x02, 0xf8]), U64::from(125));
+        assert_eq!(body["method"], "eth_sendPrivateTransaction");
+        assert_eq!(body["params"][0]["tx"], "0x02f8");
+        assert_eq!(body["params"][0]["maxBlockNumber"], "0x7d");
+    }
+
+    #[test]
+    fn test_signature_header_recovers_to_signer() {
+        let signer = WalletType::from_str(
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+        let body = request_body(&Bytes::from(vec![0x02]), U64::one()).to_string();
+        let header = signature_header(&signer, &body).unwrap();
+        let (address, signature) = header.split_once(':').unwrap();
+        assert_eq!(address, format!("{:?}", signer.address()));
+        let signature = Signature::from_str(signature).unwrap();
+        let digest = format!("0x{}", hex::encode(keccak256(body.as_bytes())));
+        assert_eq!(signature.recover(digest).unwrap(), signer.address());
+    }
+}
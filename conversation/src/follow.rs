@@ -0,0 +1,218 @@
+use std::{cell::Cell, time::Duration};
+
+use anyhow::Error;
+
+/// Limits that end a follow before the subscription does.
+#[derive(Debug, Clone, Default)]
+pub struct FollowOptions {
+    /// stop once this many messages have been delivered
+    pub max_messages: Option<u64>,
+    /// stop once this much time has passed
+    pub max_duration: Option<Duration>,
+    /// what to do with a log whose payload cannot be decoded
+    pub on_decode_error: DecodePolicy,
+}
+
+/// What a follow, rewind or fetch does with a log whose payload cannot be decoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum DecodePolicy {
+    /// return the decode error, ending the follow or abandoning the rewind
+    #[default]
+    Fail,
+    /// log a warning with the transaction hash and continue, counting the log in
+    /// FollowSummary::skipped when following
+    Skip,
+    /// skip the log as Skip does, passing the decode error to the function
+    SkipWith(fn(Error)),
+    /// deliver a MessageRecord with an empty message and the raw log data in
+    /// undecoded; reads that return text or events rather than records skip the log
+    Deliver,
+}
+
+impl DecodePolicy {
+    /**
+     * Fail with a decode error, or let the read go on past the log under any other
+     * policy, passing the error on under SkipWith.
+     * err: the decode error
+     * Returns Err(err) under Fail, otherwise Ok(()).
+     */
+    pub(crate) fn fail_or_continue(self, err: Error) -> Result<(), Error> {
+        match self {
+            DecodePolicy::Fail => Err(err),
+            DecodePolicy::SkipWith(on_error) => {
+                on_error(err);
+                Ok(())
+            }
+            DecodePolicy::Skip | DecodePolicy::Deliver => Ok(()),
+        }
+    }
+}
+
+/// Why a follow returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowStop {
+    /// max_messages messages were delivered
+    MaxMessages,
+    /// max_duration passed
+    MaxDuration,
+    /// the subscription stream ended
+    StreamEnded,
+    /// the receiving end of the channel was dropped
+    ReceiverDropped,
+}
+
+/// A summary of a completed follow.
+#[derive(Debug, Clone)]
+pub struct FollowSummary {
+    /// number of messages delivered to the callback
+    pub messages: u64,
+    /// time spent following
+    pub elapsed: Duration,
+    pub stopped_by: FollowStop,
+    /// number of undecodable logs skipped under DecodePolicy::Skip or SkipWith
+    pub skipped: u64,
+}
+
+/// A follow's decode error policy and the number of logs it has skipped.
+pub(crate) struct DecodeErrors {
+    pub(crate) policy: DecodePolicy,
+    skipped: Cell<u64>,
+}
+
+impl DecodeErrors {
+    pub(crate) fn new(policy: DecodePolicy) -> DecodeErrors {
+        DecodeErrors {
+            policy,
+            skipped: Cell::new(0),
+        }
+    }
+
+    /// Count a skipped log.
+    pub(crate) fn skip(&self) {
+        self.skipped.set(self.skipped.get() + 1);
+    }
+
+    /// The number of logs skipped so far.
+    pub(crate) fn skipped(&self) -> u64 {
+        self.skipped.get()
+    }
+}
+
+impl FollowOptions {
+    /**
+     * Check whether either limit has been reached.  When both are reached at once
+     * the message limit is reported.
+     * messages: the number of messages delivered so far
+     * elapsed: the time spent following so far
+     * Returns Some(FollowStop) if the follow should stop.
+     */
+    pub fn stop(&self, messages: u64, elapsed: Duration) -> Option<FollowStop> {
+        if self.max_messages.is_some_and(|max| messages >= max) {
+            return Some(FollowStop::MaxMessages);
+        }
+        if self.max_duration.is_some_and(|max| elapsed >= max) {
+            return Some(FollowStop::MaxDuration);
+        }
+        None
+    }
+
+    /**
+     * The time left before max_duration is reached.
+     * elapsed: the time spent following so far
+     * Returns None if there is no duration limit.
+     */
+    pub(crate) fn remaining(&self, elapsed: Duration) -> Option<Duration> {
+        self.max_duration
+            .map(|max_duration| max_duration.saturating_sub(elapsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_unlimited() {
+        let options = FollowOptions::default();
+        assert_eq!(options.stop(1_000_000, Duration::from_secs(86_400)), None);
+        assert_eq!(options.remaining(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_stop_zero_max_messages() {
+        let options = FollowOptions {
+            max_messages: Some(0),
+            max_duration: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.stop(0, Duration::ZERO),
+            Some(FollowStop::MaxMessages)
+        );
+    }
+
+    #[test]
+    fn test_stop_max_messages() {
+        let options = FollowOptions {
+            max_messages: Some(3),
+            max_duration: None,
+            ..Default::default()
+        };
+        assert_eq!(options.stop(2, Duration::ZERO), None);
+        assert_eq!(
+            options.stop(3, Duration::ZERO),
+            Some(FollowStop::MaxMessages)
+        );
+    }
+
+    #[test]
+    fn test_stop_max_duration() {
+        let options = FollowOptions {
+            max_messages: None,
+            max_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        assert_eq!(options.stop(5, Duration::from_secs(9)), None);
+        assert_eq!(
+            options.stop(5, Duration::from_secs(10)),
+            Some(FollowStop::MaxDuration)
+        );
+        assert_eq!(
+            options.remaining(Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(
+            options.remaining(Duration::from_secs(12)),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_stop_both_limits() {
+        let options = FollowOptions {
+            max_messages: Some(3),
+            max_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.stop(3, Duration::from_secs(10)),
+            Some(FollowStop::MaxMessages)
+        );
+    }
+
+    #[test]
+    fn test_decode_policy_fail_or_continue() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static REPORTED: AtomicUsize = AtomicUsize::new(0);
+        let err = || anyhow::anyhow!("undecodable");
+        assert!(DecodePolicy::Fail.fail_or_continue(err()).is_err());
+        assert!(DecodePolicy::Skip.fail_or_continue(err()).is_ok());
+        assert!(DecodePolicy::Deliver.fail_or_continue(err()).is_ok());
+        let policy = DecodePolicy::SkipWith(|_| {
+            REPORTED.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(policy.fail_or_continue(err()).is_ok());
+        assert_eq!(REPORTED.load(Ordering::SeqCst), 1);
+    }
+}
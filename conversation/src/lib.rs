@@ -1,28 +1,122 @@
-use std::{str::FromStr, sync::Arc};
+mod builder;
+mod checkpoint;
+mod codec;
+mod compression;
+mod content;
+mod conversation_id;
+mod dedup;
+mod encryption;
+mod error;
+#[cfg(feature = "test-fakechain")]
+pub mod fakechain;
+#[cfg(feature = "flashbots")]
+mod flashbots;
+mod follow;
+mod magic;
+mod nonce;
+mod readonly;
+mod revert;
+mod sampling;
+mod signed;
+mod sink;
+pub mod telemetry;
+mod webhook;
+
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    future::{ready, Future, Ready},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Error;
 use ethers::{
-    contract::abigen,
+    abi::RawLog,
+    contract::{abigen, ContractCall, ContractError, EthEvent},
     core::k256::ecdsa::SigningKey,
-    prelude::{LocalWallet, Provider, SignerMiddleware, Wallet},
-    providers::{Middleware, StreamExt, Ws},
-    types::{Address, Bytes, Filter, H160, H256, U256, U64},
+    prelude::{LocalWallet, Provider, Signer, SignerMiddleware, Wallet},
+    providers::{Middleware, PendingTransaction, StreamExt, Ws},
+    signers::{coins_bip39::English, MnemonicBuilder},
+    types::{
+        transaction::{eip2718::TypedTransaction, eip2930::AccessList},
+        Address, Block, BlockNumber, Bytes, Eip1559TransactionRequest, Filter, Log, Transaction,
+        TransactionReceipt, TransactionRequest, H256, U256, U64,
+    },
 };
 
-use ethabi::Token;
+use ethabi::{ParamType, Token};
+use futures::{stream, Stream};
+use serde::Serialize;
+use tokio::sync::mpsc;
 
 use sha3::{Digest, Sha3_256};
 
-type WalletType = Wallet<SigningKey>;
-type Client = SignerMiddleware<Provider<Ws>, WalletType>;
-type MessageCallback = fn(&String);
+pub use builder::MessageSenderBuilder;
+use checkpoint::Resume;
+pub use checkpoint::{Checkpoint, FileCheckpoint};
+pub use codec::{Envelope, JsonCodec, PayloadCodec, TextCodec};
+pub use content::DEFAULT_CONTENT_TYPE;
+use conversation_id::resolve;
+pub use conversation_id::{ConversationId, ConversationRef, IdScheme};
+use dedup::RecentLogs;
+#[cfg(feature = "encryption")]
+pub use encryption::AesGcmEncryptor;
+pub use encryption::Encryptor;
+pub use error::ConversationError;
+#[cfg(feature = "flashbots")]
+pub use flashbots::FLASHBOTS_RELAY;
+use follow::DecodeErrors;
+pub use follow::{DecodePolicy, FollowOptions, FollowStop, FollowSummary};
+use nonce::NonceManager;
+pub use readonly::MessageSenderReadOnly;
+use revert::contract_error;
+use sampling::LogSampler;
+pub use signed::SignedEnvelope;
+pub use sink::{FileSink, LogSink, MessageSink, StdoutSink};
+pub use webhook::{
+    WebhookConfig, WebhookSink, WebhookStats, WEBHOOK_QUEUE_CAPACITY, WEBHOOK_TIMEOUT,
+};
+
+pub type WalletType = Wallet<SigningKey>;
+// nonces are managed locally so that concurrent sends do not collide
+type Client = NonceManager<SignerMiddleware<Provider<Ws>, WalletType>>;
+type MessageCallback = fn(&MessageRecord);
+type ConversationCallback = fn(&String, &MessageRecord);
 
-/// gas limit for transactions
+/// default gas limit for transactions
 pub const GAS_LIMIT: u64 = 250_000u64;
-/// minimum number of confirmations for transactions
+/// default minimum number of confirmations for transactions
 pub const REQUIRED_CONFIRMATIONS: usize = 1;
-/// XPS MessageSender contract address
+/// default XPS MessageSender contract address
 pub const SENDER_CONTRACT: &str = "0x15aE865d0645816d8EEAB0b7496fdd24227d1801";
+/// XPS MessageSender contract version this crate is built against
+pub const EXPECTED_CONTRACT_VERSION: &str = "1.0.0";
+/// XPS PayloadSent event signature
+pub const PAYLOAD_SENT_EVENT: &str = "PayloadSent(bytes32,bytes,uint256)";
+/// number of recently delivered logs remembered to drop duplicates when following
+pub const DEDUP_WINDOW: usize = 1024;
+/// default priority fee tip added to gas price suggestions, in wei (1.5 gwei)
+pub const PRIORITY_FEE_TIP: u64 = 1_500_000_000;
+/// default number of blocks covered by each rewind log query
+pub const REWIND_WINDOW_BLOCKS: u64 = 500;
+/// default number of rewind log queries in flight at once
+pub const REWIND_CONCURRENCY: usize = 4;
+/// default interval between log queries when following by polling
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// interval between rewinds while waiting for a sent message to be indexed
+pub const INDEX_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// interval between provider health probes suggested for is_connected
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// default widest block range fetched by one eth_getLogs after a provider limit error
+pub const LOG_CHUNK_BLOCKS: u64 = 2_000;
+/// default longest wait for a sent transaction to be mined and confirmed
+pub const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(300);
 
 // Generate rust bindings for the DIDRegistry contract
 abigen!(
@@ -37,10 +131,693 @@ pub struct MessageRewind {
     pub last_change: U256,
 }
 
+impl MessageRewind {
+    /**
+     * The block to follow the conversation from so the rewound messages are not
+     * delivered again: the block after the last change.  A conversation with no
+     * history has a zero last change, and rather than scanning from genesis the
+     * follow then starts at the current head, which a zero start block selects.
+     * Returns the start block for follow_messages.
+     */
+    pub fn follow_start_block(&self) -> U256 {
+        if self.last_change.is_zero() {
+            U256::zero()
+        } else {
+            self.last_change + 1
+        }
+    }
+}
+
+/// A PayloadSent log parsed into its event fields and where it was emitted.  The
+/// message is the payload as emitted, still compressed, encrypted or signed if it
+/// was sent that way; open expands it into PayloadSentData.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PayloadSentEvent {
+    pub conversation_id: [u8; 32],
+    pub message: Bytes,
+    /// the block of the previous message in the conversation, zero for the first
+    pub prev_change: U256,
+    /// the block the log was emitted in, zero for a pending log
+    pub block_number: u64,
+    /// the transaction that emitted the log, zero for a pending log
+    pub tx_hash: H256,
+}
+
+impl PayloadSentEvent {
+    /**
+     * Expand the emitted payload into the message as it was before sending.
+     * encryptor: the encryptor the payload was sent with, if any
+     * Returns Ok(PayloadSentData), or ConversationError::DecryptFailed if the payload
+     * is encrypted and does not decrypt.
+     */
+    pub fn open(&self, encryptor: Option<&dyn Encryptor>) -> Result<PayloadSentData, Error> {
+        open_payload(self.message.clone(), self.prev_change, encryptor)
+    }
+}
+
+/*
+ * Strip the layers a payload was sent in, outermost first: encryption, compression,
+ * the sender signature and the content type header.
+ * payload: the payload as emitted
+ * last_change: the block of the previous message in the conversation
+ * encryptor: the encryptor the payload was sent with, if any
+ * Returns Ok(PayloadSentData) the message as it was before sending.
+ */
+fn open_payload(
+    payload: Bytes,
+    last_change: U256,
+    encryptor: Option<&dyn Encryptor>,
+) -> Result<PayloadSentData, Error> {
+    let payload = compression::decompress(encryption::open(payload, encryptor)?)?;
+    let (message, signed) = signed::open(payload)?;
+    let (message, content_type) = content::open(message)?;
+    // a typed payload is framed by its header, so only an untyped one is escaped
+    let message = match content_type {
+        Some(_) => message,
+        None => magic::unescape(message),
+    };
+    Ok(PayloadSentData {
+        message,
+        last_change,
+        signed,
+        content_type,
+    })
+}
+
+impl TryFrom<&Log> for PayloadSentEvent {
+    type Error = Error;
+
+    /// Decode the log with the bindings generated from the contract ABI, so the event
+    /// signature and data layout cannot drift from the contract.
+    fn try_from(log: &Log) -> Result<Self, Self::Error> {
+        let raw = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+        let event = PayloadSentFilter::decode_log(&raw).map_err(|err| {
+            anyhow::anyhow!(
+                "log with {} topics and {} data bytes is not {}: {err}",
+                log.topics.len(),
+                log.data.len(),
+                PayloadSentFilter::abi_signature()
+            )
+        })?;
+        Ok(PayloadSentEvent {
+            conversation_id: event.conversation_id,
+            message: event.payload,
+            prev_change: event.last_message,
+            block_number: log.block_number.unwrap_or_default().as_u64(),
+            tx_hash: log.transaction_hash.unwrap_or_default(),
+        })
+    }
+}
+
+impl TryFrom<Log> for PayloadSentEvent {
+    type Error = Error;
+
+    fn try_from(log: Log) -> Result<Self, Self::Error> {
+        PayloadSentEvent::try_from(&log)
+    }
+}
+
+/// The decoded data of a PayloadSent event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadSentData {
+    /// the message payload
+    pub message: Bytes,
+    /// the block of the previous message in the conversation, zero for the first
+    pub last_change: U256,
+    /// the sender attribution when the message was sent with send_signed
+    pub signed: Option<SignedEnvelope>,
+    /// the content type when the message was sent with send_content
+    pub content_type: Option<String>,
+}
+
+impl PayloadSentData {
+    /// The payload as text, if it is valid UTF-8.
+    pub fn message_utf8(&self) -> Option<String> {
+        String::from_utf8(self.message.to_vec()).ok()
+    }
+}
+
+/// A struct to hold a message and where on chain the log carrying it was emitted.
+/// Every field comes from the log itself, so building a record costs no extra RPC
+/// calls.
+#[derive(Debug, Clone)]
+pub struct MessageRecord {
+    pub message: String,
+    pub block_number: Option<U64>,
+    pub transaction_hash: Option<H256>,
+    pub log_index: Option<U256>,
+    pub conversation_topic: Option<H256>,
+    /// the raw log data when the payload could not be decoded and the follow's
+    /// DecodePolicy is Deliver; message is empty in that case
+    pub undecoded: Option<Bytes>,
+    /// the sender attribution when the message was sent with send_signed, kept
+    /// whether or not the signature is valid
+    pub signed: Option<SignedEnvelope>,
+    /// why an encrypted payload could not be decrypted, such as a missing or wrong
+    /// key; message is empty and undecoded holds the raw log data in that case
+    pub decrypt_error: Option<String>,
+    /// the content type when the message was sent with send_content
+    pub content_type: Option<String>,
+}
+
+impl MessageRecord {
+    /// Whether the message was sent with send_signed.
+    pub fn is_signed(&self) -> bool {
+        self.signed.is_some()
+    }
+
+    /// Whether the payload was encrypted and could not be decrypted.
+    pub fn is_undecryptable(&self) -> bool {
+        self.decrypt_error.is_some()
+    }
+
+    /// The content type the message was sent with, DEFAULT_CONTENT_TYPE for a
+    /// message sent without one.
+    pub fn content_type(&self) -> &str {
+        self.content_type.as_deref().unwrap_or(DEFAULT_CONTENT_TYPE)
+    }
+
+    /// The sender of a signed message, if its signature over this conversation and
+    /// message recovers to the address it claims.  None for an unsigned message or
+    /// an invalid signature; is_signed tells the two apart.
+    pub fn verify_sender(&self) -> Option<Address> {
+        let signed = self.signed.as_ref()?;
+        signed.verify(self.conversation_topic?, self.message.as_bytes())
+    }
+}
+
+/// A message returned by rewind_records with its place in the rewound history.
+#[derive(Debug, Clone)]
+pub struct RewoundMessage {
+    /// position among the rewound messages, counting from zero at the oldest for
+    /// rewind_records and from zero at the newest for history_stream
+    pub sequence: u64,
+    /// the message and where it was emitted
+    pub record: MessageRecord,
+}
+
+/// The size of a conversation's history, as returned by conversation_history_size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConversationStats {
+    /// the number of messages sent to the conversation
+    pub message_count: u64,
+    /// the payload bytes of those messages as emitted on chain, after any
+    /// compression and encryption
+    pub total_bytes: u64,
+}
+
+/// A struct to hold the outcome of a mined message transaction.
+#[derive(Debug, Clone)]
+pub struct MessageReceipt {
+    pub transaction_hash: H256,
+    pub block_number: Option<U64>,
+    /// the gas the transaction used, None if the node did not report it
+    pub gas_used: Option<U256>,
+    /// the price paid per gas in wei, None if the node did not report it
+    pub effective_gas_price: Option<U256>,
+}
+
+impl MessageReceipt {
+    /// The total fee paid for the transaction in wei, if the receipt reports it.
+    pub fn cost(&self) -> Option<U256> {
+        Some(self.gas_used? * self.effective_gas_price?)
+    }
+}
+
+impl From<TransactionReceipt> for MessageReceipt {
+    fn from(receipt: TransactionReceipt) -> Self {
+        MessageReceipt {
+            transaction_hash: receipt.transaction_hash,
+            block_number: receipt.block_number,
+            gas_used: receipt.gas_used,
+            effective_gas_price: receipt.effective_gas_price,
+        }
+    }
+}
+
+/// What MessageSender::check_setup found about the chain, contract and wallet.
+#[derive(Debug, Clone)]
+pub struct SetupCheck {
+    pub chain_id: U256,
+    pub contract_address: Address,
+    /// size of the code deployed at the contract address, zero if there is none
+    pub contract_code_bytes: usize,
+    /// the contract's version, None if it could not be read
+    pub contract_version: Option<String>,
+    /// the account messages are sent from
+    pub account: Address,
+    /// the account's balance in wei
+    pub balance: U256,
+}
+
+impl SetupCheck {
+    /// Whether a contract is deployed at the configured address.
+    pub fn contract_deployed(&self) -> bool {
+        self.contract_code_bytes > 0
+    }
+}
+
+/// Suggested EIP-1559 fee parameters, in wei per gas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasPriceSuggestion {
+    /// the estimated base fee of the next block
+    pub base_fee: U256,
+    pub max_priority_fee: U256,
+    /// base_fee plus max_priority_fee
+    pub max_fee: U256,
+}
+
+impl GasPriceSuggestion {
+    /**
+     * Suggest fees from a block's base fee.  The base fee can rise by at most 12.5%
+     * from one block to the next, so the suggestion allows for that rise.
+     * block: the pending block
+     * tip: the priority fee per gas
+     * Returns Ok(GasPriceSuggestion) if the block reports a base fee.
+     */
+    pub fn from_block<T>(block: &Block<T>, tip: U256) -> Result<GasPriceSuggestion, Error> {
+        let pending_base_fee = block.base_fee_per_gas.ok_or_else(|| {
+            anyhow::anyhow!("block has no base fee, the chain does not support EIP-1559")
+        })?;
+        let base_fee = pending_base_fee * 1125 / 1000;
+        Ok(GasPriceSuggestion {
+            base_fee,
+            max_priority_fee: tip,
+            max_fee: base_fee + tip,
+        })
+    }
+}
+
+/// The order rewind returns messages in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageRewindOrder {
+    /// chain order, the oldest message first
+    #[default]
+    OldestFirst,
+    /// the newest message first, the order the back pointers are walked in
+    NewestFirst,
+}
+
+/// Options for rewind.
+#[derive(Debug, Clone)]
+pub struct RewindConfig {
+    /// what rewind, history_stream, fetch_messages and get_events_in_range do with a
+    /// log that cannot be decoded; a skipped rewound log is passed over to the
+    /// message before it
+    pub on_decode_error: DecodePolicy,
+    /// number of blocks covered by each log query once rewind walks past the newest
+    /// message's block
+    pub window_blocks: u64,
+    /// number of log queries in flight at once
+    pub concurrency: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        RewindConfig {
+            on_decode_error: DecodePolicy::Fail,
+            window_blocks: REWIND_WINDOW_BLOCKS,
+            concurrency: REWIND_CONCURRENCY,
+        }
+    }
+}
+
+/// How MessageSender::new_with_retry retries the provider connection, and how a
+/// WebhookSink retries a delivery.  The delay starts at initial_delay and doubles
+/// after each failed attempt, up to max_delay.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// total number of connection attempts, including the first
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay before retrying after the given failed attempt, counting from one.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// When and how a send replaces its transaction with a higher fee one at the same
+/// nonce, so one underpriced transaction does not stall the sender.
+#[derive(Debug, Clone)]
+pub struct SpeedUpPolicy {
+    /// how long to wait for the transaction to be mined before replacing it
+    pub after: Duration,
+    /// percentage each replacement raises the fees by.  Nodes reject replacements
+    /// raising them by less than 10%.
+    pub bump_percent: u32,
+    /// most replacements sent for one message
+    pub max_replacements: u32,
+}
+
+impl Default for SpeedUpPolicy {
+    fn default() -> Self {
+        SpeedUpPolicy {
+            after: Duration::from_secs(60),
+            bump_percent: 12,
+            max_replacements: 3,
+        }
+    }
+}
+
+/// Options for a MessageSender.
+#[derive(Debug, Clone)]
+pub struct MessageSenderConfig {
+    /// address of the XPS MessageSender contract
+    pub contract_address: Address,
+    /// signature of the event messages are emitted as, for deployments whose
+    /// contract does not emit PAYLOAD_SENT_EVENT.  The conversation ID must be its
+    /// only indexed parameter.  None is PAYLOAD_SENT_EVENT.
+    pub event_signature: Option<&'static str>,
+    /// the types of the event's unindexed parameters, to decode its data with when
+    /// it is not laid out as PayloadSent's.  payload_param and last_message_param
+    /// pick the payload and the previous message block out of them.
+    pub param_types: Option<Vec<ParamType>>,
+    /// index in param_types of the bytes parameter holding the payload
+    pub payload_param: usize,
+    /// index in param_types of the uint parameter holding the previous message
+    /// block, or None if the event has no back pointer and rewinds stop at each
+    /// message
+    pub last_message_param: Option<usize>,
+    /// whether connecting fails with ConversationError::ContractNotDeployed when no
+    /// code is deployed at contract_address, which usually means the provider is on
+    /// the wrong network
+    pub verify_contract: bool,
+    /// gas limit for message transactions
+    pub gas_limit: u64,
+    /// number of recently delivered logs remembered to drop duplicates when following
+    pub dedup_window: usize,
+    /// number of confirmations send_message waits for.  More confirmations make a
+    /// reorg dropping the message less likely at the cost of send latency; zero and
+    /// one both return as soon as the transaction is mined.
+    pub required_confirmations: usize,
+    /// how long a send waits for its transaction to be confirmed before giving up
+    /// with ConversationError::ConfirmationTimeout, or None to wait indefinitely.
+    /// The transaction stays in the mempool and may still be mined.
+    pub confirmation_timeout: Option<Duration>,
+    /// replace a send's transaction with a higher fee one when it is not mined in
+    /// time, or None to wait on the original.  Ordered sends are not replaced.
+    pub speed_up: Option<SpeedUpPolicy>,
+    /// send messages privately through this Flashbots relay, such as
+    /// FLASHBOTS_RELAY, instead of the public mempool so they cannot be front-run,
+    /// or None to send them publicly.  Relays only accept Ethereum mainnet
+    /// transactions.  Private sends are not sped up, and ordered sends stay public.
+    #[cfg(feature = "flashbots")]
+    pub flashbots_relay: Option<String>,
+    /// interval between log queries when following by polling
+    pub poll_interval: Duration,
+    /// whether a follow whose eth_subscribe fails falls back to polling every
+    /// poll_interval, for HTTP-only and subscription-limited providers, instead of
+    /// returning ConversationError::Subscription
+    pub poll_on_subscribe_error: bool,
+    /// options for rewind
+    pub rewind: RewindConfig,
+    /// the order rewind returns messages in
+    pub rewind_order: MessageRewindOrder,
+    /// when following, how long to wait without a log before checking the chain for
+    /// missed messages and resubscribing if the subscription has gone stale
+    pub idle_timeout: Option<Duration>,
+    /// interval between background probes of the provider backing is_connected,
+    /// such as HEALTH_CHECK_INTERVAL, or None to run no probe.  Each probe is an RPC
+    /// call, so it is off unless asked for
+    pub health_check_interval: Option<Duration>,
+    /// interval at which the provider polls for transaction receipts, or None for
+    /// the ethers default of several seconds
+    pub provider_interval: Option<Duration>,
+    /// priority fee per gas recommended by get_gas_price_suggestion, in wei
+    pub priority_fee_tip: U256,
+    /// how conversation labels are derived into conversation IDs
+    pub id_scheme: IdScheme,
+    /// deflate level, 0 to 9, to compress sent payloads with, or None to send them
+    /// plain.  Compressed payloads are expanded when read whatever this is set to.
+    #[cfg(feature = "compression")]
+    pub compression: Option<u32>,
+    /// size in bytes a payload must exceed to be compressed, so short messages are
+    /// not spent compressing for no gain.  Setting it enables compression at the
+    /// compression level, or the default level if that is None.  Payloads over the
+    /// threshold use the same deflate format and [0xff, 0x7a] prefix as
+    /// with_compression rather than a one byte marker on every payload, since a
+    /// marker byte on plain payloads would make existing plain messages unreadable.
+    #[cfg(feature = "compression")]
+    pub compression_threshold_bytes: Option<usize>,
+    /// widest block range fetched by one log query once the provider has rejected a
+    /// range as too large; the range is fetched as consecutive chunks of this size
+    pub log_chunk_blocks: u64,
+    /// encrypts sent payloads and decrypts encrypted payloads when they are read, or
+    /// None to send payloads in the clear
+    pub encryptor: Option<Arc<dyn Encryptor>>,
+    /// trace one message in every this many in the follow and rewind loops; one
+    /// traces every message, larger values keep trace logging from slowing busy
+    /// conversations
+    pub trace_sample_every: u64,
+    /// how often follows and rewinds log the number of messages received and the
+    /// last block at info, or None to not report progress
+    pub progress_interval: Option<Duration>,
+}
+
+impl Default for MessageSenderConfig {
+    fn default() -> Self {
+        MessageSenderConfig {
+            contract_address: SENDER_CONTRACT.parse::<Address>().unwrap(),
+            event_signature: Some(PAYLOAD_SENT_EVENT),
+            param_types: None,
+            payload_param: 0,
+            last_message_param: Some(1),
+            verify_contract: true,
+            gas_limit: GAS_LIMIT,
+            dedup_window: DEDUP_WINDOW,
+            required_confirmations: REQUIRED_CONFIRMATIONS,
+            confirmation_timeout: Some(CONFIRMATION_TIMEOUT),
+            speed_up: None,
+            #[cfg(feature = "flashbots")]
+            flashbots_relay: None,
+            poll_interval: POLL_INTERVAL,
+            poll_on_subscribe_error: false,
+            rewind: RewindConfig::default(),
+            rewind_order: MessageRewindOrder::OldestFirst,
+            idle_timeout: None,
+            health_check_interval: None,
+            provider_interval: None,
+            priority_fee_tip: U256::from(PRIORITY_FEE_TIP),
+            id_scheme: IdScheme::Sha3Plain,
+            #[cfg(feature = "compression")]
+            compression: None,
+            #[cfg(feature = "compression")]
+            compression_threshold_bytes: None,
+            log_chunk_blocks: LOG_CHUNK_BLOCKS,
+            encryptor: None,
+            trace_sample_every: 1,
+            progress_interval: None,
+        }
+    }
+}
+
+impl MessageSenderConfig {
+    /**
+     * Encrypt sent payloads and decrypt encrypted payloads when they are read.
+     * Payloads are encrypted after any signing and compression, and a short magic
+     * prefix marks encrypted payloads so conversations mixing both still decode.
+     * encryptor: the encryptor, such as an AesGcmEncryptor
+     * Returns the config with encryption enabled.
+     */
+    pub fn with_encryptor(mut self, encryptor: Arc<dyn Encryptor>) -> MessageSenderConfig {
+        self.encryptor = Some(encryptor);
+        self
+    }
+
+    /*
+     * Encode a payload as it goes on chain under this config: compressed if it
+     * should be, then encrypted, so the deflate pass sees the compressible plaintext
+     * rather than ciphertext.
+     * payload: the encoded message
+     * Returns Ok(Bytes) the payload to send.
+     */
+    fn seal_payload(&self, payload: Bytes) -> Result<Bytes, Error> {
+        #[cfg(feature = "compression")]
+        let payload = match self.compression_level(payload.len()) {
+            Some(level) => compression::compress(payload, level)?,
+            None => payload,
+        };
+        self.encrypt(payload)
+    }
+
+    /*
+     * Encrypt a payload if an encryptor is configured.
+     * payload: the encoded message, compressed if it is sent compressed
+     * Returns Ok(Bytes) the payload to send.
+     */
+    fn encrypt(&self, payload: Bytes) -> Result<Bytes, Error> {
+        match self.encryptor.as_deref() {
+            Some(encryptor) => encryption::seal(encryptor, &payload),
+            None => Ok(payload),
+        }
+    }
+
+    /**
+     * The topic of the event messages are emitted as, filtered on when rewinding and
+     * following.
+     * Returns the hash of event_signature.
+     */
+    pub fn event_topic(&self) -> H256 {
+        H256::from(ethers::utils::keccak256(
+            self.event_signature.unwrap_or(PAYLOAD_SENT_EVENT),
+        ))
+    }
+
+    /*
+     * Check payload_param and last_message_param name a bytes and a uint parameter
+     * of param_types, so logs are not read through the wrong parameters.
+     * Returns Ok(()) if they do, or if param_types is not set.
+     */
+    fn check_event_params(&self) -> Result<(), Error> {
+        let Some(param_types) = &self.param_types else {
+            return Ok(());
+        };
+        if !matches!(param_types.get(self.payload_param), Some(ParamType::Bytes)) {
+            anyhow::bail!(
+                "payload_param {} is not a bytes parameter of {param_types:?}",
+                self.payload_param
+            );
+        }
+        if let Some(index) = self.last_message_param {
+            if !matches!(param_types.get(index), Some(ParamType::Uint(_))) {
+                anyhow::bail!(
+                    "last_message_param {index} is not a uint parameter of {param_types:?}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * Rewrite a log of the configured event into the PayloadSent layout, so it is
+     * decoded as any PayloadSent log.  Other logs, and logs whose data does not decode
+     * as param_types, are returned unchanged and fail to decode as PayloadSent.
+     * log: the log
+     * Returns the log as a PayloadSent log.
+     */
+    pub(crate) fn normalize_log(&self, mut log: Log) -> Log {
+        let payload_sent = PayloadSentFilter::signature();
+        let event_topic = self.event_topic();
+        if log.topics.first() != Some(&event_topic)
+            || (event_topic == payload_sent && self.param_types.is_none())
+        {
+            return log;
+        }
+        if let Some(param_types) = &self.param_types {
+            let tokens = match ethabi::decode(param_types, &log.data) {
+                Ok(tokens) => tokens,
+                Err(err) => {
+                    tracing::debug!("log data is not {param_types:?}: {err}");
+                    return log;
+                }
+            };
+            let payload = match tokens.get(self.payload_param) {
+                Some(Token::Bytes(payload)) => payload.clone(),
+                _ => {
+                    tracing::debug!("log data has no payload in {param_types:?}");
+                    return log;
+                }
+            };
+            let last_message = match self.last_message_param.map(|index| tokens.get(index)) {
+                Some(Some(Token::Uint(last_message))) => *last_message,
+                None => U256::zero(),
+                Some(_) => {
+                    tracing::debug!("log data has no previous message block in {param_types:?}");
+                    return log;
+                }
+            };
+            log.data = Bytes::from(ethabi::encode(&[
+                Token::Bytes(payload),
+                Token::Uint(last_message),
+            ]));
+        }
+        log.topics[0] = payload_sent;
+        log
+    }
+}
+
+#[cfg(feature = "compression")]
+impl MessageSenderConfig {
+    /**
+     * Compress sent payloads with deflate.  A payload that does not shrink is sent
+     * plain, and a short magic prefix marks compressed payloads so conversations
+     * mixing both still decode.
+     * level: the deflate level, 0 (fastest) to 9 (smallest)
+     * Returns the config with compression enabled.
+     */
+    pub fn with_compression(mut self, level: u32) -> MessageSenderConfig {
+        self.compression = Some(level);
+        self
+    }
+
+    /**
+     * Compress only sent payloads larger than a threshold, enabling compression at
+     * the default level if no level is set.
+     * bytes: the payload size to exceed
+     * Returns the config with the threshold set.
+     */
+    pub fn with_compression_threshold(mut self, bytes: usize) -> MessageSenderConfig {
+        self.compression_threshold_bytes = Some(bytes);
+        self
+    }
+
+    /*
+     * The deflate level to compress a payload with under this config.
+     * payload_length: the size of the payload in bytes
+     * Returns Some(level) if the payload should be compressed.
+     */
+    fn compression_level(&self, payload_length: usize) -> Option<u32> {
+        match (self.compression, self.compression_threshold_bytes) {
+            (level, Some(threshold)) if payload_length > threshold => {
+                Some(level.unwrap_or(compression::COMPRESSION_LEVEL))
+            }
+            (_, Some(_)) => None,
+            (level, None) => level,
+        }
+    }
+}
+
 /// A struct to send messages to the XPS Sender contract.
+///
+/// Each method runs inside a tracing span named after the method and carrying the
+/// conversation, so events can be grouped by conversation and operation.
+#[derive(Clone)]
 pub struct MessageSender {
-    contract: XPSSender<Client>,
+    contract: Arc<XPSSender<Client>>,
     client: Arc<Client>,
+    config: MessageSenderConfig,
+    connected: Arc<AtomicBool>,
+}
+
+impl fmt::Debug for MessageSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the signer is left out so key material cannot reach the logs
+        f.debug_struct("MessageSender")
+            .field("address", &self.address())
+            .field("contract_address", &self.config.contract_address)
+            .field("chain_id", &self.chain_id())
+            .finish_non_exhaustive()
+    }
 }
 
 impl MessageSender {
@@ -50,221 +827,4914 @@ impl MessageSender {
      * wallet_signer: the private key for the wallet
      */
     pub async fn new(rpc_url: String, wallet_signer: String) -> Result<MessageSender, Error> {
-        let sender_address = SENDER_CONTRACT;
+        MessageSender::builder()
+            .rpc_url(rpc_url)
+            .private_key(wallet_signer)
+            .build()
+            .await
+    }
+
+    /**
+     * Start building a MessageSender, for setting options one at a time.
+     * Returns a MessageSenderBuilder with every option at its default.
+     */
+    pub fn builder() -> MessageSenderBuilder {
+        MessageSenderBuilder::new()
+    }
+
+    /**
+     * Create a new MessageSender with the given options.
+     * rpc_url: the RPC URL for the chain
+     * wallet_signer: the private key for the wallet
+     * config: the options for the sender
+     */
+    pub async fn new_with_config(
+        rpc_url: String,
+        wallet_signer: String,
+        config: MessageSenderConfig,
+    ) -> Result<MessageSender, Error> {
+        MessageSender::builder()
+            .rpc_url(rpc_url)
+            .private_key(wallet_signer)
+            .config(config)
+            .build()
+            .await
+    }
+
+    /**
+     * Create a new MessageSender, retrying the provider connection with exponential
+     * backoff.  This rides out a node that is not ready yet at startup.
+     * rpc_url: the RPC URL for the chain
+     * wallet_signer: the private key for the wallet
+     * retry: how many times to attempt the connection and how long to wait between
+     */
+    pub async fn new_with_retry(
+        rpc_url: String,
+        wallet_signer: String,
+        retry: RetryPolicy,
+    ) -> Result<MessageSender, Error> {
+        MessageSender::builder()
+            .rpc_url(rpc_url)
+            .private_key(wallet_signer)
+            .retry(retry)
+            .build()
+            .await
+    }
 
-        let provider = Provider::<Ws>::connect(rpc_url).await?;
-        let chain_id = provider.get_chainid().await?;
-        tracing::info!("Connected to chain: {chain_id}");
+    /*
+     * Create a new MessageSender on a connected provider.
+     * provider: the provider
+     * wallet_signer: the private key for the wallet
+     * config: the options for the sender
+     */
+    async fn with_provider(
+        provider: Provider<Ws>,
+        wallet_signer: String,
+        config: MessageSenderConfig,
+    ) -> Result<MessageSender, Error> {
+        config.check_event_params()?;
+        let sender_address = config.contract_address;
+        let provider = match config.provider_interval {
+            Some(interval) => provider.interval(interval),
+            None => provider,
+        };
 
         // wallet/signer info
-        let wallet_result = wallet_from_key(&wallet_signer);
+        let wallet_result = parse_wallet(&wallet_signer);
         if let Ok(wallet) = wallet_result {
-            tracing::info!("Wallet: {:?}", wallet);
+            // only the address: the wallet's Debug output is not guaranteed to omit
+            // key material
+            let address = wallet.address();
+            tracing::info!("Wallet address: {address:?}");
             let middleware = SignerMiddleware::new_with_provider_chain(provider, wallet)
                 .await
                 .unwrap();
-            let client = Arc::new(middleware);
-            tracing::info!("Contract Connected: {sender_address}");
-            let sender_address = H160::from_str(sender_address).unwrap();
-            let contract = XPSSender::new(sender_address, client.clone());
+            let client = Arc::new(NonceManager::new(middleware, address));
+            tracing::info!("Contract Connected: {sender_address:?}");
+            let contract = Arc::new(XPSSender::new(sender_address, client.clone()));
+            let connected = Arc::new(AtomicBool::new(true));
+            if let Some(interval) = config.health_check_interval {
+                monitor_connection(Arc::downgrade(&client), connected.clone(), interval);
+            }
 
-            Ok(Self { contract, client })
+            let sender = Self {
+                contract,
+                client,
+                config,
+                connected,
+            };
+            if sender.config.verify_contract {
+                sender.check_contract_deployed().await?;
+            }
+            sender.check_contract_version().await;
+            Ok(sender)
         } else {
             let err = wallet_result.unwrap_err();
             tracing::error!("Wallet error: {:?}", err);
-            Err(err)
+            Err(err.into())
         }
     }
 
     /**
-     * Send a message to the XPS Sender contract.
+     * Send a message to the XPS Sender contract, waiting for the configured number
+     * of confirmations.
      * conversation: the conversation ID
      * message: the message to send
-     * Returns Ok(()) if the transaction was successful.
+     * Returns Ok(MessageReceipt) if the transaction was successful.
      */
-    pub async fn send_message(&self, conversation: &String, message: &String) -> Result<(), Error> {
-        let conversation_id_result = to_conversation_id(conversation);
-        if let Err(err) = conversation_id_result {
-            tracing::error!("Conversation ID error: {:?}", err);
-            return Err(anyhow::anyhow!("failed to get conversation ID"));
-        }
-        let conversation_id = conversation_id_result.unwrap();
-        let message_bytes = Bytes::from(message.as_bytes().to_vec());
-        let tx = self.contract.send_message(conversation_id, message_bytes);
-        let receipt = tx
-            .gas(GAS_LIMIT)
-            .send()
-            .await
-            .unwrap()
-            .confirmations(REQUIRED_CONFIRMATIONS)
-            .await;
-        if let Err(err) = receipt {
-            tracing::error!("Transaction error: {:?}", err);
-            return Err(anyhow::anyhow!("failed to send message"));
-        }
-        tracing::info!("Transaction receipt: {:?}", receipt);
-        Ok(())
+    pub async fn send_message(
+        &self,
+        conversation: &String,
+        message: &String,
+    ) -> Result<MessageReceipt, Error> {
+        self.send_message_with_confirmations(
+            conversation,
+            message,
+            self.config.required_confirmations,
+        )
+        .await
     }
 
     /**
-     * Rewind the conversation to the last n messages.
-     * Returns Ok(MessageRewind) a struct containing messages and the last change block.
+     * Send a message to the XPS Sender contract, waiting for the given number of
+     * confirmations instead of the configured one.
+     * conversation: the conversation ID
+     * message: the message to send
+     * confirmations: the number of confirmations to wait for
+     * Returns Ok(MessageReceipt) if the transaction was successful.
      */
-    pub async fn rewind(&self, conversation: &String, n: u32) -> Result<MessageRewind, Error> {
-        let mut n = n;
-        let conversation_id = to_conversation_id(conversation).unwrap();
-        let last_change_result: Result<U256, _> =
-            self.contract.last_message(conversation_id).call().await;
-        tracing::info!("conversation_id: {}", hex::encode(conversation_id));
-        if let Err(err) = last_change_result {
-            tracing::error!("last change error: {:?}", err);
-            return Err(anyhow::anyhow!("failed to get last change"));
-        }
-        let mut rewind = MessageRewind {
-            message: Vec::new(),
-            last_change: U256::zero(),
-        };
-        let mut last_change = last_change_result.unwrap();
-        rewind.last_change = last_change;
-        while last_change != U256::zero() {
-            tracing::debug!("prev_change: {}", last_change);
-            let conversation_topic = [H256::from(conversation_id)];
-            let contract_addr = SENDER_CONTRACT.parse::<Address>().unwrap();
-            let filter = Filter::new()
-                .from_block(U64::from(last_change.as_u64()))
-                .to_block(U64::from(last_change.as_u64()))
-                .event("PayloadSent(bytes32,bytes,uint256)")
-                .address(vec![contract_addr])
-                .topic1(conversation_topic.to_vec());
-            let logs = self.client.get_logs(&filter).await;
-            if let Ok(logs) = logs {
-                for log in logs.iter() {
-                    if tracing::level_enabled!(tracing::Level::TRACE) {
-                        tracing::trace!("log: {:?}", log);
-                    }
-                    let param_result = abi_decode_payload_sent(log.data.to_vec());
-                    if let Ok(param) = param_result {
-                        tracing::debug!("param: {:?}", param);
-                        let message = param[0].clone().into_string().unwrap();
-                        if tracing::level_enabled!(tracing::Level::TRACE) {
-                            tracing::trace!("message: {message}");
-                        }
-                        rewind.message.push(message);
-                        last_change = param[1].clone().into_uint().unwrap();
-                    } else {
-                        let err = param_result.unwrap_err();
-                        tracing::error!("param error: {:?}", err);
-                        return Err(err);
-                    }
-
-                    n -= 1;
-                    if n == 0 {
-                        last_change = U256::zero();
-                        break;
-                    }
-                }
-            }
-        }
-
-        rewind.message.reverse();
-        tracing::info!("{} messages found", rewind.message.len());
-        Ok(rewind)
+    #[tracing::instrument(
+        name = "send_message",
+        skip_all,
+        fields(
+            conversation = %conversation,
+            conversation_id = %resolve(conversation, self.config.id_scheme),
+            message_len = message.len(),
+            confirmations = confirmations,
+            tx_hash = tracing::field::Empty,
+            block = tracing::field::Empty,
+        )
+    )]
+    pub async fn send_message_with_confirmations(
+        &self,
+        conversation: &String,
+        message: &String,
+        confirmations: usize,
+    ) -> Result<MessageReceipt, Error> {
+        let call = self.message_call(conversation, message)?;
+        self.submit(conversation, call, confirmations).await
     }
 
     /**
-     * Follow the conversation and call the callback function for each new message.
+     * Send a message with an EIP-2930 access list declaring the storage it touches,
+     * which costs less gas on chains that support access lists.
      * conversation: the conversation ID
-     * start_block: the block to start following from
-     * callback: the callback function to call for each new message
-     * Returns Ok(()) if the transaction was successful.
+     * message: the message to send
+     * access_list: the access list, such as one from build_access_list_for_message
+     * Returns Ok(MessageReceipt) if the transaction was successful.
      */
-    pub async fn follow_messages(
+    #[tracing::instrument(
+        name = "send_message",
+        skip_all,
+        fields(
+            conversation = %conversation,
+            conversation_id = %resolve(conversation, self.config.id_scheme),
+            message_len = message.len(),
+            access_list_entries = access_list.0.len(),
+            tx_hash = tracing::field::Empty,
+            block = tracing::field::Empty,
+        )
+    )]
+    pub async fn send_message_with_access_list(
         &self,
-        conversation: &String,
-        start_block: &U256,
-        callback: MessageCallback,
-    ) -> Result<(), Error> {
-        let conversation_id = to_conversation_id(conversation).unwrap();
-        tracing::info!("conversation_id: {}", hex::encode(conversation_id));
-        let conversation_topic = [H256::from(conversation_id)];
-        let contract_addr = SENDER_CONTRACT.parse::<Address>().unwrap();
-        let filter = Filter::new()
-            .from_block(U64::from(start_block.as_u64()))
-            .event("PayloadSent(bytes32,bytes,uint256)")
-            .address(vec![contract_addr])
-            .topic1(conversation_topic.to_vec());
-
-        let mut stream = self.client.subscribe_logs(&filter).await.unwrap();
-        while let Some(log) = stream.next().await {
-            if tracing::level_enabled!(tracing::Level::TRACE) {
-                tracing::trace!("log: {:?}", log);
-            }
-            let param_result = abi_decode_payload_sent(log.data.to_vec());
-            if let Ok(param) = param_result {
-                tracing::debug!("param: {:?}", param);
-                let message = param[0].clone().into_string().unwrap();
-                tracing::trace!("message: {message}");
-                callback(&message);
-            } else {
-                let err = param_result.unwrap_err();
-                tracing::error!("param error: {:?}", err);
-                return Err(err);
-            }
-        }
-        Ok(())
+        conversation: &str,
+        message: &str,
+        access_list: AccessList,
+    ) -> Result<MessageReceipt, Error> {
+        let mut call = self.message_call(conversation, &message.to_string())?;
+        call.tx.set_access_list(access_list);
+        self.submit(conversation, call, self.config.required_confirmations)
+            .await
     }
-}
 
-/*
- * Create a wallet from a private key.
- * wallet_key: the private key
- * Returns Ok(WalletType) if the wallet was created successfully.
- */
-fn wallet_from_key(wallet_key: &str) -> Result<WalletType, Error> {
-    let wallet = wallet_key.parse::<LocalWallet>()?;
-    Ok(wallet)
-}
+    /**
+     * Ask the node for the access list of a message send with eth_createAccessList.
+     * The storage a send touches does not depend on the message, so the list can be
+     * built once per conversation and reused.
+     * conversation: the conversation ID
+     * Returns Ok(AccessList) the accounts and storage slots a send to the
+     * conversation reads and writes.
+     */
+    pub async fn build_access_list_for_message(
+        &self,
+        conversation: &str,
+    ) -> Result<AccessList, Error> {
+        let call = self.contract_call(conversation, Bytes::new());
+        let created = self.client.create_access_list(&call.tx, None).await?;
+        tracing::debug!(
+            "Access list with {} entries, estimated gas {}",
+            created.access_list.0.len(),
+            created.gas_used
+        );
+        Ok(created.access_list)
+    }
 
-/*
- * Create a conversation ID from a conversation string.
- * conversation: the conversation string
- * Returns Ok([u8; 32]) if the conversation ID was created successfully.
- */
-fn to_conversation_id(conversation: &String) -> Result<[u8; 32], Error> {
-    let mut hasher = Sha3_256::default();
-    hasher.update(conversation.as_bytes());
-    let result = hasher.finalize();
-    let conversation_id = H256::from_slice(&result);
-    let conversation_id = *conversation_id.as_fixed_bytes();
-    if conversation_id.len() > 32 {
-        return Err(anyhow::anyhow!("Conversation ID too long"));
+    /**
+     * Send a message privately through a Flashbots relay so it cannot be front-run,
+     * as send_message does when flashbots_relay is configured, using FLASHBOTS_RELAY
+     * if it is not.  Relays only accept Ethereum mainnet transactions; on any other
+     * chain this fails without sending.
+     * conversation: the conversation ID
+     * message: the message to send
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    #[cfg(feature = "flashbots")]
+    pub async fn send_message_via_flashbots(
+        &self,
+        conversation: &str,
+        message: &str,
+    ) -> Result<MessageReceipt, Error> {
+        let mut sender = self.clone();
+        sender
+            .config
+            .flashbots_relay
+            .get_or_insert_with(|| FLASHBOTS_RELAY.to_string());
+        sender
+            .send_message(&conversation.to_string(), &message.to_string())
+            .await
     }
-    Ok(conversation_id)
-}
+
+    /**
+     * Send a message compressed whatever its size, at the configured compression
+     * level or the default one.  Rewind and follow expand it transparently.
+     * conversation: the conversation ID
+     * message: the message to send
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    #[cfg(feature = "compression")]
+    #[tracing::instrument(name = "send_message_compressed", skip_all, fields(conversation = %conversation, tx_hash = tracing::field::Empty, block = tracing::field::Empty))]
+    pub async fn send_message_compressed(
+        &self,
+        conversation: &String,
+        message: &String,
+    ) -> Result<MessageReceipt, Error> {
+        let level = self
+            .config
+            .compression
+            .unwrap_or(compression::COMPRESSION_LEVEL);
+        let payload = compression::compress(Bytes::from(message.as_bytes().to_vec()), level)?;
+        let call = self.contract_call(conversation, self.config.encrypt(payload)?);
+        self.submit(conversation, call, self.config.required_confirmations)
+            .await
+    }
+
+    /**
+     * Send the same message to several conversations, one transaction each, all in
+     * flight at once.  A failed send does not stop the others.
+     * conversations: the conversation IDs, each at most once
+     * message: the message to send
+     * Returns Ok(HashMap) of each conversation's send result, or an error if a
+     * conversation is listed twice.
+     */
+    #[tracing::instrument(name = "broadcast", skip_all, fields(conversations = conversations.len()))]
+    pub async fn broadcast(
+        &self,
+        conversations: &[&str],
+        message: &str,
+    ) -> Result<HashMap<String, Result<MessageReceipt, Error>>, Error> {
+        let mut seen = HashSet::with_capacity(conversations.len());
+        if let Some(duplicate) = conversations
+            .iter()
+            .find(|conversation| !seen.insert(**conversation))
+        {
+            return Err(anyhow::anyhow!("conversation {duplicate} is listed twice"));
+        }
+        let message = message.to_string();
+        let sends = conversations.iter().map(|conversation| {
+            let conversation = conversation.to_string();
+            let message = &message;
+            async move {
+                let result = self.send_message(&conversation, message).await;
+                if let Err(err) = &result {
+                    tracing::warn!("broadcast to {conversation} failed: {err}");
+                }
+                (conversation, result)
+            }
+        });
+        Ok(futures::future::join_all(sends).await.into_iter().collect())
+    }
+
+    /**
+     * Re-send the last n messages of one conversation into another, for migrating a
+     * conversation to a new ID.  Messages are sent oldest first, each waiting for
+     * the previous to be confirmed so the target keeps the source's order.  Empty
+     * messages are skipped.  The replay stops at the first failed send, so the
+     * target holds a prefix of the source without gaps and a retry can resume after
+     * the messages that were sent.
+     * source: the conversation ID to rewind
+     * target: the conversation ID to send to
+     * n: the number of messages to rewind from the source
+     * Returns Ok(Vec) of the send results in order, ending with the failure if a
+     * send failed, or an error if the source could not be rewound.
+     */
+    #[tracing::instrument(name = "replay", skip_all, fields(source = %source, target = %target, n = n))]
+    pub async fn replay(
+        &self,
+        source: &String,
+        target: &String,
+        n: u32,
+    ) -> Result<Vec<Result<MessageReceipt, Error>>, Error> {
+        let rewind = self
+            .rewind_ordered(source, n, MessageRewindOrder::OldestFirst)
+            .await?;
+        let messages = replay_messages(rewind.message);
+        tracing::info!("replaying {} messages", messages.len());
+        let mut results = Vec::with_capacity(messages.len());
+        for message in messages.iter() {
+            let result = self.send_message(target, message).await;
+            let failed = result.is_err();
+            if let Err(err) = &result {
+                tracing::warn!(
+                    "replay stopped at message {} of {}: {err}",
+                    results.len(),
+                    messages.len()
+                );
+            }
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /**
+     * Send a message wrapped in a signed envelope carrying the sender's address and
+     * an ECDSA signature, by the sending wallet, over the conversation ID, message
+     * and a nonce.  Readers recover the sender with MessageRecord::verify_sender, so
+     * attribution survives the message being relayed off chain.
+     * conversation: the conversation ID
+     * message: the message to send
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    #[tracing::instrument(name = "send_message", skip_all, fields(conversation = %conversation, tx_hash = tracing::field::Empty, block = tracing::field::Empty))]
+    pub async fn send_signed(
+        &self,
+        conversation: &str,
+        message: &str,
+    ) -> Result<MessageReceipt, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        // the send time keeps nonces unique for a sender
+        let nonce = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        let payload = signed::seal(
+            self.client.inner().signer(),
+            conversation_id.as_topic(),
+            message.as_bytes(),
+            nonce,
+        )?;
+        let call = self.payload_call(conversation, payload)?;
+        self.submit(conversation, call, self.config.required_confirmations)
+            .await
+    }
+
+    /**
+     * Send a typed value as a message, encoded by a payload codec, waiting for the
+     * configured number of confirmations.  The payload need not be text; one that
+     * starts with 0xff is escaped so it is not read back as compressed, encrypted or
+     * signed.
+     * conversation: the conversation ID
+     * value: the value to send
+     * codec: the codec encoding the value
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    #[tracing::instrument(name = "send_message", skip_all, fields(conversation = %conversation, tx_hash = tracing::field::Empty, block = tracing::field::Empty))]
+    pub async fn send_typed<T: Serialize>(
+        &self,
+        conversation: &str,
+        value: &T,
+        codec: &impl PayloadCodec<T>,
+    ) -> Result<MessageReceipt, Error> {
+        let payload = magic::escape(codec.encode(value)?);
+        let call = self.payload_call(conversation, payload)?;
+        self.submit(conversation, call, self.config.required_confirmations)
+            .await
+    }
+
+    /**
+     * Send a payload tagged with its content type, so readers can tell how to
+     * interpret it without agreeing out of band.  Messages sent without a type, as
+     * by send_message, read back as DEFAULT_CONTENT_TYPE.
+     * conversation: the conversation ID
+     * content_type: the content type, such as a MIME type, at most 255 bytes
+     * payload: the payload
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    #[tracing::instrument(
+        name = "send_message",
+        skip_all,
+        fields(
+            conversation = %conversation,
+            content_type = %content_type,
+            message_len = payload.len(),
+            tx_hash = tracing::field::Empty,
+            block = tracing::field::Empty,
+        )
+    )]
+    pub async fn send_content(
+        &self,
+        conversation: &str,
+        content_type: &str,
+        payload: &[u8],
+    ) -> Result<MessageReceipt, Error> {
+        let call = self.payload_call(conversation, content::seal(content_type, payload)?)?;
+        self.submit(conversation, call, self.config.required_confirmations)
+            .await
+    }
+
+    /**
+     * Send a message as in send_message, then wait until rewind returns it.  Some
+     * RPC nodes lag behind the chain when indexing logs, so a confirmed message may
+     * not be visible to readers straight away.
+     * conversation: the conversation ID
+     * message: the message to send
+     * max_wait: how long to wait for the message to be indexed after confirmation
+     * Returns Ok(MessageReceipt) once the message is visible to rewind.
+     */
+    #[tracing::instrument(name = "send_message", skip_all, fields(conversation = %conversation, tx_hash = tracing::field::Empty, block = tracing::field::Empty))]
+    pub async fn send_message_and_wait_for_index(
+        &self,
+        conversation: &str,
+        message: &str,
+        max_wait: Duration,
+    ) -> Result<MessageReceipt, Error> {
+        let conversation = conversation.to_string();
+        let receipt = self
+            .send_message(&conversation, &message.to_string())
+            .await?;
+        let deadline = Instant::now() + max_wait;
+        loop {
+            match self.rewind(&conversation, 1).await {
+                Ok(rewind) if is_indexed(&rewind, message, receipt.block_number) => {
+                    return Ok(receipt);
+                }
+                Ok(_) => tracing::debug!("message not indexed yet"),
+                Err(err) => tracing::debug!("rewind failed while waiting for index: {err}"),
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(anyhow::anyhow!(
+                    "message in transaction {:?} was not indexed within {max_wait:?}",
+                    receipt.transaction_hash
+                ));
+            }
+            tokio::time::sleep(INDEX_POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+
+    /**
+     * Send a message to the XPS Sender contract as an EIP-1559 transaction with
+     * explicit fee caps, for predictable inclusion in the fee market.
+     * conversation: the conversation ID
+     * message: the message to send
+     * max_fee_per_gas: the maximum total fee per gas, in wei
+     * max_priority_fee: the maximum priority fee (tip) per gas, in wei
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    #[tracing::instrument(name = "send_message_eip1559", skip_all, fields(conversation = %conversation, tx_hash = tracing::field::Empty, block = tracing::field::Empty))]
+    pub async fn send_message_eip1559(
+        &self,
+        conversation: &str,
+        message: &str,
+        max_fee_per_gas: U256,
+        max_priority_fee: U256,
+    ) -> Result<MessageReceipt, Error> {
+        if max_priority_fee > max_fee_per_gas {
+            return Err(anyhow::anyhow!(
+                "max priority fee {max_priority_fee} exceeds max fee per gas {max_fee_per_gas}"
+            ));
+        }
+        let mut call = self.message_call(conversation, &message.to_string())?;
+        let mut tx = Eip1559TransactionRequest::new()
+            .max_fee_per_gas(max_fee_per_gas)
+            .max_priority_fee_per_gas(max_priority_fee);
+        tx.to = call.tx.to().cloned();
+        tx.data = call.tx.data().cloned();
+        tx.gas = call.tx.gas().cloned();
+        call.tx = TypedTransaction::Eip1559(tx);
+        self.submit(conversation, call, self.config.required_confirmations)
+            .await
+    }
+
+    /**
+     * Suggest fee parameters for send_message_eip1559 from the pending block's base
+     * fee and the configured priority fee tip.
+     * Returns Ok(GasPriceSuggestion) if the pending block reports a base fee.
+     */
+    #[tracing::instrument(name = "get_gas_price_suggestion", skip_all)]
+    pub async fn get_gas_price_suggestion(&self) -> Result<GasPriceSuggestion, Error> {
+        let block = self
+            .client
+            .get_block(BlockNumber::Pending)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("provider returned no pending block"))?;
+        GasPriceSuggestion::from_block(&block, self.config.priority_fee_tip)
+    }
+
+    /**
+     * Check the setup without sending anything: read the chain ID, the code and
+     * version of the contract at the configured address, and the sending account's
+     * balance.
+     * Returns Ok(SetupCheck) if the provider answered every query.
+     */
+    #[tracing::instrument(name = "check_setup", skip_all)]
+    pub async fn check_setup(&self) -> Result<SetupCheck, Error> {
+        let chain_id = self.client.get_chainid().await?;
+        let contract_address = self.config.contract_address;
+        let code = self.client.get_code(contract_address, None).await?;
+        let contract_version = if code.is_empty() {
+            None
+        } else {
+            self.get_contract_version().await.ok()
+        };
+        let account = self.address();
+        let balance = self.client.get_balance(account, None).await?;
+        Ok(SetupCheck {
+            chain_id,
+            contract_address,
+            contract_code_bytes: code.len(),
+            contract_version,
+            account,
+            balance,
+        })
+    }
+
+    /**
+     * Watch the gas price, polling it every interval and emitting it each time it
+     * changes, starting with the current price.
+     * interval: the time between polls
+     * Returns Ok(Stream) of the gas prices in wei once the current price has been
+     * read; the stream ends after the first failed poll.
+     */
+    #[tracing::instrument(name = "watch_gas_price", skip_all)]
+    pub async fn watch_gas_price(
+        &self,
+        interval: Duration,
+    ) -> Result<impl Stream<Item = Result<U256, Error>> + '_, Error> {
+        let current = self.client.get_gas_price().await?;
+        let state: (Option<U256>, U256) = (Some(current), current);
+        Ok(stream::try_unfold(
+            state,
+            move |(unsent, last)| async move {
+                if let Some(price) = unsent {
+                    return Ok(Some((price, (None, price))));
+                }
+                loop {
+                    tokio::time::sleep(interval).await;
+                    let price = self.client.get_gas_price().await?;
+                    if price != last {
+                        tracing::debug!("gas price changed from {last} to {price}");
+                        return Ok(Some((price, (None, price))));
+                    }
+                }
+            },
+        ))
+    }
+
+    /**
+     * Wait until the gas price drops below a threshold, polling it every configured
+     * poll interval.
+     * threshold: the gas price in wei to wait for the price to fall below
+     * timeout: the longest wait
+     * Returns Ok(U256) the first gas price below the threshold, or an error if the
+     * timeout elapsed first.
+     */
+    #[tracing::instrument(name = "wait_for_gas_below", skip_all, fields(threshold = %threshold))]
+    pub async fn wait_for_gas_below(
+        &self,
+        threshold: U256,
+        timeout: Duration,
+    ) -> Result<U256, Error> {
+        let wait = async {
+            let prices = self.watch_gas_price(self.config.poll_interval).await?;
+            futures::pin_mut!(prices);
+            while let Some(price) = prices.next().await {
+                let price = price?;
+                if price < threshold {
+                    return Ok(price);
+                }
+            }
+            Err(anyhow::anyhow!("gas price watch ended"))
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(price) => price,
+            Err(_) => Err(anyhow::anyhow!(
+                "gas price did not drop below {threshold} within {timeout:?}"
+            )),
+        }
+    }
+
+    /**
+     * Send several messages to a conversation with consecutive nonces, so they are
+     * mined in order.  The account nonce is fetched once and each transaction is
+     * submitted in turn; confirmations are then awaited concurrently.
+     * conversation: the conversation ID
+     * messages: the messages to send, in order
+     * Returns Ok(Vec<MessageReceipt>) in message order if every transaction succeeded,
+     * or an error if any was dropped or reverted.
+     */
+    #[tracing::instrument(name = "send_messages_ordered", skip_all, fields(conversation = %conversation, messages = messages.len()))]
+    pub async fn send_messages_ordered(
+        &self,
+        conversation: &str,
+        messages: &[String],
+    ) -> Result<Vec<MessageReceipt>, Error> {
+        let conversation = conversation.to_string();
+        let start = self.client.initialize_nonce().await?;
+        tracing::debug!("sending {} messages from nonce {start}", messages.len());
+        let mut tx_hashes = Vec::with_capacity(messages.len());
+        for message in messages {
+            // the nonce manager hands out consecutive nonces from the one just fetched
+            let call = self
+                .message_call(&conversation, message)?
+                .nonce(self.client.next());
+            let sent = call.send().await.map(|pending| pending.tx_hash());
+            match sent {
+                Ok(tx_hash) => tx_hashes.push(tx_hash),
+                Err(err) => {
+                    tracing::error!("Transaction error: {:?}", err);
+                    // later sends must not leave a gap at the failed nonce
+                    self.client.resync().await?;
+                    return Err(contract_error(err).context(format!(
+                        "failed to send message {} of {}",
+                        tx_hashes.len() + 1,
+                        messages.len()
+                    )));
+                }
+            }
+        }
+        let confirmations = self.config.required_confirmations;
+        let receipts = futures::future::join_all(tx_hashes.iter().map(|tx_hash| {
+            within_confirmation_timeout(
+                self.config.confirmation_timeout,
+                *tx_hash,
+                PendingTransaction::new(*tx_hash, self.client.provider())
+                    .confirmations(confirmations),
+            )
+        }))
+        .await;
+        let mut results = Vec::with_capacity(receipts.len());
+        for (tx_hash, receipt) in tx_hashes.iter().zip(receipts) {
+            let receipt = ordered_receipt(*tx_hash, receipt??)?;
+            self.log_gas(&receipt);
+            results.push(receipt);
+        }
+        Ok(results)
+    }
+
+    /*
+     * Log the gas accounting of a mined message transaction, warning if it used
+     * most of the gas limit.
+     * receipt: the receipt
+     */
+    fn log_gas(&self, receipt: &MessageReceipt) {
+        tracing::info!(
+            tx_hash = ?receipt.transaction_hash,
+            gas_used = ?receipt.gas_used,
+            effective_gas_price = ?receipt.effective_gas_price,
+            cost = ?receipt.cost(),
+            "transaction mined"
+        );
+        if near_gas_limit(receipt.gas_used, self.config.gas_limit) {
+            tracing::warn!(
+                "Transaction {:?} used {:?} of the {} gas limit",
+                receipt.transaction_hash,
+                receipt.gas_used,
+                self.config.gas_limit
+            );
+        }
+    }
+
+    /*
+     * Build the contract call sending a message.
+     * conversation: the conversation ID
+     * message: the message to send
+     * Returns Ok(ContractCall) the call with the gas limit set.
+     */
+    fn message_call(
+        &self,
+        conversation: &str,
+        message: &String,
+    ) -> Result<ContractCall<Client, ()>, Error> {
+        self.payload_call(conversation, Bytes::from(message.as_bytes().to_vec()))
+    }
+
+    /*
+     * Build the contract call sending a payload.
+     * conversation: the conversation ID
+     * payload: the encoded message
+     * Returns Ok(ContractCall) the call with the gas limit set.
+     */
+    fn payload_call(
+        &self,
+        conversation: &str,
+        payload: Bytes,
+    ) -> Result<ContractCall<Client, ()>, Error> {
+        let payload = self.config.seal_payload(payload)?;
+        Ok(self.contract_call(conversation, payload))
+    }
+
+    /*
+     * Build the contract call sending a payload exactly as given.
+     * conversation: the conversation ID
+     * payload: the payload as it goes on chain
+     * Returns the call with the gas limit set.
+     */
+    fn contract_call(&self, conversation: &str, payload: Bytes) -> ContractCall<Client, ()> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let tx = self.contract.send_message(conversation_id.into(), payload);
+        tx.gas(self.config.gas_limit)
+    }
+
+    /*
+     * Send a contract call and wait for it to be confirmed, recording the send
+     * metrics.
+     * conversation: the conversation ID the call sends to
+     * call: the contract call
+     * confirmations: the number of confirmations to wait for
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    async fn submit(
+        &self,
+        conversation: &str,
+        call: ContractCall<Client, ()>,
+        confirmations: usize,
+    ) -> Result<MessageReceipt, Error> {
+        let topic = resolve(conversation, self.config.id_scheme).as_topic();
+        let started = Instant::now();
+        let result = self.submit_call(call, confirmations).await;
+        match &result {
+            Ok(receipt) => telemetry::send_succeeded(
+                topic,
+                self.chain_id(),
+                started.elapsed(),
+                receipt.gas_used,
+            ),
+            Err(_) => telemetry::send_failed(topic, self.chain_id()),
+        }
+        result
+    }
+
+    /*
+     * Send a contract call and wait for it to be confirmed, as in submit.
+     * call: the contract call
+     * confirmations: the number of confirmations to wait for
+     * Returns Ok(MessageReceipt) if the transaction was successful.
+     */
+    async fn submit_call(
+        &self,
+        call: ContractCall<Client, ()>,
+        confirmations: usize,
+    ) -> Result<MessageReceipt, Error> {
+        // recorded on the calling send's span, which declares these fields
+        let span = tracing::Span::current();
+        #[cfg(feature = "flashbots")]
+        if let Some(relay) = &self.config.flashbots_relay {
+            let (tx_hash, block) = self.send_private(relay, &call).await.map_err(|err| {
+                tracing::error!("Transaction error: {:?}", err);
+                err.context("failed to send message")
+            })?;
+            span.record("tx_hash", tracing::field::debug(tx_hash));
+            let receipt = within_confirmation_timeout(
+                self.config.confirmation_timeout,
+                tx_hash,
+                self.private_receipt(tx_hash, block, confirmations),
+            )
+            .await?;
+            return self.checked_receipt(&call, receipt).await;
+        }
+        let pending = call.send().await;
+        if let Err(err) = pending {
+            tracing::error!("Transaction error: {:?}", err);
+            return Err(contract_error(err).context("failed to send message"));
+        }
+        let pending = pending.unwrap();
+        span.record("tx_hash", tracing::field::debug(pending.tx_hash()));
+        let tx_hash = pending.tx_hash();
+        let receipt = match &self.config.speed_up {
+            Some(speed_up) => {
+                self.confirm_with_speed_up(tx_hash, confirmations, speed_up)
+                    .await
+            }
+            None => within_confirmation_timeout(
+                self.config.confirmation_timeout,
+                tx_hash,
+                pending.confirmations(confirmations),
+            )
+            .await?
+            .map_err(Error::from),
+        };
+        self.checked_receipt(&call, receipt).await
+    }
+
+    /*
+     * Check the outcome of waiting for a sent call's receipt, recording the block on
+     * the calling send's span.  A dropped transaction's nonce is resynced so the
+     * next send does not wait behind it.
+     * call: the contract call, replayed to find the reason if it reverted
+     * receipt: the result of waiting for the receipt
+     * Returns Ok(MessageReceipt) if the transaction was mined and succeeded.
+     */
+    async fn checked_receipt(
+        &self,
+        call: &ContractCall<Client, ()>,
+        receipt: Result<Option<TransactionReceipt>, Error>,
+    ) -> Result<MessageReceipt, Error> {
+        if let Err(err) = receipt {
+            tracing::error!("Transaction error: {:?}", err);
+            return Err(anyhow::anyhow!("failed to send message"));
+        }
+        tracing::debug!("Transaction receipt: {:?}", receipt);
+        match receipt.unwrap() {
+            Some(receipt) => {
+                if let Some(block) = receipt.block_number {
+                    tracing::Span::current().record("block", block.as_u64());
+                }
+                if receipt.status == Some(U64::zero()) {
+                    let err = self.replay_revert(call, receipt.block_number).await;
+                    tracing::error!("Transaction {:?} reverted: {err}", receipt.transaction_hash);
+                    return Err(err);
+                }
+                let receipt = MessageReceipt::from(receipt);
+                self.log_gas(&receipt);
+                Ok(receipt)
+            }
+            None => {
+                // the dropped transaction's nonce is free for the next send
+                self.client.resync().await?;
+                Err(anyhow::anyhow!("transaction dropped from the mempool"))
+            }
+        }
+    }
+
+    /*
+     * Wait for a transaction to be mined, replacing it with a higher fee one at the
+     * same nonce each time the policy's wait elapses, then wait for the required
+     * confirmations of whichever transaction was mined.  The confirmation timeout
+     * bounds the whole wait.
+     * tx_hash: the transaction sent
+     * confirmations: the number of confirmations to wait for
+     * speed_up: when and by how much to raise the fees
+     * Returns Ok(Some(TransactionReceipt)) of the mined transaction, Ok(None) if every
+     * transaction was dropped, or ConversationError::ConfirmationTimeout naming the
+     * newest replacement.
+     */
+    async fn confirm_with_speed_up(
+        &self,
+        tx_hash: H256,
+        confirmations: usize,
+        speed_up: &SpeedUpPolicy,
+    ) -> Result<Option<TransactionReceipt>, Error> {
+        let deadline = self
+            .config
+            .confirmation_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let remaining =
+            || deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let mut sent = vec![tx_hash];
+        let mined = loop {
+            let newest = *sent.last().unwrap();
+            let mut wait = speed_up.after;
+            if let Some(remaining) = remaining() {
+                if remaining.is_zero() {
+                    tracing::warn!(
+                        "Transaction {newest:?} not mined before the confirmation timeout"
+                    );
+                    return Err(ConversationError::ConfirmationTimeout { tx_hash: newest }.into());
+                }
+                wait = wait.min(remaining);
+            }
+            let pending = PendingTransaction::new(newest, self.client.provider());
+            match tokio::time::timeout(wait, pending).await {
+                Ok(Ok(Some(receipt))) => break receipt,
+                // a replaced transaction is dropped once another with its nonce is mined
+                Ok(Ok(None)) => match self.mined_receipt(&sent).await? {
+                    Some(receipt) => break receipt,
+                    None => return Ok(None),
+                },
+                Ok(Err(err)) => return Err(err.into()),
+                Err(_) if sent.len() > speed_up.max_replacements as usize => continue,
+                Err(_) => {}
+            }
+            match self
+                .replace_transaction(newest, speed_up.bump_percent)
+                .await
+            {
+                Ok(replacement) => {
+                    tracing::info!("Replaced stuck transaction {newest:?} with {replacement:?}");
+                    sent.push(replacement);
+                }
+                Err(err) => {
+                    // most often the transaction was mined while the wait elapsed
+                    tracing::warn!("Unable to replace transaction {newest:?}: {err}");
+                    if let Some(receipt) = self.mined_receipt(&sent).await? {
+                        break receipt;
+                    }
+                }
+            }
+        };
+        tracing::Span::current().record("tx_hash", tracing::field::debug(mined.transaction_hash));
+        if confirmations <= 1 {
+            return Ok(Some(mined));
+        }
+        let pending = PendingTransaction::new(mined.transaction_hash, self.client.provider())
+            .confirmations(confirmations);
+        Ok(within_confirmation_timeout(remaining(), mined.transaction_hash, pending).await??)
+    }
+
+    /*
+     * Resend a pending transaction at the same nonce with its fees raised.
+     * tx_hash: the pending transaction
+     * bump_percent: the percentage to raise the fees by
+     * Returns Ok(H256) the replacement's hash, or an error if the transaction is no
+     * longer pending or the node rejected the replacement.
+     */
+    async fn replace_transaction(&self, tx_hash: H256, bump_percent: u32) -> Result<H256, Error> {
+        let tx = self
+            .client
+            .get_transaction(tx_hash)
+            .await?
+            .filter(|tx| tx.block_number.is_none())
+            .ok_or_else(|| anyhow::anyhow!("transaction {tx_hash:?} is no longer pending"))?;
+        let pending = self
+            .client
+            .send_transaction(replacement_transaction(&tx, bump_percent), None)
+            .await?;
+        Ok(pending.tx_hash())
+    }
+
+    /*
+     * Sign a contract call and send it privately through a Flashbots relay.  Relays
+     * only accept Ethereum mainnet transactions.
+     * relay: the relay URL
+     * call: the contract call
+     * Returns Ok((tx_hash, block)) with the block number it was sent at once the
+     * relay has accepted it.
+     */
+    #[cfg(feature = "flashbots")]
+    async fn send_private(
+        &self,
+        relay: &str,
+        call: &ContractCall<Client, ()>,
+    ) -> Result<(H256, U64), Error> {
+        let chain_id = self.chain_id();
+        if chain_id != 1 {
+            return Err(anyhow::anyhow!(
+                "flashbots relays only accept Ethereum mainnet transactions, not chain {chain_id}"
+            ));
+        }
+        let mut tx = call.tx.clone();
+        self.client.fill_transaction(&mut tx, None).await?;
+        let signer = self.client.inner().signer();
+        let signature = signer.sign_transaction_sync(&tx)?;
+        let block = self.client.get_block_number().await?;
+        let raw = tx.rlp_signed(&signature);
+        match flashbots::send_private_transaction(relay, signer, &raw, block).await {
+            Ok(tx_hash) => Ok((tx_hash, block)),
+            Err(err) => {
+                // the nonce was taken by the rejected transaction
+                self.client.resync().await?;
+                Err(err)
+            }
+        }
+    }
+
+    /*
+     * Wait for a private transaction's receipt.  Private transactions are not in the
+     * public mempool, so the receipt is polled until the relay gives up on them.
+     * tx_hash: the transaction sent
+     * block: the block number it was sent at
+     * confirmations: the number of confirmations to wait for
+     * Returns Ok(Some(TransactionReceipt)) once confirmed, or Ok(None) if it was not
+     * included in time.
+     */
+    #[cfg(feature = "flashbots")]
+    async fn private_receipt(
+        &self,
+        tx_hash: H256,
+        block: U64,
+        confirmations: usize,
+    ) -> Result<Option<TransactionReceipt>, Error> {
+        loop {
+            let head = self.client.get_block_number().await?;
+            match self.client.get_transaction_receipt(tx_hash).await? {
+                Some(receipt) => {
+                    let mined = receipt.block_number.unwrap_or(head);
+                    if head + 1 >= mined + confirmations {
+                        return Ok(Some(receipt));
+                    }
+                }
+                None if head > block + flashbots::MAX_BLOCKS => return Ok(None),
+                None => {}
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /*
+     * Find which of a message's transactions was mined.
+     * sent: the original transaction and its replacements
+     * Returns Ok(Some(TransactionReceipt)) if one of them has a receipt.
+     */
+    async fn mined_receipt(&self, sent: &[H256]) -> Result<Option<TransactionReceipt>, Error> {
+        for tx_hash in sent.iter().rev() {
+            if let Some(receipt) = self.client.get_transaction_receipt(*tx_hash).await? {
+                return Ok(Some(receipt));
+            }
+        }
+        Ok(None)
+    }
+
+    /*
+     * Create the trace sampler for a follow or rewind loop from the config.
+     * operation: the loop, named in progress reports
+     */
+    fn log_sampler(&self, operation: &'static str) -> LogSampler {
+        LogSampler::new(
+            operation,
+            self.config.trace_sample_every,
+            self.config.progress_interval,
+        )
+    }
+
+    /*
+     * Find out why a mined message transaction reverted by replaying it as a call
+     * at its block, since the receipt carries no reason.
+     * call: the reverted contract call
+     * block: the block the transaction was mined in
+     * Returns ConversationError::Reverted with the reason the replay reverted with.
+     */
+    async fn replay_revert(&self, call: &ContractCall<Client, ()>, block: Option<U64>) -> Error {
+        let block = block.map(|block| BlockNumber::Number(block).into());
+        let reason = match self.client.call(&call.tx, block).await {
+            Err(err) => {
+                let err = contract_error(ContractError::<Client>::from_middleware_error(err));
+                match err.downcast::<ConversationError>() {
+                    Ok(reverted @ ConversationError::Reverted { .. }) => return reverted.into(),
+                    Ok(err) => err.to_string(),
+                    Err(err) => format!("replay failed: {err}"),
+                }
+            }
+            Ok(_) => String::from("no reason given"),
+        };
+        ConversationError::Reverted { reason }.into()
+    }
+
+    /**
+     * Rewind the conversation to the last n messages.
+     * The newest message's block is fetched alone; older blocks are fetched as
+     * windows of block ranges queried concurrently, and the back pointers are then
+     * followed through the fetched logs, so a long history costs a few round trips
+     * per window rather than one per message.
+     * Logs that cannot be decoded are handled by the configured DecodePolicy;
+     * skipped logs do not count towards n.
+     * Messages are returned in the configured rewind_order.
+     * Returns Ok(MessageRewind) a struct containing messages and the last change block.
+     */
+    pub async fn rewind(&self, conversation: &str, n: u32) -> Result<MessageRewind, Error> {
+        self.rewind_ordered(conversation, n, self.config.rewind_order)
+            .await
+    }
+
+    /**
+     * Rewind the conversation to the last n messages as in rewind, in the given
+     * order rather than the configured one.
+     * conversation: the conversation ID
+     * n: the number of messages to rewind
+     * order: the order to return the messages in
+     * Returns Ok(MessageRewind) a struct containing messages and the last change block.
+     */
+    #[tracing::instrument(
+        name = "rewind",
+        skip_all,
+        fields(
+            conversation = %conversation,
+            conversation_id = %resolve(conversation, self.config.id_scheme),
+            n = n,
+            order = ?order,
+            messages = tracing::field::Empty,
+            block = tracing::field::Empty,
+        )
+    )]
+    pub async fn rewind_ordered(
+        &self,
+        conversation: &str,
+        n: u32,
+        order: MessageRewindOrder,
+    ) -> Result<MessageRewind, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let (last_change, logs) = self
+            .rewind_logs(conversation_id, n, None, text_message)
+            .await?;
+        let mut message: Vec<String> = logs.into_iter().map(|(_, message)| message).collect();
+        if order == MessageRewindOrder::NewestFirst {
+            message.reverse();
+        }
+        let rewind = MessageRewind {
+            message,
+            last_change,
+        };
+        tracing::Span::current()
+            .record("messages", rewind.message.len())
+            .record("block", last_change.as_u64());
+        tracing::info!("{} messages found", rewind.message.len());
+        Ok(rewind)
+    }
+
+    /**
+     * Rewind the conversation to the last n messages as in rewind, keeping where
+     * each was emitted and any signed envelope, and numbering them in order.
+     * conversation: the conversation ID
+     * n: the number of messages to rewind
+     * Returns Ok(Vec<RewoundMessage>) the messages ordered by block number and log
+     * index, oldest first.
+     */
+    #[tracing::instrument(name = "rewind", skip_all, fields(conversation = %conversation, n = n))]
+    pub async fn rewind_records(
+        &self,
+        conversation: &String,
+        n: u32,
+    ) -> Result<Vec<RewoundMessage>, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let (_, logs) = self
+            .rewind_logs(conversation_id, n, None, text_message)
+            .await?;
+        logs.iter()
+            .zip(0..)
+            .map(|((log, _), sequence)| {
+                let record = decode_record(log, self.config.encryptor.as_deref())?;
+                Ok(RewoundMessage { sequence, record })
+            })
+            .collect()
+    }
+
+    /**
+     * Stream a conversation's whole history lazily, newest first.  The back pointers
+     * are walked as in rewind, fetching the next round of block windows only once
+     * the messages already fetched have been consumed, so a long history is never
+     * held in memory and dropping the stream stops the walk.  Logs that cannot be
+     * decoded are handled by the configured DecodePolicy.
+     * conversation: the conversation ID
+     * Returns a stream of the messages numbered from zero at the newest, ending
+     * after the oldest message or the first error.
+     */
+    pub fn history_stream(
+        &self,
+        conversation: &str,
+    ) -> impl Stream<Item = Result<RewoundMessage, Error>> + '_ {
+        struct Walk {
+            // None until the newest message block is read from the contract
+            last_change: Option<U256>,
+            fetched: HashMap<U256, Vec<Log>>,
+            fetched_from: U256,
+            first_fetch: bool,
+            pending: VecDeque<Log>,
+            sequence: u64,
+        }
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let walk = Walk {
+            last_change: None,
+            fetched: HashMap::new(),
+            fetched_from: U256::zero(),
+            first_fetch: true,
+            pending: VecDeque::new(),
+            sequence: 0,
+        };
+        stream::try_unfold(walk, move |mut walk| async move {
+            loop {
+                if let Some(log) = walk.pending.pop_front() {
+                    match decode_record(&log, self.config.encryptor.as_deref()) {
+                        Ok(record) => {
+                            let message = RewoundMessage {
+                                sequence: walk.sequence,
+                                record,
+                            };
+                            walk.sequence += 1;
+                            return Ok(Some((message, walk)));
+                        }
+                        Err(err) => {
+                            let policy = self.config.rewind.on_decode_error;
+                            policy.fail_or_continue(err)?;
+                            if matches!(policy, DecodePolicy::Deliver) {
+                                let message = RewoundMessage {
+                                    sequence: walk.sequence,
+                                    record: undecoded_record(&log),
+                                };
+                                walk.sequence += 1;
+                                return Ok(Some((message, walk)));
+                            }
+                            tracing::warn!("skipping undecodable log: {:?}", log.transaction_hash);
+                        }
+                    }
+                }
+                let last_change = match walk.last_change {
+                    Some(last_change) => last_change,
+                    None => {
+                        let newest = self
+                            .contract
+                            .last_message(conversation_id.into())
+                            .call()
+                            .await
+                            .map_err(contract_error)?;
+                        walk.fetched_from = newest + 1;
+                        *walk.last_change.insert(newest)
+                    }
+                };
+                if last_change.is_zero() {
+                    return Ok(None);
+                }
+                if last_change < walk.fetched_from {
+                    // the first round fetches the newest block alone, as rewind does
+                    let windows = if walk.first_fetch {
+                        rewind_windows(last_change, 1, 1)
+                    } else {
+                        rewind_windows(
+                            last_change,
+                            self.config.rewind.window_blocks,
+                            self.config.rewind.concurrency,
+                        )
+                    };
+                    walk.first_fetch = false;
+                    walk.fetched_from = windows.last().map(|(from, _)| *from).unwrap_or_default();
+                    walk.fetched = self.fetch_rewind_windows(conversation_id, windows).await?;
+                }
+                let logs = walk.fetched.remove(&last_change).unwrap_or_default();
+                // the oldest log in the block points back to the previous block
+                let previous = match logs.first() {
+                    Some(oldest) => raw_last_message(&oldest.data)
+                        .ok_or_else(|| anyhow::anyhow!("malformed PayloadSent log data"))?,
+                    None => return Err(reorged_block_error(last_change)),
+                };
+                walk.pending.extend(logs.into_iter().rev());
+                walk.last_change = Some(previous);
+            }
+        })
+    }
+
+    /**
+     * Rewind the conversation to the last n messages as in rewind, decoding each
+     * payload with a codec.
+     * conversation: the conversation ID
+     * n: the number of messages to rewind
+     * codec: the codec decoding the payloads
+     * Returns Ok(Vec<T>) the decoded messages, oldest first, or an error naming the
+     * first payload the codec rejects.
+     */
+    #[tracing::instrument(name = "rewind", skip_all, fields(conversation = %conversation, n = n))]
+    pub async fn rewind_typed<T>(
+        &self,
+        conversation: &String,
+        n: u32,
+        codec: &impl PayloadCodec<T>,
+    ) -> Result<Vec<T>, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let (_, logs) = self
+            .rewind_logs(conversation_id, n, None, text_message)
+            .await?;
+        logs.iter()
+            .map(|(log, _)| {
+                let payload = decode_payload(log, self.config.encryptor.as_deref())?;
+                codec.decode(&payload.message).map_err(|err| {
+                    anyhow::anyhow!(
+                        "payload in {:?} did not decode: {err}",
+                        log.transaction_hash
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /**
+     * Rewind the conversation to the last n messages as in rewind, keeping each
+     * payload as bytes with the content type it was sent with.
+     * conversation: the conversation ID
+     * n: the number of messages to rewind
+     * Returns Ok(Vec<(content_type, payload)>) oldest first, with
+     * DEFAULT_CONTENT_TYPE for messages sent without a type.
+     */
+    #[tracing::instrument(name = "rewind", skip_all, fields(conversation = %conversation, n = n))]
+    pub async fn rewind_content(
+        &self,
+        conversation: &str,
+        n: u32,
+    ) -> Result<Vec<(String, Bytes)>, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let (_, logs) = self
+            .rewind_logs(conversation_id, n, None, |payload| {
+                let content_type = payload.content_type.as_deref();
+                Ok((
+                    content_type.unwrap_or(DEFAULT_CONTENT_TYPE).to_string(),
+                    payload.message.clone(),
+                ))
+            })
+            .await?;
+        Ok(logs.into_iter().map(|(_, content)| content).collect())
+    }
+
+    /*
+     * Walk the conversation's back pointers to its last n messages, as in rewind.
+     * conversation_id: the conversation ID
+     * n: the number of messages to walk
+     * at: the block to read the last message pointer at, or None for the latest
+     * decode: reads each decoded payload, such as text_message
+     * Returns Ok((last_change, logs)) with the decoded logs and their messages in
+     * chain order, oldest first.
+     */
+    async fn rewind_logs<T>(
+        &self,
+        conversation_id: ConversationId,
+        n: u32,
+        at: Option<U64>,
+        decode: fn(&PayloadSentData) -> Result<T, Error>,
+    ) -> Result<(U256, Vec<(Log, T)>), Error> {
+        let started = Instant::now();
+        let mut n = n;
+        let call = self.contract.last_message(conversation_id.into());
+        let call = match at {
+            Some(block) => call.block(BlockNumber::Number(block)),
+            None => call,
+        };
+        let last_change_result: Result<U256, _> = call.call().await;
+        tracing::info!("conversation_id: {conversation_id}");
+        if let Err(err) = last_change_result {
+            tracing::error!("last change error: {:?}", err);
+            return Err(contract_error(err).context("failed to get last change"));
+        }
+        let mut walked = Vec::new();
+        let mut sampler = self.log_sampler("rewind");
+        let mut last_change = last_change_result.unwrap();
+        let newest = last_change;
+        if newest.is_zero() {
+            tracing::info!("conversation {conversation_id} has no messages");
+        }
+        // logs by block for the blocks fetched so far, all at or above fetched_from
+        let mut fetched: HashMap<U256, Vec<Log>> = HashMap::new();
+        let mut fetched_from = last_change + 1;
+        let mut first_fetch = true;
+        while last_change != U256::zero() {
+            tracing::debug!("prev_change: {}", last_change);
+            if last_change < fetched_from {
+                // most rewinds are short, so start with the newest block alone
+                let windows = if first_fetch {
+                    rewind_windows(last_change, 1, 1)
+                } else {
+                    rewind_windows(
+                        last_change,
+                        self.config.rewind.window_blocks,
+                        self.config.rewind.concurrency,
+                    )
+                };
+                first_fetch = false;
+                fetched_from = windows.last().map(|(from, _)| *from).unwrap_or_default();
+                fetched = self.fetch_rewind_windows(conversation_id, windows).await?;
+            }
+            let logs = fetched.get(&last_change).cloned().unwrap_or_default();
+            if logs.is_empty() {
+                tracing::error!("no messages in block {last_change}");
+                return Err(reorged_block_error(last_change));
+            }
+            // newest first, so the walk stops at the newest n of a block's messages;
+            // the oldest log in the block points back to the previous block
+            for log in logs.iter().rev() {
+                let param_result =
+                    decode_payload(log, self.config.encryptor.as_deref()).and_then(|payload| {
+                        Ok((
+                            decode(&payload)?,
+                            payload.message.len(),
+                            payload.last_change,
+                        ))
+                    });
+                match param_result {
+                    Ok((message, message_len, previous)) => {
+                        if sampler.sample(log.block_number) {
+                            tracing::trace!(
+                                block = %last_change,
+                                log_index = ?log.log_index,
+                                tx_hash = ?log.transaction_hash,
+                                message_len = message_len,
+                                previous = %previous,
+                                "rewound message"
+                            );
+                        }
+                        walked.push((log.clone(), message));
+                        last_change = previous;
+                    }
+                    Err(err) => {
+                        tracing::error!("param error: {:?}", err);
+                        self.config.rewind.on_decode_error.fail_or_continue(err)?;
+                        tracing::warn!("skipping undecodable log: {:?}", log.transaction_hash);
+                        // the payload is unreadable but the back pointer may not be
+                        match raw_last_message(&log.data) {
+                            Some(previous) => {
+                                last_change = previous;
+                                continue;
+                            }
+                            None => {
+                                last_change = U256::zero();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                n -= 1;
+                if n == 0 {
+                    last_change = U256::zero();
+                    break;
+                }
+            }
+        }
+
+        telemetry::rewind_finished(
+            conversation_id.as_topic(),
+            self.chain_id(),
+            started.elapsed(),
+        );
+        Ok((newest, chronological(walked)))
+    }
+
+    /*
+     * Fetch the conversation's logs in each block range concurrently.
+     * conversation_id: the conversation ID
+     * windows: the inclusive block ranges to fetch
+     * Returns Ok(HashMap) of the logs in each block, in log order.
+     */
+    async fn fetch_rewind_windows(
+        &self,
+        conversation_id: ConversationId,
+        windows: Vec<(U256, U256)>,
+    ) -> Result<HashMap<U256, Vec<Log>>, Error> {
+        tracing::debug!("fetching {} rewind windows", windows.len());
+        let queries = windows.into_iter().map(|(from, to)| {
+            let filter = range_filter(
+                self.config.contract_address,
+                self.config.event_topic(),
+                conversation_id,
+                from,
+                to,
+            );
+            async move { self.get_logs(&filter).await }
+        });
+        let results: Vec<_> = stream::iter(queries)
+            .buffer_unordered(self.config.rewind.concurrency.max(1))
+            .collect()
+            .await;
+        let mut blocks: HashMap<U256, Vec<Log>> = HashMap::new();
+        for logs in results {
+            // each block lies in a single window, so logs within a block keep their order
+            for log in logs? {
+                let block = log.block_number.unwrap_or_default().as_u64();
+                blocks.entry(U256::from(block)).or_default().push(log);
+            }
+        }
+        Ok(blocks)
+    }
+
+    /*
+     * Fetch the logs matching a filter.  When the provider rejects the block range
+     * as too large or as returning too many results, the range is fetched again as
+     * consecutive chunks of log_chunk_blocks, halving any chunk that is still
+     * rejected, and the results are concatenated in block order.
+     * filter: the log filter
+     * Returns Ok(Vec<Log>) the logs in chain order.
+     */
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Error> {
+        let err = match self.client.get_logs(filter).await {
+            Ok(logs) => return Ok(self.normalize_logs(logs)),
+            Err(err) if is_log_limit_error(&err.to_string()) => err,
+            Err(err) => return Err(err.into()),
+        };
+        let mut pending = match self
+            .filter_chunks(filter, self.config.log_chunk_blocks)
+            .await?
+        {
+            Some(chunks) => chunks,
+            // without a numeric start there is no range to split
+            None => return Err(err.into()),
+        };
+        tracing::warn!(
+            "log query limit hit, fetching {} chunks: {err}",
+            pending.len()
+        );
+        let mut logs = Vec::new();
+        while let Some((from, to)) = pending.pop_front() {
+            let chunk = filter.clone().from_block(from).to_block(to);
+            match self.client.get_logs(&chunk).await {
+                Ok(chunk_logs) => logs.extend(chunk_logs),
+                Err(err) if from < to && is_log_limit_error(&err.to_string()) => {
+                    tracing::debug!("splitting blocks {from} to {to}: {err}");
+                    let middle = from + (to - from) / 2;
+                    pending.push_front((middle + 1, to));
+                    pending.push_front((from, middle));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(self.normalize_logs(logs))
+    }
+
+    /*
+     * Split a filter's block range into consecutive chunks, oldest first.  Without
+     * a to_block the range ends at the current head.
+     * filter: the log filter
+     * chunk_blocks: the number of blocks in each chunk
+     * Returns Ok(Some(chunks)) the inclusive block ranges, or Ok(None) if the filter
+     * has no numeric from_block.
+     */
+    async fn filter_chunks(
+        &self,
+        filter: &Filter,
+        chunk_blocks: u64,
+    ) -> Result<Option<VecDeque<(U64, U64)>>, Error> {
+        let from = match filter.get_from_block() {
+            Some(from) => from,
+            None => return Ok(None),
+        };
+        let to = match filter.get_to_block() {
+            Some(to) => to,
+            None => self.client.get_block_number().await?,
+        };
+        tracing::debug!("chunking blocks {from} to {to} by {chunk_blocks}");
+        Ok(Some(log_chunks(from, to, chunk_blocks).into()))
+    }
+
+    /*
+     * Rewrite logs of the configured event into the PayloadSent layout.
+     * logs: the logs as fetched
+     * Returns the logs, decodable as PayloadSent.
+     */
+    fn normalize_logs(&self, logs: Vec<Log>) -> Vec<Log> {
+        logs.into_iter()
+            .map(|log| self.config.normalize_log(log))
+            .collect()
+    }
+
+    /**
+     * The address of the account sending messages, derived from the signing key.
+     * Returns the signer's Address.
+     */
+    pub fn address(&self) -> Address {
+        self.client.inner().address()
+    }
+
+    /**
+     * The on chain ID of a conversation as this sender resolves it, under the
+     * configured id_scheme.
+     * conversation: the conversation label or 0x-prefixed raw ID
+     * Returns the ConversationId.
+     */
+    pub fn conversation_id(&self, conversation: &str) -> ConversationId {
+        resolve(conversation, self.config.id_scheme)
+    }
+
+    /*
+     * The chain ID of the connected provider, as the signer was configured with.
+     * Returns the chain ID.
+     */
+    fn chain_id(&self) -> u64 {
+        self.client.inner().signer().chain_id()
+    }
+
+    /**
+     * Check a configured public address against the signer's address, warning if
+     * they differ so a stale configuration does not misreport the sending account.
+     * public_key: the configured address as hex
+     * Returns true if the configured address is the signer's address.
+     */
+    pub fn check_address(&self, public_key: &str) -> bool {
+        let address = self.address();
+        let matches = is_address(public_key, address);
+        if !matches {
+            tracing::warn!(
+                "configured public key {public_key} is not the signer address {address:?}"
+            );
+        }
+        matches
+    }
+
+    /**
+     * Check whether the provider connection was healthy at the last background probe.
+     * This does no I/O; the flag is cleared when a probe fails, for example after the
+     * WebSocket disconnects, and set again when one succeeds.  Probes only run with a
+     * health_check_interval configured; without one the flag stays set.
+     */
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Fork a read-only handle sharing this sender's provider connection.  The handle
+     * only reads the chain, so it can be handed to concurrent readers without
+     * exposing send_message.
+     */
+    pub fn clone_readonly(&self) -> MessageSenderReadOnly {
+        MessageSenderReadOnly::new(self.clone())
+    }
+
+    /**
+     * Get the block of the last message sent to the conversation.
+     * conversation: the conversation ID
+     * Returns Ok(U256) the block number, zero if the conversation has no messages.
+     */
+    #[tracing::instrument(name = "last_message_block", skip_all, fields(conversation = %conversation))]
+    pub async fn last_message_block(&self, conversation: &str) -> Result<U256, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let last_change = self
+            .contract
+            .last_message(conversation_id.into())
+            .call()
+            .await
+            .map_err(contract_error)?;
+        Ok(last_change)
+    }
+
+    /**
+     * Get the version string reported by the XPS Sender contract.
+     * Returns Ok(String) the version, or an error if the contract has no version
+     * function.
+     */
+    #[tracing::instrument(name = "get_contract_version", skip_all)]
+    pub async fn get_contract_version(&self) -> Result<String, Error> {
+        let version = self
+            .contract
+            .version()
+            .call()
+            .await
+            .map_err(contract_error)?;
+        Ok(version)
+    }
+
+    /*
+     * Check code is deployed at the contract address.
+     * Returns Ok(()) if it is, or ConversationError::ContractNotDeployed naming the
+     * address and chain.
+     */
+    async fn check_contract_deployed(&self) -> Result<(), Error> {
+        let address = self.config.contract_address;
+        let code = self.client.get_code(address, None).await?;
+        if code.is_empty() {
+            let chain_id = self.chain_id();
+            tracing::error!("No contract deployed at {address:?} on chain {chain_id}");
+            return Err(ConversationError::ContractNotDeployed { address, chain_id }.into());
+        }
+        Ok(())
+    }
+
+    /*
+     * Warn if the contract is not the version this crate expects.  Sending to a
+     * different version may still work, so a mismatch is not an error.
+     */
+    async fn check_contract_version(&self) {
+        match self.get_contract_version().await {
+            Ok(version) if version == EXPECTED_CONTRACT_VERSION => {
+                tracing::debug!("Contract version: {version}");
+            }
+            Ok(version) => tracing::warn!(
+                "Contract version {version} differs from the expected {EXPECTED_CONTRACT_VERSION}"
+            ),
+            Err(err) => tracing::warn!("Unable to read the contract version: {err}"),
+        }
+    }
+
+    /**
+     * Check whether any message has been sent to the conversation.
+     * conversation: the conversation ID
+     * Returns Ok(true) if the conversation has at least one message.
+     */
+    pub async fn conversation_exists(&self, conversation: &str) -> Result<bool, Error> {
+        let last_change = self.last_message_block(conversation).await?;
+        Ok(!last_change.is_zero())
+    }
+
+    /**
+     * Fetch and decode the message sent by a single transaction.
+     * tx_hash: the transaction hash
+     * Returns Ok(MessageRecord) if the transaction sent a message to the XPS Sender
+     * contract.
+     */
+    #[tracing::instrument(name = "message_by_tx", skip_all, fields(tx_hash = ?tx_hash))]
+    pub async fn message_by_tx(&self, tx_hash: H256) -> Result<MessageRecord, Error> {
+        let receipt = self.client.get_transaction_receipt(tx_hash).await?;
+        let receipt = match receipt {
+            Some(receipt) => receipt,
+            None => return Err(anyhow::anyhow!("transaction {tx_hash:?} not found")),
+        };
+        match find_payload_log(
+            self.config.contract_address,
+            self.config.event_topic(),
+            &receipt.logs,
+        ) {
+            Some(log) => decode_record(
+                &self.config.normalize_log(log.clone()),
+                self.config.encryptor.as_deref(),
+            ),
+            None => Err(anyhow::anyhow!(
+                "transaction {tx_hash:?} did not send a message to {:?}",
+                self.config.contract_address
+            )),
+        }
+    }
+
+    /**
+     * Get the message at an ordinal position in the conversation, where index 0 is
+     * the oldest message.
+     * The conversation is walked twice, once to count it and once to rewind to the
+     * index, so the cost is O(total) log queries.  Callers reading several indices
+     * should rewind once and cache the result instead.
+     * conversation: the conversation ID
+     * index: the position of the message
+     * Returns Ok(None) if the index is past the end of the conversation.
+     */
+    #[tracing::instrument(name = "get_message_at_index", skip_all, fields(conversation = %conversation, index = index))]
+    pub async fn get_message_at_index(
+        &self,
+        conversation: &str,
+        index: u32,
+    ) -> Result<Option<String>, Error> {
+        let total = self.message_count(conversation, None).await?;
+        if u64::from(index) >= total {
+            return Ok(None);
+        }
+        let n = u32::try_from(total - u64::from(index))?;
+        let rewind = self
+            .rewind_ordered(conversation, n, MessageRewindOrder::OldestFirst)
+            .await?;
+        Ok(rewind.message.into_iter().next())
+    }
+
+    /**
+     * Count the messages in a conversation.
+     * The contract does not store a count, so this walks the lastMessage back
+     * pointers with one log query per message block; pass a cap to bound the walk on
+     * large conversations.
+     * conversation: the conversation ID
+     * cap: stop counting once this many messages have been found
+     * Returns Ok(u64) the number of messages, or the cap if it was reached.
+     */
+    #[tracing::instrument(name = "message_count", skip_all, fields(conversation = %conversation))]
+    pub async fn message_count(&self, conversation: &str, cap: Option<u64>) -> Result<u64, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let count = self.walk_logs(conversation_id, cap, |_| Ok(())).await?;
+        tracing::debug!("{count} messages counted");
+        Ok(count)
+    }
+
+    /**
+     * Measure a conversation's on-chain history, for capacity planning.  Walks every
+     * message as message_count does, without decoding or decrypting the payloads.
+     * conversation: the conversation ID
+     * Returns Ok(ConversationStats) the number of messages and their payload bytes.
+     */
+    #[tracing::instrument(name = "conversation_history_size", skip_all, fields(conversation = %conversation))]
+    pub async fn conversation_history_size(
+        &self,
+        conversation: &str,
+    ) -> Result<ConversationStats, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let mut total_bytes = 0;
+        let message_count = self
+            .walk_logs(conversation_id, None, |log| {
+                total_bytes += PayloadSentEvent::try_from(log)?.message.len() as u64;
+                Ok(())
+            })
+            .await?;
+        tracing::debug!("{message_count} messages of {total_bytes} bytes");
+        Ok(ConversationStats {
+            message_count,
+            total_bytes,
+        })
+    }
+
+    /*
+     * Walk the lastMessage back pointers from the newest message, with one log query
+     * per message block, visiting each log.
+     * conversation_id: the conversation ID
+     * cap: stop once this many logs have been visited
+     * visit: called with each log, newest first
+     * Returns Ok(u64) the number of logs visited.
+     */
+    async fn walk_logs(
+        &self,
+        conversation_id: ConversationId,
+        cap: Option<u64>,
+        mut visit: impl FnMut(&Log) -> Result<(), Error>,
+    ) -> Result<u64, Error> {
+        let mut last_change = self
+            .contract
+            .last_message(conversation_id.into())
+            .call()
+            .await
+            .map_err(contract_error)?;
+        let mut count = 0;
+        while !last_change.is_zero() {
+            let logs = self.normalize_logs(
+                self.client
+                    .get_logs(&block_filter(
+                        self.config.contract_address,
+                        self.config.event_topic(),
+                        conversation_id,
+                        last_change,
+                    ))
+                    .await?,
+            );
+            if logs.is_empty() {
+                return Err(reorged_block_error(last_change));
+            }
+            let previous = previous_message_block(&logs)?;
+            for log in logs.iter().rev() {
+                if cap.is_some_and(|cap| count >= cap) {
+                    tracing::debug!("count cap reached at {count} messages");
+                    return Ok(count);
+                }
+                visit(log)?;
+                count += 1;
+            }
+            last_change = previous;
+        }
+        Ok(count)
+    }
+
+    /**
+     * Estimate how many eth_getLogs queries rewind would make to fetch the last n
+     * messages, to decide whether to page through a long history instead.
+     * The contract stores only the newest message block, so the back pointers are
+     * walked with one single-block log query per message block, as message_count
+     * does; the rewind windows covering those blocks are then counted without
+     * fetching them.  Ranges a provider rejects as too large are retried in chunks
+     * by rewind and are not counted.
+     * conversation: the conversation ID
+     * n: the number of messages rewind would be asked for
+     * Returns Ok(u64) the number of eth_getLogs queries.
+     */
+    #[tracing::instrument(name = "estimate_rewind_rpc_calls", skip_all, fields(conversation = %conversation, n = n))]
+    pub async fn estimate_rewind_rpc_calls(
+        &self,
+        conversation: &str,
+        n: u32,
+    ) -> Result<u64, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let mut last_change = self
+            .contract
+            .last_message(conversation_id.into())
+            .call()
+            .await
+            .map_err(contract_error)?;
+        let mut blocks = Vec::new();
+        let mut remaining = n;
+        while !last_change.is_zero() && remaining > 0 {
+            let logs = self.normalize_logs(
+                self.client
+                    .get_logs(&block_filter(
+                        self.config.contract_address,
+                        self.config.event_topic(),
+                        conversation_id,
+                        last_change,
+                    ))
+                    .await?,
+            );
+            if logs.is_empty() {
+                return Err(reorged_block_error(last_change));
+            }
+            blocks.push(last_change);
+            // the walk leaves a block through its oldest log, after the newest ones
+            for log in logs.iter().rev() {
+                last_change = match raw_last_message(&log.data) {
+                    Some(previous) => previous,
+                    None => return Err(anyhow::anyhow!("malformed PayloadSent log data")),
+                };
+                remaining -= 1;
+                if remaining == 0 {
+                    break;
+                }
+            }
+        }
+        let queries = rewind_queries(
+            &blocks,
+            self.config.rewind.window_blocks,
+            self.config.rewind.concurrency,
+        );
+        tracing::debug!("{} message blocks need {queries} log queries", blocks.len());
+        Ok(queries)
+    }
+
+    /**
+     * Build a filter for the conversation's PayloadSent logs, for historical queries
+     * with fetch_messages.
+     * conversation: the conversation ID
+     * from: the first block, inclusive, or None for the earliest
+     * to: the last block, inclusive, or None for the latest
+     * Returns Ok(Filter) if the conversation ID was created successfully.
+     */
+    pub fn conversation_filter(
+        &self,
+        conversation: &str,
+        from: Option<U64>,
+        to: Option<U64>,
+    ) -> Result<Filter, Error> {
+        let conversation_id = resolve(conversation, self.config.id_scheme);
+        let mut filter = payload_filter(
+            self.config.contract_address,
+            self.config.event_topic(),
+            vec![conversation_id.as_topic()],
+        );
+        if let Some(from) = from {
+            filter = filter.from_block(from);
+        }
+        if let Some(to) = to {
+            filter = filter.to_block(to);
+        }
+        Ok(filter)
+    }
+
+    /**
+     * Fetch the messages matching a log filter in chain order, without walking the
+     * back pointers as rewind does.  Logs removed by a reorg are left out, and logs
+     * that cannot be decoded are handled by the configured DecodePolicy.
+     * filter: the log filter, typically from conversation_filter
+     * Returns Ok(Vec<MessageRecord>) the decoded messages.
+     */
+    #[tracing::instrument(name = "fetch_messages", skip_all)]
+    pub async fn fetch_messages(&self, filter: &Filter) -> Result<Vec<MessageRecord>, Error> {
+        let logs = self.get_logs(filter).await?;
+        let mut messages = Vec::with_capacity(logs.len());
+        for log in logs.iter().filter(|log| !is_removed(log)) {
+            match decode_record(log, self.config.encryptor.as_deref()) {
+                Ok(record) => messages.push(record),
+                Err(err) => {
+                    let policy = self.config.rewind.on_decode_error;
+                    policy.fail_or_continue(err)?;
+                    if matches!(policy, DecodePolicy::Deliver) {
+                        messages.push(undecoded_record(log));
+                    } else {
+                        tracing::warn!("skipping undecodable log: {:?}", log.transaction_hash);
+                    }
+                }
+            }
+        }
+        tracing::debug!("{} messages fetched", messages.len());
+        Ok(messages)
+    }
+
+    /**
+     * Fetch every PayloadSent event of a conversation in a block range with a single
+     * log query, in chain order.  Unlike rewind this does not walk the back pointers,
+     * so it is much faster over a continuous range but only finds the messages in the
+     * range given; estimate_rewind_rpc_calls helps choose between the two.  A range
+     * the provider rejects as too large is fetched in chunks.  Logs removed by a
+     * reorg are left out, and logs that are not well formed PayloadSent events are
+     * handled by the configured DecodePolicy.
+     * conversation: the conversation ID
+     * from: the first block, inclusive
+     * to: the last block, inclusive
+     * Returns Ok(Vec<PayloadSentEvent>) the events in the range.
+     */
+    #[tracing::instrument(name = "get_events_in_range", skip_all, fields(conversation = %conversation, from = %from, to = %to))]
+    pub async fn get_events_in_range(
+        &self,
+        conversation: &str,
+        from: U64,
+        to: U64,
+    ) -> Result<Vec<PayloadSentEvent>, Error> {
+        if from > to {
+            return Err(anyhow::anyhow!("block range {from} to {to} is empty"));
+        }
+        let filter = self.conversation_filter(conversation, Some(from), Some(to))?;
+        let logs = self.get_logs(&filter).await?;
+        let mut events = Vec::with_capacity(logs.len());
+        for log in logs.iter().filter(|log| !is_removed(log)) {
+            match PayloadSentEvent::try_from(log) {
+                Ok(event) => events.push(event),
+                Err(err) => {
+                    self.config.rewind.on_decode_error.fail_or_continue(err)?;
+                    tracing::warn!("skipping malformed log: {:?}", log.transaction_hash);
+                }
+            }
+        }
+        tracing::debug!("{} events fetched", events.len());
+        Ok(events)
+    }
+
+    /**
+     * Stream the logs matching a filter whose block range is too wide for a single
+     * eth_getLogs.  The range is split into chunks of chunk_size blocks that are
+     * fetched one at a time as the stream is polled, so logs arrive in chain order
+     * and only one chunk is held in memory.  Without a to_block the range ends at
+     * the head when the stream is first polled.
+     * filter: the log filter, which must have a numeric from_block
+     * chunk_size: the number of blocks fetched by each query
+     * Returns a stream of the logs, ending after the first error.
+     */
+    pub fn get_logs_paginated(
+        &self,
+        filter: Filter,
+        chunk_size: u64,
+    ) -> impl Stream<Item = Result<Log, Error>> + '_ {
+        let state: (Option<VecDeque<(U64, U64)>>, VecDeque<Log>) = (None, VecDeque::new());
+        stream::try_unfold(state, move |(mut chunks, mut pending)| {
+            let filter = filter.clone();
+            async move {
+                loop {
+                    if let Some(log) = pending.pop_front() {
+                        return Ok(Some((log, (chunks, pending))));
+                    }
+                    let remaining = match chunks {
+                        Some(ref mut remaining) => remaining,
+                        None => {
+                            let all = self.filter_chunks(&filter, chunk_size).await?;
+                            chunks.insert(all.ok_or_else(|| {
+                                anyhow::anyhow!("get_logs_paginated needs a numeric from_block")
+                            })?)
+                        }
+                    };
+                    let (from, to) = match remaining.pop_front() {
+                        Some(chunk) => chunk,
+                        None => return Ok(None),
+                    };
+                    let chunk = filter.clone().from_block(from).to_block(to);
+                    pending.extend(self.get_logs(&chunk).await?);
+                }
+            }
+        })
+    }
+
+    /**
+     * Follow the conversation and call the callback function for each new message.
+     * Messages mined between start_block and the current head are backfilled with
+     * a ranged log query before live delivery begins, so each message is delivered
+     * exactly once regardless of when the subscription becomes active.  A zero
+     * start_block skips the backfill and follows from the current head.  Logs
+     * removed by a chain reorganization are skipped with a warning, and logs already
+     * delivered within the configured dedup window are dropped.  With an idle timeout
+     * configured, a subscription that stops delivering while the conversation keeps
+     * advancing is torn down and resubscribed with backfill.  If the provider does
+     * not support eth_subscribe, the follow fails with ConversationError::Subscription,
+     * or polls instead if the config's poll_on_subscribe_error is set.
+     * conversation: the conversation ID
+     * start_block: the block to start following from
+     * callback: the callback function to call for each new message
+     * Returns Ok(()) if the transaction was successful.
+     */
+    #[tracing::instrument(
+        name = "follow_messages",
+        skip_all,
+        fields(
+            conversation = %conversation,
+            conversation_id = %resolve(conversation, self.config.id_scheme),
+            start_block = %start_block,
+        )
+    )]
+    pub async fn follow_messages(
+        &self,
+        conversation: &String,
+        start_block: &U256,
+        callback: MessageCallback,
+    ) -> Result<(), Error> {
+        self.follow(
+            std::slice::from_ref(conversation),
+            start_block,
+            0,
+            &FollowOptions::default(),
+            immediate(|_, message| {
+                callback(message);
+                Ok(())
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * Follow the conversation as in follow_messages until one of the limits in the
+     * options is reached.
+     * conversation: the conversation ID
+     * start_block: the block to start following from
+     * options: the message count and duration limits
+     * callback: the callback function to call for each new message
+     * Returns Ok(FollowSummary) describing why the follow stopped.
+     */
+    #[tracing::instrument(name = "follow_messages", skip_all, fields(conversation = %conversation))]
+    pub async fn follow_messages_with_options(
+        &self,
+        conversation: &String,
+        start_block: &U256,
+        options: &FollowOptions,
+        callback: MessageCallback,
+    ) -> Result<FollowSummary, Error> {
+        self.follow(
+            std::slice::from_ref(conversation),
+            start_block,
+            0,
+            options,
+            immediate(|_, message| {
+                callback(message);
+                Ok(())
+            }),
+        )
+        .await
+    }
+
+    /**
+     * Follow the conversation as in follow_messages, resuming after the position
+     * stored in the checkpoint and storing the position of each message once the
+     * callback has handled it.  Without a stored position the follow starts at the
+     * current head.  Messages are delivered at least once: one interrupted before
+     * its position was stored is delivered again after a restart.
+     * conversation: the conversation ID
+     * checkpoint: the storage for the last handled position
+     * callback: the callback function to call for each new message
+     * Returns Ok(()) if the transaction was successful.
+     */
+    #[tracing::instrument(name = "follow_messages", skip_all, fields(conversation = %conversation))]
+    pub async fn follow_messages_resumable(
+        &self,
+        conversation: &String,
+        checkpoint: &dyn Checkpoint,
+        callback: MessageCallback,
+    ) -> Result<(), Error> {
+        let resume = Resume::load(checkpoint)?;
+        self.follow(
+            std::slice::from_ref(conversation),
+            &resume.start_block(),
+            0,
+            &FollowOptions::default(),
+            immediate(|_, message| resume.deliver(message, callback)),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * Follow several conversations with a single subscription and call the callback
+     * function for each new message.  Backfill behaves as in follow_messages.
+     * conversations: the conversation IDs
+     * start_block: the block to start following from
+     * callback: the callback function to call with the conversation and message
+     * Returns Ok(()) if the transaction was successful.
+     */
+    #[tracing::instrument(name = "follow_conversations", skip_all, fields(conversations = conversations.len()))]
+    pub async fn follow_conversations(
+        &self,
+        conversations: &[String],
+        start_block: &U256,
+        callback: ConversationCallback,
+    ) -> Result<(), Error> {
+        self.follow(
+            conversations,
+            start_block,
+            0,
+            &FollowOptions::default(),
+            immediate(|conversation, message| {
+                callback(conversation, message);
+                Ok(())
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * Follow several conversations as in follow_conversations until one of the
+     * limits in the options is reached.
+     * conversations: the conversation IDs
+     * start_block: the block to start following from
+     * options: the message count and duration limits
+     * callback: the callback function to call with the conversation and message
+     * Returns Ok(FollowSummary) describing why the follow stopped.
+     */
+    #[tracing::instrument(name = "follow_conversations", skip_all, fields(conversations = conversations.len()))]
+    pub async fn follow_conversations_with_options(
+        &self,
+        conversations: &[String],
+        start_block: &U256,
+        options: &FollowOptions,
+        callback: ConversationCallback,
+    ) -> Result<FollowSummary, Error> {
+        self.follow(
+            conversations,
+            start_block,
+            0,
+            options,
+            immediate(|conversation, message| {
+                callback(conversation, message);
+                Ok(())
+            }),
+        )
+        .await
+    }
+
+    /**
+     * Follow several conversations as in follow_conversations_with_options and
+     * deliver each new message to a sink.  A delivery error ends the follow.
+     * conversations: the conversation IDs
+     * start_block: the block to start following from
+     * options: the message count and duration limits
+     * sink: the destination for each new message
+     * Returns Ok(FollowSummary) describing why the follow stopped.
+     */
+    #[tracing::instrument(name = "follow_conversations", skip_all, fields(conversations = conversations.len()))]
+    pub async fn follow_conversations_to_sink(
+        &self,
+        conversations: &[String],
+        start_block: &U256,
+        options: &FollowOptions,
+        sink: &dyn MessageSink,
+    ) -> Result<FollowSummary, Error> {
+        self.follow(
+            conversations,
+            start_block,
+            0,
+            options,
+            immediate(|conversation, message| sink.deliver(conversation, message)),
+        )
+        .await
+    }
+
+    /**
+     * Follow the conversation as in follow_messages_with_options, sending each new
+     * message on a bounded channel.  The follow waits while the channel is full, so
+     * a slow receiver holds back the subscription instead of messages piling up in
+     * memory, and it stops cleanly once the receiver is dropped.
+     * conversation: the conversation ID
+     * start_block: the block to start following from
+     * options: the message count and duration limits
+     * sender: the sending half of the channel
+     * Returns Ok(FollowSummary) describing why the follow stopped.
+     */
+    #[tracing::instrument(name = "follow_messages", skip_all, fields(conversation = %conversation))]
+    pub async fn follow_to_channel(
+        &self,
+        conversation: &String,
+        start_block: &U256,
+        options: &FollowOptions,
+        sender: mpsc::Sender<MessageRecord>,
+    ) -> Result<FollowSummary, Error> {
+        let sender = &sender;
+        self.follow(
+            std::slice::from_ref(conversation),
+            start_block,
+            0,
+            options,
+            |_, message| async move {
+                match sender.send(message).await {
+                    Ok(()) => Ok(ControlFlow::Continue(())),
+                    Err(_) => Ok(ControlFlow::Break(())),
+                }
+            },
+        )
+        .await
+    }
+
+    /**
+     * Replay the last n messages of the conversation and then follow it, with no
+     * window between the two for messages to be lost in.  The subscription is opened
+     * first and the current block number read; the history is rewound as of that
+     * block and delivered oldest first, and only then are messages from the
+     * subscription delivered, skipping any at or before that block.  Calling rewind
+     * and then follow_messages instead drops messages sent between the two calls.
+     * conversation: the conversation ID
+     * n: the number of past messages to replay
+     * options: the message count and duration limits, which count replayed messages
+     * callback: the callback function to call for each message
+     * Returns Ok(FollowSummary) describing why the follow stopped.
+     */
+    #[tracing::instrument(name = "follow_messages", skip_all, fields(conversation = %conversation, n = n))]
+    pub async fn follow_messages_complete(
+        &self,
+        conversation: &String,
+        n: u32,
+        options: &FollowOptions,
+        callback: MessageCallback,
+    ) -> Result<FollowSummary, Error> {
+        self.follow(
+            std::slice::from_ref(conversation),
+            &U256::zero(),
+            n,
+            options,
+            immediate(|_, message| {
+                callback(message);
+                Ok(())
+            }),
+        )
+        .await
+    }
+
+    /**
+     * Follow the conversation as in follow_messages_with_options, decoding each
+     * payload with a codec before calling the callback.  A payload the codec rejects
+     * is handled by the options' on_decode_error, except that under Deliver it is
+     * skipped, as there is no value to deliver.
+     * conversation: the conversation ID
+     * start_block: the block to start following from
+     * options: the message count and duration limits
+     * codec: the codec decoding the payloads
+     * callback: the callback function to call with each decoded value and its message
+     * Returns Ok(FollowSummary) describing why the follow stopped.
+     */
+    #[tracing::instrument(name = "follow_messages", skip_all, fields(conversation = %conversation))]
+    pub async fn follow_typed<T>(
+        &self,
+        conversation: &String,
+        start_block: &U256,
+        options: &FollowOptions,
+        codec: &impl PayloadCodec<T>,
+        callback: impl Fn(T, &MessageRecord),
+    ) -> Result<FollowSummary, Error> {
+        // payloads that are not text arrive undecoded and are decoded here
+        let delivering = FollowOptions {
+            on_decode_error: DecodePolicy::Deliver,
+            ..options.clone()
+        };
+        let encryptor = self.config.encryptor.as_deref();
+        let skipped = Cell::new(0);
+        let mut summary = self
+            .follow(
+                std::slice::from_ref(conversation),
+                start_block,
+                0,
+                &delivering,
+                immediate(|_, message| {
+                    let value = record_payload(message, encryptor)
+                        .and_then(|payload| codec.decode(&payload));
+                    match value {
+                        Ok(value) => {
+                            callback(value, message);
+                            Ok(())
+                        }
+                        Err(err) => {
+                            let hash = message.transaction_hash;
+                            let reason = err.to_string();
+                            options.on_decode_error.fail_or_continue(err)?;
+                            tracing::warn!(
+                                "skipping payload the codec rejects: {hash:?}: {reason}"
+                            );
+                            skipped.set(skipped.get() + 1);
+                            Ok(())
+                        }
+                    }
+                }),
+            )
+            .await?;
+        summary.skipped += skipped.get();
+        Ok(summary)
+    }
+
+    async fn follow<F, Fut>(
+        &self,
+        conversations: &[String],
+        start_block: &U256,
+        history: u32,
+        options: &FollowOptions,
+        callback: F,
+    ) -> Result<FollowSummary, Error>
+    where
+        F: Fn(String, MessageRecord) -> Fut,
+        Fut: Future<Output = Result<ControlFlow<()>, Error>>,
+    {
+        let started = Instant::now();
+        let mut delivered: u64 = 0;
+        let decode_errors = DecodeErrors::new(options.on_decode_error);
+        let summary = |delivered: u64, stopped_by: FollowStop| FollowSummary {
+            messages: delivered,
+            elapsed: started.elapsed(),
+            stopped_by,
+            skipped: decode_errors.skipped(),
+        };
+        if let Some(stop) = options.stop(delivered, started.elapsed()) {
+            return Ok(summary(delivered, stop));
+        }
+
+        let topics = conversation_topics(conversations, self.config.id_scheme)?;
+        let mut recent = RecentLogs::new(self.config.dedup_window);
+        let mut sampler = self.log_sampler("follow");
+        for (topic, conversation) in topics.iter() {
+            tracing::info!("conversation_id: {} ({conversation})", hex::encode(topic));
+        }
+        let filter = conversations_filter(
+            self.config.contract_address,
+            self.config.event_topic(),
+            &topics,
+        );
+
+        let mut start_block = U64::from(start_block.as_u64());
+        let mut history = history;
+        loop {
+            // subscribe before backfilling so nothing mined in between is lost
+            let stream_result = self
+                .client
+                .subscribe_logs(&filter.clone().from_block(BlockNumber::Latest))
+                .await;
+            if let Err(err) = stream_result {
+                tracing::error!("Subscription error: {:?}", err);
+                if !self.config.poll_on_subscribe_error {
+                    return Err(subscription_error(err));
+                }
+                tracing::warn!(
+                    "falling back to polling every {:?}",
+                    self.config.poll_interval
+                );
+                let remaining = FollowOptions {
+                    max_messages: options
+                        .max_messages
+                        .map(|max| max.saturating_sub(delivered)),
+                    max_duration: options.remaining(started.elapsed()),
+                    on_decode_error: options.on_decode_error,
+                };
+                let polled = self
+                    .poll(
+                        conversations,
+                        &U256::from(start_block.as_u64()),
+                        history,
+                        self.config.poll_interval,
+                        &remaining,
+                        callback,
+                    )
+                    .await?;
+                return Ok(FollowSummary {
+                    skipped: decode_errors.skipped() + polled.skipped,
+                    ..summary(delivered + polled.messages, polled.stopped_by)
+                });
+            }
+            let mut stream = stream_result.unwrap();
+
+            let head = self.client.get_block_number().await?;
+            // the newest block known to have been fully delivered
+            let mut synced_block = head;
+            // subscribing from the latest block also yields the logs already in it,
+            // which are older than a start block past the head
+            let mut last_seen: Option<LogPosition> = if start_block.is_zero() {
+                None
+            } else {
+                Some((start_block - 1, U256::MAX))
+            };
+            if history > 0 {
+                // replay the history as of the head the subscription started after,
+                // then pass over anything the subscription delivers up to that head
+                let logs = self.history_logs(&topics, history, head).await?;
+                for log in logs.iter() {
+                    if let Some((conversation, record)) = new_message(
+                        log,
+                        &topics,
+                        &mut recent,
+                        &mut sampler,
+                        &decode_errors,
+                        self.config.encryptor.as_deref(),
+                        self.chain_id(),
+                    )? {
+                        if callback(conversation, record).await?.is_break() {
+                            return Ok(summary(delivered, FollowStop::ReceiverDropped));
+                        }
+                        delivered += 1;
+                    }
+                    if let Some(stop) = options.stop(delivered, started.elapsed()) {
+                        return Ok(summary(delivered, stop));
+                    }
+                }
+                last_seen = Some((head, U256::MAX));
+                // a resubscription backfills from the synced block instead
+                history = 0;
+            }
+            if !start_block.is_zero() && start_block <= head {
+                tracing::info!("backfilling blocks {start_block} to {head}");
+                let backfill = filter.clone().from_block(start_block).to_block(head);
+                let logs = self.get_logs(&backfill).await?;
+                for log in logs.iter().filter(|log| !is_removed(log)) {
+                    if let Some((conversation, record)) = new_message(
+                        log,
+                        &topics,
+                        &mut recent,
+                        &mut sampler,
+                        &decode_errors,
+                        self.config.encryptor.as_deref(),
+                        self.chain_id(),
+                    )? {
+                        if callback(conversation, record).await?.is_break() {
+                            return Ok(summary(delivered, FollowStop::ReceiverDropped));
+                        }
+                        delivered += 1;
+                    }
+                    last_seen = log_position(log).or(last_seen);
+                    if let Some(stop) = options.stop(delivered, started.elapsed()) {
+                        return Ok(summary(delivered, stop));
+                    }
+                }
+            }
+
+            loop {
+                if let Some(stop) = options.stop(delivered, started.elapsed()) {
+                    return Ok(summary(delivered, stop));
+                }
+                // wake for whichever comes first, the idle timeout or the deadline
+                let remaining = options.remaining(started.elapsed());
+                let wait = match (self.config.idle_timeout, remaining) {
+                    (Some(idle_timeout), Some(remaining)) => Some(idle_timeout.min(remaining)),
+                    (idle_timeout, remaining) => idle_timeout.or(remaining),
+                };
+                let next = match wait {
+                    Some(wait) => match tokio::time::timeout(wait, stream.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            let idle = self.config.idle_timeout.is_some()
+                                && options.stop(delivered, started.elapsed()).is_none();
+                            if idle && self.is_stale(&topics, synced_block).await? {
+                                break;
+                            }
+                            continue;
+                        }
+                    },
+                    None => stream.next().await,
+                };
+                let log = match next {
+                    Some(log) => self.config.normalize_log(log),
+                    None => return Ok(summary(delivered, FollowStop::StreamEnded)),
+                };
+                if is_removed(&log) {
+                    tracing::warn!(
+                        "skipping log removed by a reorg: {:?}",
+                        log.transaction_hash
+                    );
+                    continue;
+                }
+                if !is_after(&log, &last_seen) {
+                    tracing::debug!("skipping backfilled log: {:?}", log.transaction_hash);
+                    continue;
+                }
+                if let Some((conversation, record)) = new_message(
+                    &log,
+                    &topics,
+                    &mut recent,
+                    &mut sampler,
+                    &decode_errors,
+                    self.config.encryptor.as_deref(),
+                    self.chain_id(),
+                )? {
+                    if callback(conversation, record).await?.is_break() {
+                        return Ok(summary(delivered, FollowStop::ReceiverDropped));
+                    }
+                    delivered += 1;
+                }
+                if let Some(block) = log.block_number {
+                    synced_block = synced_block.max(block);
+                }
+            }
+
+            // the subscription went stale, resubscribe and backfill what was missed;
+            // logs already delivered from the synced block are dropped as duplicates
+            tracing::warn!("subscription stale since block {synced_block}, resubscribing");
+            telemetry::reconnected(self.chain_id());
+            start_block = synced_block;
+        }
+    }
+
+    /*
+     * Check whether a followed conversation has messages newer than the synced block.
+     * topics: the conversation topics being followed
+     * synced_block: the newest block delivered by the subscription
+     * Returns Ok(true) if the subscription has fallen behind the chain.
+     */
+    async fn is_stale(
+        &self,
+        topics: &HashMap<H256, String>,
+        synced_block: U64,
+    ) -> Result<bool, Error> {
+        let mut last_messages = Vec::with_capacity(topics.len());
+        for topic in topics.keys() {
+            let last_change = self
+                .contract
+                .last_message(topic.to_fixed_bytes())
+                .call()
+                .await
+                .map_err(contract_error)?;
+            last_messages.push(last_change);
+        }
+        Ok(is_behind(&last_messages, synced_block))
+    }
+
+    /**
+     * Subscribe to the messages of every conversation, without knowing their IDs in
+     * advance.  Messages mined from start_block to the current head are backfilled
+     * first, as in follow_messages, and a zero start_block follows from the head.
+     * Logs removed by a reorg and logs that cannot be decoded are skipped.
+     * start_block: the block to start from
+     * Returns Ok(Stream) of (conversation ID as 0x-prefixed hex, message) pairs.
+     */
+    #[tracing::instrument(name = "subscribe_all_conversations", skip_all)]
+    pub async fn subscribe_all_conversations(
+        &self,
+        start_block: U64,
+    ) -> Result<impl Stream<Item = (String, String)> + '_, Error> {
+        let filter =
+            all_conversations_filter(self.config.contract_address, self.config.event_topic());
+        // subscribe before backfilling so nothing mined in between is lost
+        let live = self
+            .client
+            .subscribe_logs(&filter.clone().from_block(BlockNumber::Latest))
+            .await
+            .map_err(subscription_error)?;
+        let head = self.client.get_block_number().await?;
+        let mut backfill = Vec::new();
+        if !start_block.is_zero() && start_block <= head {
+            tracing::info!("backfilling blocks {start_block} to {head}");
+            backfill = self
+                .get_logs(&filter.from_block(start_block).to_block(head))
+                .await?;
+        }
+        let last_seen = backfill.last().and_then(log_position);
+        let live = live
+            .map(|log| self.config.normalize_log(log))
+            .filter(move |log| ready(is_after(log, &last_seen)));
+        let encryptor = self.config.encryptor.clone();
+        Ok(stream::iter(backfill)
+            .chain(live)
+            .filter(|log| ready(!is_removed(log)))
+            .filter_map(move |log| ready(any_conversation_message(&log, encryptor.as_deref()))))
+    }
+
+    /**
+     * Watch a conversation's on-chain size grow, for services that archive or prune
+     * once it passes a size.  The history is measured as in conversation_history_size,
+     * then each new message adds its payload bytes to the total.  Logs removed by a
+     * reorg are skipped.
+     * conversation: the conversation ID
+     * threshold: the step in bytes to report at, greater than zero
+     * Returns Ok(Stream) of the total payload bytes each time a message takes it past
+     * a multiple of threshold.
+     */
+    #[tracing::instrument(name = "subscribe_conversation_size_threshold", skip_all, fields(conversation = %conversation, threshold = threshold))]
+    pub async fn subscribe_conversation_size_threshold(
+        &self,
+        conversation: &str,
+        threshold: u64,
+    ) -> Result<impl Stream<Item = u64> + '_, Error> {
+        if threshold == 0 {
+            return Err(anyhow::anyhow!("size threshold must be greater than zero"));
+        }
+        let filter = self.conversation_filter(conversation, None, None)?;
+        // subscribe before measuring the history so nothing mined in between is lost
+        let live = self
+            .client
+            .subscribe_logs(&filter.from_block(BlockNumber::Latest))
+            .await
+            .map_err(subscription_error)?;
+        let mut total = 0;
+        let mut last_seen = None;
+        self.walk_logs(resolve(conversation, self.config.id_scheme), None, |log| {
+            total += PayloadSentEvent::try_from(log)?.message.len() as u64;
+            last_seen = last_seen.max(log_position(log));
+            Ok(())
+        })
+        .await?;
+        tracing::debug!("conversation history is {total} bytes");
+        Ok(live
+            .map(|log| self.config.normalize_log(log))
+            .filter(move |log| ready(is_after(log, &last_seen) && !is_removed(log)))
+            .filter_map(move |log| {
+                let size = match PayloadSentEvent::try_from(&log) {
+                    Ok(event) => event.message.len() as u64,
+                    Err(err) => {
+                        tracing::warn!(
+                            "skipping undecodable log {:?}: {err}",
+                            log.transaction_hash
+                        );
+                        return ready(None);
+                    }
+                };
+                let before = total;
+                total += size;
+                ready(crossed_threshold(before, total, threshold).then_some(total))
+            }))
+    }
+
+    /**
+     * Follow the conversation by polling for logs instead of subscribing, for
+     * providers that do not support eth_subscribe.  Every poll interval the logs
+     * between the last polled block and the current head are fetched, so the
+     * messages delivered are the same as with follow_messages.  A zero start_block
+     * follows from the current head.
+     * conversation: the conversation ID
+     * start_block: the block to start following from
+     * interval: the time between polls, or None for the configured poll_interval
+     * callback: the callback function to call for each new message
+     * Returns Ok(()) if the transaction was successful.
+     */
+    #[tracing::instrument(name = "follow_messages_poll", skip_all, fields(conversation = %conversation))]
+    pub async fn follow_messages_poll(
+        &self,
+        conversation: &String,
+        start_block: &U256,
+        interval: Option<Duration>,
+        callback: MessageCallback,
+    ) -> Result<(), Error> {
+        self.poll(
+            std::slice::from_ref(conversation),
+            start_block,
+            0,
+            interval.unwrap_or(self.config.poll_interval),
+            &FollowOptions::default(),
+            immediate(|_, message| {
+                callback(message);
+                Ok(())
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /*
+     * Follow conversations by polling for logs, as follow does by subscribing.
+     * conversations: the conversation IDs
+     * start_block: the block to start following from, zero for the current head
+     * history: the number of past messages per conversation to replay first
+     * interval: the time between polls
+     * options: the message count, duration and decode error options
+     * callback: the callback function to call for each new message
+     * Returns Ok(FollowSummary) describing why the follow stopped.
+     */
+    async fn poll<F, Fut>(
+        &self,
+        conversations: &[String],
+        start_block: &U256,
+        history: u32,
+        interval: Duration,
+        options: &FollowOptions,
+        callback: F,
+    ) -> Result<FollowSummary, Error>
+    where
+        F: Fn(String, MessageRecord) -> Fut,
+        Fut: Future<Output = Result<ControlFlow<()>, Error>>,
+    {
+        let started = Instant::now();
+        let mut delivered: u64 = 0;
+        let decode_errors = DecodeErrors::new(options.on_decode_error);
+        let summary = |delivered: u64, stopped_by: FollowStop| FollowSummary {
+            messages: delivered,
+            elapsed: started.elapsed(),
+            stopped_by,
+            skipped: decode_errors.skipped(),
+        };
+        let topics = conversation_topics(conversations, self.config.id_scheme)?;
+        let mut recent = RecentLogs::new(self.config.dedup_window);
+        let mut sampler = self.log_sampler("follow");
+        for (topic, conversation) in topics.iter() {
+            tracing::info!("conversation_id: {} ({conversation})", hex::encode(topic));
+        }
+        let filter = conversations_filter(
+            self.config.contract_address,
+            self.config.event_topic(),
+            &topics,
+        );
+
+        let head = self.client.get_block_number().await?;
+        let mut next_block = if start_block.is_zero() {
+            head + 1
+        } else {
+            U64::from(start_block.as_u64())
+        };
+        // the logs to deliver next, starting with any history to replay
+        let mut polled = if history > 0 {
+            self.history_logs(&topics, history, head).await?
+        } else {
+            Vec::new()
+        };
+        loop {
+            for log in polled.iter().filter(|log| !is_removed(log)) {
+                if let Some(stop) = options.stop(delivered, started.elapsed()) {
+                    return Ok(summary(delivered, stop));
+                }
+                if let Some((conversation, record)) = new_message(
+                    log,
+                    &topics,
+                    &mut recent,
+                    &mut sampler,
+                    &decode_errors,
+                    self.config.encryptor.as_deref(),
+                    self.chain_id(),
+                )? {
+                    if callback(conversation, record).await?.is_break() {
+                        return Ok(summary(delivered, FollowStop::ReceiverDropped));
+                    }
+                    delivered += 1;
+                }
+            }
+            if let Some(stop) = options.stop(delivered, started.elapsed()) {
+                return Ok(summary(delivered, stop));
+            }
+            let head = self.client.get_block_number().await?;
+            if next_block <= head {
+                tracing::debug!("polling blocks {next_block} to {head}");
+                let range = filter.clone().from_block(next_block).to_block(head);
+                polled = self.get_logs(&range).await?;
+                next_block = head + 1;
+                continue;
+            }
+            polled = Vec::new();
+            let wait = match options.remaining(started.elapsed()) {
+                Some(remaining) => interval.min(remaining),
+                None => interval,
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /*
+     * Fetch the last messages of each conversation as of a block, for a follow to
+     * replay before delivering new messages.
+     * topics: the conversation topics being followed
+     * history: the number of messages per conversation
+     * head: the block to rewind from
+     * Returns Ok(Vec<Log>) the logs in chain order.
+     */
+    async fn history_logs(
+        &self,
+        topics: &HashMap<H256, String>,
+        history: u32,
+        head: U64,
+    ) -> Result<Vec<Log>, Error> {
+        let mut logs = Vec::new();
+        for topic in topics.keys() {
+            let (_, walked) = self
+                .rewind_logs(ConversationId(topic.0), history, Some(head), text_message)
+                .await?;
+            logs.extend(walked.into_iter().map(|(log, _)| log));
+        }
+        logs.sort_by_key(log_position);
+        tracing::info!("replaying {} messages up to block {head}", logs.len());
+        Ok(logs)
+    }
+}
+
+/// The (block number, log index) position of a log on chain.
+type LogPosition = (U64, U256);
+
+/*
+ * Check whether a size passed a multiple of a threshold as it grew.
+ * before: the size before
+ * after: the size after
+ * threshold: the step, greater than zero
+ * Returns true if a multiple of threshold is above before and at most after.
+ */
+fn crossed_threshold(before: u64, after: u64, threshold: u64) -> bool {
+    after / threshold > before / threshold
+}
+
+/*
+ * Get the on chain position of a log.
+ * log: the log
+ * Returns Some(LogPosition) if the log has been mined.
+ */
+fn log_position(log: &Log) -> Option<LogPosition> {
+    Some((log.block_number?, log.log_index?))
+}
+
+/*
+ * Put rewound logs in chain order by (block number, log index), so messages that
+ * share a block come out in the order they were emitted whatever order they were
+ * walked in.
+ * walked: the logs and their messages
+ * Returns the logs oldest first.
+ */
+fn chronological<T>(mut walked: Vec<(Log, T)>) -> Vec<(Log, T)> {
+    walked.sort_by_key(|(log, _)| log_position(log));
+    walked
+}
+
+/*
+ * Check whether a log comes after the last seen position.
+ * log: the log
+ * last_seen: the position of the last delivered log, if any
+ * Returns true if the log has not yet been delivered.
+ */
+fn is_after(log: &Log, last_seen: &Option<LogPosition>) -> bool {
+    match (log_position(log), last_seen) {
+        (Some(position), Some(last_seen)) => position > *last_seen,
+        _ => true,
+    }
+}
+
+/*
+ * Select the rewound messages worth replaying.
+ * messages: the rewound messages, oldest first
+ * Returns the non-empty messages in the same order.
+ */
+fn replay_messages(messages: Vec<String>) -> Vec<String> {
+    messages
+        .into_iter()
+        .filter(|message| !message.is_empty())
+        .collect()
+}
+
+/*
+ * Check whether a hex address names the given address, ignoring checksum case.
+ * public_key: the address as hex
+ * address: the address to compare with
+ * Returns true if public_key parses to address.
+ */
+fn is_address(public_key: &str, address: Address) -> bool {
+    public_key
+        .trim()
+        .parse::<Address>()
+        .is_ok_and(|parsed| parsed == address)
+}
+
+/*
+ * Check whether a rewind of the last message reflects a sent message.
+ * rewind: the rewind of the conversation's last message
+ * message: the message that was sent
+ * block: the block the message was mined in, if known
+ * Returns true if the message, or a later one, is visible.
+ */
+fn is_indexed(rewind: &MessageRewind, message: &str, block: Option<U64>) -> bool {
+    match block {
+        Some(block) => {
+            !rewind.message.is_empty() && rewind.last_change >= U256::from(block.as_u64())
+        }
+        None => rewind.message.first().is_some_and(|last| last == message),
+    }
+}
+
+/*
+ * Spawn a background task that probes the provider and records whether it answered.
+ * The task ends once the client has been dropped.
+ * client: the client to probe
+ * connected: the flag to update
+ * interval: the time between probes
+ */
+fn monitor_connection(client: Weak<Client>, connected: Arc<AtomicBool>, interval: Duration) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let client = match client.upgrade() {
+                Some(client) => client,
+                None => break,
+            };
+            let healthy = match client.get_block_number().await {
+                Ok(_) => true,
+                Err(err) => {
+                    tracing::warn!("provider health probe failed: {err}");
+                    false
+                }
+            };
+            if connected.swap(healthy, Ordering::Relaxed) != healthy {
+                tracing::info!("provider connected: {healthy}");
+            }
+        }
+    });
+}
+
+/*
+ * Connect to the chain and check the connection by fetching the chain id.
+ * rpc_url: the RPC URL for the chain
+ * Returns Ok(Provider<Ws>) if the provider is connected.
+ */
+async fn connect(rpc_url: &str) -> Result<Provider<Ws>, Error> {
+    let provider = Provider::<Ws>::connect(rpc_url).await?;
+    let chain_id = provider.get_chainid().await?;
+    tracing::info!("Connected to chain: {chain_id}");
+    Ok(provider)
+}
+
+/*
+ * Connect to the provider, retrying with exponential backoff.  Each attempt is
+ * recorded on the current span's attempt field.
+ * rpc_url: the RPC URL for the chain
+ * retry: how many times to attempt the connection and how long to wait between
+ * Returns Ok(Provider) once an attempt succeeds, or the last attempt's error.
+ */
+async fn connect_with_retry(rpc_url: &str, retry: &RetryPolicy) -> Result<Provider<Ws>, Error> {
+    let mut attempt = 1;
+    loop {
+        tracing::Span::current().record("attempt", attempt);
+        match connect(rpc_url).await {
+            Ok(provider) => return Ok(provider),
+            Err(err) if attempt < retry.max_attempts => {
+                let delay = retry.delay(attempt);
+                tracing::warn!(
+                    "connection attempt {attempt} of {} failed, retrying in {delay:?}: {err}",
+                    retry.max_attempts
+                );
+                tokio::time::sleep(delay).await;
+                // the chain is not known until a connection succeeds
+                telemetry::reconnected(0);
+                attempt += 1;
+            }
+            Err(err) => {
+                tracing::error!("connection attempt {attempt} failed, giving up: {err}");
+                return Err(err);
+            }
+        }
+    }
+}
+
+/*
+ * Create a filter for the conversation's PayloadSent logs in a single block.
+ * contract_addr: the XPS MessageSender contract address
+ * event_topic: the topic of the event messages are emitted as
+ * conversation_id: the conversation ID
+ * block: the block number
+ * Returns the log filter.
+ */
+fn block_filter(
+    contract_addr: Address,
+    event_topic: H256,
+    conversation_id: ConversationId,
+    block: U256,
+) -> Filter {
+    range_filter(contract_addr, event_topic, conversation_id, block, block)
+}
+
+/*
+ * Create a filter for the conversation's PayloadSent logs in a range of blocks.
+ * contract_addr: the XPS MessageSender contract address
+ * event_topic: the topic of the event messages are emitted as
+ * conversation_id: the conversation ID
+ * from: the first block, inclusive
+ * to: the last block, inclusive
+ * Returns the log filter.
+ */
+fn range_filter(
+    contract_addr: Address,
+    event_topic: H256,
+    conversation_id: ConversationId,
+    from: U256,
+    to: U256,
+) -> Filter {
+    payload_filter(contract_addr, event_topic, vec![conversation_id.as_topic()])
+        .from_block(U64::from(from.as_u64()))
+        .to_block(U64::from(to.as_u64()))
+}
+
+/*
+ * Create a filter for the PayloadSent logs of any of the conversation topics.
+ * contract_addr: the XPS MessageSender contract address
+ * event_topic: the topic of the event messages are emitted as
+ * topics: the conversation topics
+ * Returns the log filter, without a block range.
+ */
+fn payload_filter(contract_addr: Address, event_topic: H256, topics: Vec<H256>) -> Filter {
+    all_conversations_filter(contract_addr, event_topic).topic1(topics)
+}
+
+/*
+ * Create a filter for the PayloadSent logs of every conversation.
+ * contract_addr: the XPS MessageSender contract address
+ * event_topic: the topic of the event messages are emitted as
+ * Returns the log filter, without a block range.
+ */
+fn all_conversations_filter(contract_addr: Address, event_topic: H256) -> Filter {
+    Filter::new()
+        .topic0(event_topic)
+        .address(vec![contract_addr])
+}
+
+/*
+ * Decode a log from any conversation into its conversation ID and text message.
+ * log: the log
+ * encryptor: the encryptor for encrypted payloads, if one is configured
+ * Returns Some((conversation_id, message)) with the ID as 0x-prefixed hex, or None
+ * with a warning if the log cannot be decoded.
+ */
+fn any_conversation_message(
+    log: &Log,
+    encryptor: Option<&dyn Encryptor>,
+) -> Option<(String, String)> {
+    let conversation_id = match log.topics.get(1) {
+        Some(topic) => format!("{topic:#x}"),
+        None => {
+            tracing::warn!(
+                "PayloadSent log without a conversation: {:?}",
+                log.transaction_hash
+            );
+            return None;
+        }
+    };
+    match decode_text_payload(log, encryptor) {
+        Ok((message, _)) => Some((conversation_id, message)),
+        Err(err) => {
+            tracing::warn!("skipping undecodable log {:?}: {err}", log.transaction_hash);
+            None
+        }
+    }
+}
+
+/*
+ * Build a replacement for a pending transaction: the same nonce, recipient, data
+ * and gas limit with the fees raised, keeping its EIP-1559 or legacy type.
+ * tx: the pending transaction
+ * bump_percent: the percentage to raise the fees by
+ * Returns the replacement transaction.
+ */
+fn replacement_transaction(tx: &Transaction, bump_percent: u32) -> TypedTransaction {
+    match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) {
+        (Some(max_fee), Some(max_priority_fee)) => {
+            let mut replacement = Eip1559TransactionRequest::new()
+                .from(tx.from)
+                .data(tx.input.clone())
+                .value(tx.value)
+                .gas(tx.gas)
+                .nonce(tx.nonce)
+                .max_fee_per_gas(bump_fee(max_fee, bump_percent))
+                .max_priority_fee_per_gas(bump_fee(max_priority_fee, bump_percent));
+            replacement.to = tx.to.map(Into::into);
+            TypedTransaction::Eip1559(replacement)
+        }
+        _ => {
+            let mut replacement = TransactionRequest::new()
+                .from(tx.from)
+                .data(tx.input.clone())
+                .value(tx.value)
+                .gas(tx.gas)
+                .nonce(tx.nonce)
+                .gas_price(bump_fee(tx.gas_price.unwrap_or_default(), bump_percent));
+            replacement.to = tx.to.map(Into::into);
+            TypedTransaction::Legacy(replacement)
+        }
+    }
+}
+
+/*
+ * Raise a fee by a percentage, by at least one wei so a zero fee still rises.
+ * fee: the fee in wei
+ * bump_percent: the percentage to raise it by
+ * Returns the raised fee.
+ */
+fn bump_fee(fee: U256, bump_percent: u32) -> U256 {
+    fee + (fee * bump_percent / 100).max(U256::one())
+}
+
+/*
+ * Wait for a transaction's confirmations, giving up after the timeout.
+ * timeout: the longest wait, or None to wait indefinitely
+ * tx_hash: the transaction waited for
+ * confirmed: the future resolving once the transaction is confirmed
+ * Returns Ok with the future's output, or ConversationError::ConfirmationTimeout if
+ * the timeout elapsed first.
+ */
+async fn within_confirmation_timeout<F: Future>(
+    timeout: Option<Duration>,
+    tx_hash: H256,
+    confirmed: F,
+) -> Result<F::Output, ConversationError> {
+    let Some(timeout) = timeout else {
+        return Ok(confirmed.await);
+    };
+    match tokio::time::timeout(timeout, confirmed).await {
+        Ok(output) => Ok(output),
+        Err(_) => {
+            tracing::warn!("Transaction {tx_hash:?} not confirmed within {timeout:?}");
+            Err(ConversationError::ConfirmationTimeout { tx_hash })
+        }
+    }
+}
+
+/*
+ * Check whether a transaction used most of its gas limit, a sign the message was
+ * larger than expected or the limit is set too low.
+ * gas_used: the gas used, if the receipt reports it
+ * gas_limit: the configured gas limit
+ * Returns true if more than 90% of the limit was used.
+ */
+fn near_gas_limit(gas_used: Option<U256>, gas_limit: u64) -> bool {
+    match gas_used {
+        Some(gas_used) => gas_used * 10 > U256::from(gas_limit) * 9,
+        None => false,
+    }
+}
+
+/*
+ * Check the receipt of one of several ordered messages.
+ * tx_hash: the transaction hash
+ * receipt: the receipt, if the transaction was mined
+ * Returns Ok(MessageReceipt) if the transaction was mined and did not revert.
+ */
+fn ordered_receipt(
+    tx_hash: H256,
+    receipt: Option<TransactionReceipt>,
+) -> Result<MessageReceipt, Error> {
+    match receipt {
+        None => Err(anyhow::anyhow!(
+            "transaction {tx_hash:?} dropped from the mempool"
+        )),
+        Some(receipt) if receipt.status == Some(U64::zero()) => {
+            Err(anyhow::anyhow!("transaction {tx_hash:?} reverted"))
+        }
+        Some(receipt) => Ok(MessageReceipt::from(receipt)),
+    }
+}
+
+/*
+ * Split the blocks below and including top into consecutive ranges, newest first.
+ * Block zero is never included since a zero back pointer ends the conversation.
+ * top: the newest block
+ * window_blocks: the number of blocks in each range
+ * count: the maximum number of ranges
+ * Returns the inclusive (from, to) block ranges.
+ */
+fn rewind_windows(top: U256, window_blocks: u64, count: usize) -> Vec<(U256, U256)> {
+    let window = U256::from(window_blocks.max(1));
+    let mut windows = Vec::with_capacity(count);
+    let mut to = top;
+    while windows.len() < count.max(1) && !to.is_zero() {
+        let from = if to > window {
+            to - window + 1
+        } else {
+            U256::one()
+        };
+        windows.push((from, to));
+        to = from - 1;
+    }
+    windows
+}
+
+/*
+ * Count the log queries rewind makes to visit the given message blocks: the newest
+ * block is fetched alone, then each block below the fetched range starts another
+ * round of concurrent windows.
+ * blocks: the message blocks visited, newest first
+ * window_blocks: the number of blocks in each window
+ * concurrency: the number of windows fetched per round
+ * Returns the number of eth_getLogs queries.
+ */
+fn rewind_queries(blocks: &[U256], window_blocks: u64, concurrency: usize) -> u64 {
+    let mut queries = 0;
+    let mut fetched_from: Option<U256> = None;
+    for block in blocks {
+        if fetched_from.is_some_and(|from| *block >= from) {
+            continue;
+        }
+        let windows = match fetched_from {
+            None => rewind_windows(*block, 1, 1),
+            Some(_) => rewind_windows(*block, window_blocks, concurrency),
+        };
+        queries += windows.len() as u64;
+        fetched_from = windows.last().map(|(from, _)| *from);
+    }
+    queries
+}
+
+/*
+ * Split an inclusive block range into consecutive chunks, oldest first.
+ * from: the first block
+ * to: the last block
+ * chunk_blocks: the number of blocks in each chunk
+ * Returns the inclusive (from, to) block ranges.
+ */
+fn log_chunks(from: U64, to: U64, chunk_blocks: u64) -> Vec<(U64, U64)> {
+    let chunk = U64::from(chunk_blocks.max(1));
+    let mut chunks = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let end = (start + chunk - 1).min(to);
+        chunks.push((start, end));
+        start = end + 1;
+    }
+    chunks
+}
+
+/*
+ * Check whether a log query error is a provider limit on the block range or on the
+ * number of results, rather than a failure that retrying in chunks cannot fix.
+ * message: the error message
+ * Returns true if the range should be split.
+ */
+fn is_log_limit_error(message: &str) -> bool {
+    const LIMIT_ERRORS: [&str; 8] = [
+        "block range",
+        "range is too large",
+        "range too large",
+        "query returned more than",
+        "too many results",
+        "response size",
+        "limit exceeded",
+        "exceeds the limit",
+    ];
+    let message = message.to_lowercase();
+    LIMIT_ERRORS.iter().any(|limit| message.contains(limit))
+}
+
+/*
+ * Create a filter for the PayloadSent logs of the followed conversations.
+ * contract_addr: the XPS MessageSender contract address
+ * event_topic: the topic of the event messages are emitted as
+ * topics: the conversation topics being followed
+ * Returns the log filter, without a block range.
+ */
+fn conversations_filter(
+    contract_addr: Address,
+    event_topic: H256,
+    topics: &HashMap<H256, String>,
+) -> Filter {
+    payload_filter(contract_addr, event_topic, topics.keys().copied().collect())
+}
+
+/*
+ * Map each conversation topic to the conversation it was derived from.
+ * conversations: the conversation strings
+ * scheme: how conversation labels are derived
+ * Returns Ok(HashMap<H256, String>) if every conversation ID was created successfully.
+ */
+fn conversation_topics(
+    conversations: &[String],
+    scheme: IdScheme,
+) -> Result<HashMap<H256, String>, Error> {
+    let mut topics = HashMap::new();
+    for conversation in conversations.iter() {
+        topics.insert(
+            resolve(conversation, scheme).as_topic(),
+            conversation.clone(),
+        );
+    }
+    Ok(topics)
+}
+
+/*
+ * Find the conversation a log belongs to.
+ * log: the log
+ * topics: the conversation topics being followed
+ * Returns the conversation string, or the hex topic if it is not being followed.
+ */
+fn log_conversation(log: &Log, topics: &HashMap<H256, String>) -> String {
+    match log.topics.get(1) {
+        Some(topic) => match topics.get(topic) {
+            Some(conversation) => conversation.clone(),
+            None => hex::encode(topic),
+        },
+        None => String::new(),
+    }
+}
+
+/*
+ * Check whether any conversation's last message is newer than the synced block.
+ * last_messages: the last message block of each followed conversation
+ * synced_block: the newest block delivered
+ * Returns true if a message has been mined that was never delivered.
+ */
+fn is_behind(last_messages: &[U256], synced_block: U64) -> bool {
+    let synced_block = U256::from(synced_block.as_u64());
+    last_messages
+        .iter()
+        .any(|last_change| *last_change > synced_block)
+}
+
+/*
+ * Check whether a log was removed from the chain by a reorg.
+ * log: the log
+ * Returns true if the log is no longer part of the canonical chain.
+ */
+fn is_removed(log: &Log) -> bool {
+    log.removed.unwrap_or(false)
+}
+
+/*
+ * The error for a log subscription the provider would not open.
+ * err: the provider error
+ */
+fn subscription_error(err: impl fmt::Display) -> Error {
+    ConversationError::Subscription {
+        reason: err.to_string(),
+    }
+    .into()
+}
+
+/*
+ * The error for a change block that no longer holds any of the conversation's logs,
+ * which happens when the block was reorganized away after last_message was read.
+ * block: the block number
+ */
+fn reorged_block_error(block: U256) -> Error {
+    anyhow::anyhow!("no messages found in block {block}, it may have been reorganized")
+}
+
+/*
+ * Decode a PayloadSent log into the conversation and message to deliver, unless the
+ * log was already delivered recently.
+ * log: the log
+ * topics: the conversation topics being followed
+ * recent: the recently delivered logs
+ * sampler: chooses which messages are traced
+ * decode_errors: what to do if the payload cannot be decoded, counting skipped logs
+ * encryptor: the encryptor for encrypted payloads, if one is configured
+ * chain_id: the chain followed, for the received message metric
+ * Returns Ok(Some((conversation, message))) for a new message, Ok(None) for a
+ * duplicate or a skipped log.
+ */
+fn new_message(
+    log: &Log,
+    topics: &HashMap<H256, String>,
+    recent: &mut RecentLogs,
+    sampler: &mut LogSampler,
+    decode_errors: &DecodeErrors,
+    encryptor: Option<&dyn Encryptor>,
+    chain_id: u64,
+) -> Result<Option<(String, MessageRecord)>, Error> {
+    if !recent.insert(log) {
+        tracing::debug!("skipping duplicate log: {:?}", log.transaction_hash);
+        return Ok(None);
+    }
+    if sampler.sample(log.block_number) {
+        tracing::trace!(
+            block = ?log.block_number,
+            log_index = ?log.log_index,
+            tx_hash = ?log.transaction_hash,
+            data_len = log.data.len(),
+            "followed log"
+        );
+    }
+    let record = match decode_record(log, encryptor) {
+        Ok(record) => record,
+        Err(err) => {
+            decode_errors.policy.fail_or_continue(err)?;
+            if matches!(decode_errors.policy, DecodePolicy::Deliver) {
+                tracing::warn!("delivering undecodable log: {:?}", log.transaction_hash);
+                undecoded_record(log)
+            } else {
+                tracing::warn!("skipping undecodable log: {:?}", log.transaction_hash);
+                decode_errors.skip();
+                return Ok(None);
+            }
+        }
+    };
+    telemetry::message_received(record.conversation_topic, chain_id);
+    Ok(Some((log_conversation(log, topics), record)))
+}
+
+/*
+ * The record delivered for a log that cannot be decoded under DecodePolicy::Deliver.
+ * log: the log
+ * Returns the record, with an empty message and the raw log data in undecoded.
+ */
+fn undecoded_record(log: &Log) -> MessageRecord {
+    MessageRecord {
+        message: String::new(),
+        block_number: log.block_number,
+        transaction_hash: log.transaction_hash,
+        log_index: log.log_index,
+        conversation_topic: log.topics.get(1).copied(),
+        undecoded: Some(log.data.clone()),
+        signed: None,
+        decrypt_error: None,
+        content_type: None,
+    }
+}
+
+/*
+ * Adapt a callback that handles a message before returning to the form follow takes.
+ * callback: the callback function to call with the conversation and message
+ * Returns the adapted callback, which always continues the follow.
+ */
+fn immediate(
+    callback: impl Fn(&String, &MessageRecord) -> Result<(), Error>,
+) -> impl Fn(String, MessageRecord) -> Ready<Result<ControlFlow<()>, Error>> {
+    move |conversation, message| ready(callback(&conversation, &message).map(ControlFlow::Continue))
+}
+
+/*
+ * Decode a PayloadSent log into a MessageRecord.  A payload that cannot be decrypted
+ * is not an error: it is returned as a record flagged with the decrypt error, so one
+ * message under another key does not stop a follow.
+ * log: the log
+ * encryptor: the encryptor for encrypted payloads, if one is configured
+ * Returns Ok(MessageRecord) if the log was decoded successfully.
+ */
+fn decode_record(log: &Log, encryptor: Option<&dyn Encryptor>) -> Result<MessageRecord, Error> {
+    let param_result =
+        decode_payload(log, encryptor).and_then(|payload| Ok((text_message(&payload)?, payload)));
+    if let Err(err) = &param_result {
+        if let Some(ConversationError::DecryptFailed { reason }) = err.downcast_ref() {
+            tracing::warn!("undecryptable log {:?}: {reason}", log.transaction_hash);
+            return Ok(MessageRecord {
+                message: String::new(),
+                block_number: log.block_number,
+                transaction_hash: log.transaction_hash,
+                log_index: log.log_index,
+                conversation_topic: log.topics.get(1).copied(),
+                undecoded: Some(log.data.clone()),
+                signed: None,
+                decrypt_error: Some(reason.clone()),
+                content_type: None,
+            });
+        }
+    }
+    if let Ok((message, payload)) = param_result {
+        Ok(MessageRecord {
+            message,
+            block_number: log.block_number,
+            transaction_hash: log.transaction_hash,
+            log_index: log.log_index,
+            conversation_topic: log.topics.get(1).copied(),
+            undecoded: None,
+            signed: payload.signed,
+            decrypt_error: None,
+            content_type: payload.content_type,
+        })
+    } else {
+        let err = param_result.unwrap_err();
+        tracing::error!("param error: {:?}", err);
+        Err(err)
+    }
+}
 
 /*
- * Decode the payload sent event.
+ * Read the lastMessage back pointer from PayloadSent log data without decoding the
+ * payload.  The pointer is the second head word, after the payload offset.
  * data: the event data
- * Returns Ok(Vec<Token>) if the event was decoded successfully.
+ * Returns Some(U256) if the data holds both head words.
+ */
+fn raw_last_message(data: &[u8]) -> Option<U256> {
+    data.get(32..64).map(U256::from_big_endian)
+}
+
+/*
+ * The block a back pointer walk moves to once it has visited a message block.  The
+ * contract emits each log before recording its block as the newest, so only the
+ * oldest log of a block points back past it; the others point at the block itself.
+ * logs: the conversation's logs in the block, in chain order
+ * Returns Ok(U256) the previous message block, zero before the first message.
+ */
+fn previous_message_block(logs: &[Log]) -> Result<U256, Error> {
+    logs.first()
+        .and_then(|log| raw_last_message(&log.data))
+        .ok_or_else(|| anyhow::anyhow!("malformed PayloadSent log data"))
+}
+
+/*
+ * Find the PayloadSent log emitted by the XPS Sender contract.
+ * contract_addr: the XPS MessageSender contract address
+ * event_topic: the topic of the event messages are emitted as
+ * logs: the logs of a transaction receipt
+ * Returns Some(&Log) if the transaction sent a message.
+ */
+fn find_payload_log(contract_addr: Address, event_topic: H256, logs: &[Log]) -> Option<&Log> {
+    logs.iter()
+        .find(|log| log.address == contract_addr && log.topics.first() == Some(&event_topic))
+}
+
+/// The BIP-44 derivation path of the first Ethereum account, as most wallets use.
+pub const DEFAULT_HD_PATH: &str = "m/44'/60'/0'/0/0";
+
+/// The number of hex characters in a private key, not counting a `0x` prefix.
+const PRIVATE_KEY_HEX_LENGTH: usize = 64;
+
+/**
+ * Create a wallet from a private key.
+ * key: the hex encoded private key, optionally prefixed with 0x
+ * Returns Ok(WalletType) if the wallet was created successfully, or
+ * ConversationError::InvalidPrivateKey carrying the supplied key length.
+ */
+pub fn parse_wallet(key: &str) -> Result<WalletType, ConversationError> {
+    key.parse::<LocalWallet>()
+        .map_err(|err| ConversationError::InvalidPrivateKey {
+            reason: format!(
+                "{err}, expected {PRIVATE_KEY_HEX_LENGTH} hex characters optionally prefixed with 0x"
+            ),
+            key_length: key.len(),
+        })
+}
+
+/**
+ * Create a wallet from a BIP-39 mnemonic phrase.
+ * phrase: the English mnemonic phrase
+ * derivation_path: the BIP-32 path of the account, such as DEFAULT_HD_PATH
+ * Returns Ok(WalletType) if the wallet was derived successfully, or
+ * ConversationError::InvalidMnemonic carrying the number of words supplied.
+ */
+pub fn wallet_from_mnemonic(
+    phrase: &str,
+    derivation_path: &str,
+) -> Result<WalletType, ConversationError> {
+    let invalid = |reason: String| ConversationError::InvalidMnemonic {
+        reason,
+        word_count: phrase.split_whitespace().count(),
+    };
+    MnemonicBuilder::<English>::default()
+        .phrase(phrase.trim())
+        .derivation_path(derivation_path)
+        .map_err(|err| invalid(format!("derivation path {derivation_path}: {err}")))?
+        .build()
+        .map_err(|err| invalid(err.to_string()))
+}
+
+/**
+ * The private key of a wallet as hex, for constructors that take a key string, such
+ * as MessageSender::new with a wallet from wallet_from_mnemonic.
+ * wallet: the wallet
+ * Returns the 64 hex character private key.
+ */
+pub fn wallet_key_hex(wallet: &WalletType) -> String {
+    hex::encode(wallet.signer().to_bytes())
+}
+
+/**
+ * Check that a configured public key is the address of a private key, so a pair
+ * copied from different accounts is caught before any transaction is sent.
+ * private_key: the hex encoded private key
+ * public_key: the configured address as hex
+ * Returns Ok(Address) the private key's address, or ConversationError::KeyMismatch.
+ */
+pub fn check_key_pair(private_key: &str, public_key: &str) -> Result<Address, ConversationError> {
+    let address = parse_wallet(private_key)?.address();
+    if !is_address(public_key, address) {
+        return Err(ConversationError::KeyMismatch {
+            configured: public_key.to_string(),
+            derived: format!("{address:?}"),
+        });
+    }
+    Ok(address)
+}
+
+/**
+ * Create a conversation ID from a conversation string as a MessageSender with the
+ * default IdScheme::Sha3Plain does, taking 0x-prefixed 32 byte hex as a raw ID.
+ * ConversationId::from gives the same ID as a typed value, and
+ * MessageSender::conversation_id the ID under the sender's configured scheme.
+ * conversation: the conversation string
+ * Returns Ok([u8; 32]) if the conversation ID was created successfully.
  */
-fn abi_decode_payload_sent(data: Vec<u8>) -> Result<Vec<Token>, Error> {
-    let param = [ethabi::ParamType::String, ethabi::ParamType::Uint(256)];
-    let decoded = ethabi::decode(&param, &data)?;
-    Ok(decoded)
+pub fn to_conversation_id(conversation: &str) -> Result<[u8; 32], Error> {
+    Ok(resolve(conversation, IdScheme::Sha3Plain).into())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/**
+ * Create a conversation ID for a group chat from its participants.  The addresses
+ * are sorted and deduplicated, so any ordering or repetition of the same set of
+ * participants gives the same ID.  The sorted addresses are ABI packed as an
+ * address[] and hashed with SHA3-256.
+ * participants: the addresses of the group members
+ * Returns the 32 byte conversation ID.
+ */
+pub fn group_conversation_id(mut participants: Vec<Address>) -> [u8; 32] {
+    participants.sort();
+    participants.dedup();
+    let addresses = participants.into_iter().map(Token::Address).collect();
+    let packed = ethers::abi::encode_packed(&[Token::Array(addresses)])
+        .expect("an address array always packs");
+    let mut hasher = Sha3_256::default();
+    hasher.update(packed);
+    hasher.finalize().into()
+}
+
+/**
+ * Decode a PayloadSent log with the bindings generated from the contract ABI, so the
+ * event signature and data layout cannot drift from the contract.  A compressed
+ * payload is expanded, so the message is the payload as it was before sending.
+ * An encrypted payload fails with ConversationError::DecryptFailed; decode_payload
+ * decrypts it.
+ * log: the log
+ * Returns Ok(PayloadSentData) if the log is a well formed PayloadSent event, or an
+ * error describing the mismatch.
+ */
+pub fn decode_payload_sent(log: &Log) -> Result<PayloadSentData, Error> {
+    decode_payload(log, None)
+}
+
+/**
+ * Decode a PayloadSent log as in decode_payload_sent, decrypting an encrypted payload.
+ * log: the log
+ * encryptor: the encryptor the payload was sent with, if any
+ * Returns Ok(PayloadSentData) if the log is a well formed PayloadSent event, or
+ * ConversationError::DecryptFailed if the payload is encrypted and does not decrypt.
+ */
+pub fn decode_payload(
+    log: &Log,
+    encryptor: Option<&dyn Encryptor>,
+) -> Result<PayloadSentData, Error> {
+    PayloadSentEvent::try_from(log)?.open(encryptor)
+}
+
+/**
+ * Decode the data of a PayloadSent log as a text message and its back pointer, for
+ * processors that obtain logs without a MessageSender, such as their own indexer.
+ * Only the data is read; checking the log's address and topics is up to the caller.
+ * data: the log data
+ * Returns Ok((message, last_change)) if the data holds a UTF-8 message.
+ */
+pub fn decode_payload_sent_log(data: &[u8]) -> Result<(String, U256), ConversationError> {
+    let (message, last_change) = decode_payload_sent_raw_bytes(data)?;
+    match String::from_utf8(message.to_vec()) {
+        Ok(message) => Ok((message, last_change)),
+        Err(err) => Err(ConversationError::PayloadNotUtf8 {
+            reason: err.to_string(),
+        }),
+    }
+}
+
+/**
+ * Decode the data of a PayloadSent log as in decode_payload_sent_log, leaving the
+ * message as bytes for payloads that are not text.
+ * data: the log data
+ * Returns Ok((message, last_change)) if the data is an encoded (bytes, uint256).
+ */
+pub fn decode_payload_sent_raw_bytes(data: &[u8]) -> Result<(Bytes, U256), ConversationError> {
+    let payload = open_payload_data(data, None)?;
+    Ok((payload.message, payload.last_change))
+}
+
+/*
+ * Decode the data of a PayloadSent log and open its payload.
+ * data: the log data
+ * encryptor: the encryptor for encrypted payloads, if one is configured
+ * Returns Ok(PayloadSentData) if the data is an encoded (bytes, uint256) whose
+ * payload opens.
+ */
+fn open_payload_data(
+    data: &[u8],
+    encryptor: Option<&dyn Encryptor>,
+) -> Result<PayloadSentData, ConversationError> {
+    let invalid = |reason: String| ConversationError::InvalidPayloadData {
+        reason,
+        data_length: data.len(),
+    };
+    let tokens = ethabi::decode(
+        &[ethabi::ParamType::Bytes, ethabi::ParamType::Uint(256)],
+        data,
+    )
+    .map_err(|err| invalid(err.to_string()))?;
+    match tokens.as_slice() {
+        [Token::Bytes(message), Token::Uint(last_change)] => {
+            open_payload(Bytes::from(message.clone()), *last_change, encryptor)
+                .map_err(|err| invalid(err.to_string()))
+        }
+        _ => Err(invalid(String::from("unexpected token types"))),
+    }
+}
+
+/*
+ * The payload bytes a follow delivered a record for, whether or not they are text.
+ * A record the follow could not decode as text carries the raw log data, which is
+ * opened here.
+ * message: the delivered record
+ * encryptor: the encryptor for encrypted payloads, if one is configured
+ * Returns Ok(Bytes) the payload as it was before sending.
+ */
+fn record_payload(
+    message: &MessageRecord,
+    encryptor: Option<&dyn Encryptor>,
+) -> Result<Bytes, Error> {
+    match &message.undecoded {
+        Some(data) => Ok(open_payload_data(data, encryptor)?.message),
+        None => Ok(Bytes::from(message.message.clone().into_bytes())),
+    }
+}
+
+/*
+ * Decode a PayloadSent log carrying a text message.
+ * log: the log
+ * encryptor: the encryptor for encrypted payloads, if one is configured
+ * Returns Ok((message, last_change)) if the payload is valid UTF-8.
+ */
+fn decode_text_payload(
+    log: &Log,
+    encryptor: Option<&dyn Encryptor>,
+) -> Result<(String, U256), Error> {
+    let payload = decode_payload(log, encryptor)?;
+    Ok((text_message(&payload)?, payload.last_change))
+}
+
+/*
+ * Read a decoded PayloadSent message as text.
+ * payload: the decoded event data
+ * Returns Ok(String) if the message is valid UTF-8.
+ */
+fn text_message(payload: &PayloadSentData) -> Result<String, Error> {
+    payload
+        .message_utf8()
+        .ok_or_else(|| anyhow::anyhow!("PayloadSent message is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::utils::keccak256;
+
+    #[test]
+    fn test_to_conversation_id() {
+        let conversation = String::from("test");
+        let conversation_id = to_conversation_id(&conversation).unwrap();
+        let expected: [u8; 32] = [
+            54, 240, 40, 88, 11, 176, 44, 200, 39, 42, 154, 2, 15, 66, 0, 227, 70, 226, 118, 174,
+            102, 78, 69, 238, 128, 116, 85, 116, 226, 245, 171, 128,
+        ];
+        assert_eq!(conversation_id, expected);
+        // a raw ID is used as is, not hashed again
+        let raw = format!("0x{}", hex::encode(expected));
+        assert_eq!(to_conversation_id(&raw).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_follow_start_block_without_history() {
+        let rewind = MessageRewind {
+            message: Vec::new(),
+            last_change: U256::zero(),
+        };
+        // zero follows from the current head rather than scanning from genesis
+        assert_eq!(rewind.follow_start_block(), U256::zero());
+    }
+
+    #[test]
+    fn test_follow_start_block_after_last_change() {
+        let rewind = MessageRewind {
+            message: vec![String::from("hello")],
+            last_change: U256::from(1234),
+        };
+        assert_eq!(rewind.follow_start_block(), U256::from(1235));
+    }
+
+    #[test]
+    fn test_message_sender_config_default() {
+        let config = MessageSenderConfig::default();
+        assert_eq!(
+            config.contract_address,
+            SENDER_CONTRACT.parse::<Address>().unwrap()
+        );
+        assert!(config.verify_contract);
+        assert_eq!(config.gas_limit, GAS_LIMIT);
+        assert_eq!(config.dedup_window, DEDUP_WINDOW);
+        assert_eq!(config.required_confirmations, REQUIRED_CONFIRMATIONS);
+        assert_eq!(config.poll_interval, POLL_INTERVAL);
+        assert!(!config.poll_on_subscribe_error);
+        assert_eq!(config.idle_timeout, None);
+        assert_eq!(config.health_check_interval, None);
+        assert_eq!(config.priority_fee_tip, U256::from(PRIORITY_FEE_TIP));
+        assert_eq!(config.log_chunk_blocks, LOG_CHUNK_BLOCKS);
+        assert_eq!(config.id_scheme, IdScheme::Sha3Plain);
+        assert!(matches!(config.rewind.on_decode_error, DecodePolicy::Fail));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression_level_threshold() {
+        let config = MessageSenderConfig::default();
+        assert_eq!(config.compression_level(1_000), None);
+        let config = config.with_compression(9);
+        assert_eq!(config.compression_level(1), Some(9));
+        let config = config.with_compression_threshold(100);
+        assert_eq!(config.compression_level(100), None);
+        assert_eq!(config.compression_level(101), Some(9));
+        let config = MessageSenderConfig::default().with_compression_threshold(100);
+        assert_eq!(
+            config.compression_level(101),
+            Some(compression::COMPRESSION_LEVEL)
+        );
+    }
 
     #[test]
-    fn test_to_conversation_id() {
-        let conversation = String::from("test");
-        let conversation_id = to_conversation_id(&conversation).unwrap();
-        let expected: [u8; 32] = [
-            54, 240, 40, 88, 11, 176, 44, 200, 39, 42, 154, 2, 15, 66, 0, 227, 70, 226, 118, 174,
-            102, 78, 69, 238, 128, 116, 85, 116, 226, 245, 171, 128,
+    fn test_retry_policy_delay() {
+        let retry = RetryPolicy {
+            max_attempts: 10,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        assert_eq!(retry.delay(1), Duration::from_millis(100));
+        assert_eq!(retry.delay(2), Duration::from_millis(200));
+        assert_eq!(retry.delay(4), Duration::from_millis(800));
+        assert_eq!(retry.delay(5), Duration::from_secs(1));
+        assert_eq!(retry.delay(64), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_parse_wallet() {
+        let key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let wallet = parse_wallet(key).unwrap();
+        assert_eq!(
+            wallet.address(),
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert!(parse_wallet(&key[2..]).is_ok());
+    }
+
+    #[test]
+    fn test_check_key_pair() {
+        let key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let address = check_key_pair(key, "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266").unwrap();
+        assert_eq!(
+            address,
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+                .parse::<Address>()
+                .unwrap()
+        );
+        match check_key_pair(key, "0x6CEb0bF1f28ca4165d5C0A04f61DC733987eD6ad") {
+            Err(ConversationError::KeyMismatch {
+                configured,
+                derived,
+            }) => {
+                assert_eq!(configured, "0x6CEb0bF1f28ca4165d5C0A04f61DC733987eD6ad");
+                assert_eq!(derived, "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+            }
+            other => panic!("mismatched pair should not pass: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_wallet_from_mnemonic() {
+        let phrase = "test test test test test test test test test test test junk";
+        let wallet = wallet_from_mnemonic(phrase, DEFAULT_HD_PATH).unwrap();
+        assert_eq!(
+            wallet.address(),
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert_eq!(
+            wallet_key_hex(&wallet),
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+        let second = wallet_from_mnemonic(phrase, "m/44'/60'/0'/0/1").unwrap();
+        assert_eq!(
+            second.address(),
+            "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+                .parse::<Address>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_wallet_from_mnemonic_errors() {
+        match wallet_from_mnemonic("test test junk", DEFAULT_HD_PATH) {
+            Err(ConversationError::InvalidMnemonic { word_count, .. }) => {
+                assert_eq!(word_count, 3)
+            }
+            other => panic!("short phrase should not pass: {other:?}"),
+        }
+        let phrase = "test test test test test test test test test test test junk";
+        assert!(wallet_from_mnemonic(phrase, "not a path").is_err());
+    }
+
+    #[test]
+    fn test_record_verify_sender_reports_tampering() {
+        let wallet =
+            parse_wallet("0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80")
+                .unwrap();
+        let topic = ConversationId::from("test").as_topic();
+        let envelope = signed::seal(&wallet, topic, b"hello", 1).unwrap();
+        let data = ethabi::encode(&[Token::Bytes(envelope.to_vec()), Token::Uint(U256::zero())]);
+        let mut record = decode_record(&payload_log(data), None).unwrap();
+        assert_eq!(record.message, "hello");
+        assert_eq!(record.verify_sender(), Some(wallet.address()));
+
+        record.message = String::from("goodbye");
+        assert!(record.is_signed());
+        assert_eq!(record.verify_sender(), None);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_decode_record_encrypted_payload() {
+        let encryptor = AesGcmEncryptor::new(&[1; 32]);
+        let sealed = encryption::seal(&encryptor, b"hello").unwrap();
+        let data = ethabi::encode(&[Token::Bytes(sealed.to_vec()), Token::Uint(U256::zero())]);
+        let log = payload_log(data);
+
+        let record = decode_record(&log, Some(&encryptor)).unwrap();
+        assert_eq!(record.message, "hello");
+        assert!(!record.is_undecryptable());
+
+        // a wrong or missing key flags the record instead of failing the decode
+        for encryptor in [Some(AesGcmEncryptor::new(&[2; 32])), None] {
+            let record = decode_record(&log, encryptor.as_ref().map(|e| e as _)).unwrap();
+            assert!(record.is_undecryptable());
+            assert_eq!(record.message, "");
+            assert_eq!(record.undecoded, Some(log.data.clone()));
+        }
+    }
+
+    #[cfg(all(feature = "compression", feature = "encryption"))]
+    #[test]
+    fn test_seal_payload_compresses_before_encrypting() {
+        let encryptor: Arc<dyn Encryptor> = Arc::new(AesGcmEncryptor::new(&[1; 32]));
+        let message = Bytes::from("hello ".repeat(100).into_bytes());
+        let encrypted = MessageSenderConfig::default().with_encryptor(encryptor.clone());
+        let compressed = encrypted.clone().with_compression(9);
+        let encrypted = encrypted.seal_payload(message.clone()).unwrap();
+        let sealed = compressed.seal_payload(message.clone()).unwrap();
+        assert!(sealed.len() < encrypted.len() / 2);
+
+        let data = ethabi::encode(&[Token::Bytes(sealed.to_vec()), Token::Uint(U256::zero())]);
+        let record = decode_record(&payload_log(data), Some(encryptor.as_ref())).unwrap();
+        assert_eq!(record.message.as_bytes(), message.as_ref());
+    }
+
+    #[test]
+    fn test_replay_messages_skips_empty_and_keeps_order() {
+        let messages = vec![
+            String::from("first"),
+            String::new(),
+            String::from("second"),
+            String::from("third"),
         ];
-        assert_eq!(conversation_id, expected);
+        assert_eq!(replay_messages(messages), vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn test_is_address() {
+        let address = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+            .parse::<Address>()
+            .unwrap();
+        assert!(is_address(
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+            address
+        ));
+        assert!(is_address(
+            "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+            address
+        ));
+        assert!(!is_address(
+            "0x6CEb0bF1f28ca4165d5C0A04f61DC733987eD6ad",
+            address
+        ));
+        assert!(!is_address("not an address", address));
+    }
+
+    #[test]
+    fn test_parse_wallet_truncated_key() {
+        let key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff";
+        match parse_wallet(key) {
+            Err(ConversationError::InvalidPrivateKey { key_length, .. }) => {
+                assert_eq!(key_length, 64)
+            }
+            other => panic!("truncated key should not parse: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_confirmation_timeout() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let tx_hash = H256::repeat_byte(0x0c);
+        let stuck = runtime.block_on(within_confirmation_timeout(
+            Some(Duration::from_millis(10)),
+            tx_hash,
+            std::future::pending::<()>(),
+        ));
+        match stuck {
+            Err(ConversationError::ConfirmationTimeout { tx_hash: timed_out }) => {
+                assert_eq!(timed_out, tx_hash)
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        let mined = runtime.block_on(within_confirmation_timeout(
+            Some(Duration::from_secs(1)),
+            tx_hash,
+            async { 42 },
+        ));
+        assert_eq!(mined.unwrap(), 42);
+        let unbounded = runtime.block_on(within_confirmation_timeout(None, tx_hash, async { 7 }));
+        assert_eq!(unbounded.unwrap(), 7);
+    }
+
+    #[test]
+    fn test_bump_fee() {
+        assert_eq!(bump_fee(U256::from(1_000), 12), U256::from(1_120));
+        assert_eq!(bump_fee(U256::zero(), 12), U256::one());
+    }
+
+    #[test]
+    fn test_replacement_transaction_keeps_nonce() {
+        let tx = Transaction {
+            from: Address::repeat_byte(1),
+            to: Some(Address::repeat_byte(2)),
+            nonce: U256::from(9),
+            gas: U256::from(GAS_LIMIT),
+            input: Bytes::from(vec![0xab]),
+            max_fee_per_gas: Some(U256::from(100)),
+            max_priority_fee_per_gas: Some(U256::from(10)),
+            ..Default::default()
+        };
+        match replacement_transaction(&tx, 20) {
+            TypedTransaction::Eip1559(replacement) => {
+                assert_eq!(replacement.nonce, Some(U256::from(9)));
+                assert_eq!(replacement.to, Some(Address::repeat_byte(2).into()));
+                assert_eq!(replacement.data, Some(Bytes::from(vec![0xab])));
+                assert_eq!(replacement.max_fee_per_gas, Some(U256::from(120)));
+                assert_eq!(replacement.max_priority_fee_per_gas, Some(U256::from(12)));
+            }
+            other => panic!("unexpected transaction type: {other:?}"),
+        }
+        let legacy = Transaction {
+            gas_price: Some(U256::from(50)),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            ..tx
+        };
+        match replacement_transaction(&legacy, 20) {
+            TypedTransaction::Legacy(replacement) => {
+                assert_eq!(replacement.nonce, Some(U256::from(9)));
+                assert_eq!(replacement.gas_price, Some(U256::from(60)));
+            }
+            other => panic!("unexpected transaction type: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_near_gas_limit() {
+        assert!(near_gas_limit(Some(U256::from(240_000)), GAS_LIMIT));
+        assert!(!near_gas_limit(Some(U256::from(60_000)), GAS_LIMIT));
+        assert!(!near_gas_limit(None, GAS_LIMIT));
+    }
+
+    #[test]
+    fn test_message_receipt_cost() {
+        let receipt = MessageReceipt {
+            transaction_hash: H256::zero(),
+            block_number: Some(U64::from(1)),
+            gas_used: Some(U256::from(21_000)),
+            effective_gas_price: Some(U256::from(2_000_000_000u64)),
+        };
+        assert_eq!(receipt.cost(), Some(U256::from(42_000_000_000_000u64)));
+        let pending = MessageReceipt {
+            gas_used: None,
+            ..receipt
+        };
+        assert_eq!(pending.cost(), None);
+    }
+
+    #[test]
+    fn test_gas_price_suggestion() {
+        let block: Block<H256> = Block {
+            base_fee_per_gas: Some(U256::from(8_000_000_000u64)),
+            ..Default::default()
+        };
+        let suggestion =
+            GasPriceSuggestion::from_block(&block, U256::from(PRIORITY_FEE_TIP)).unwrap();
+        assert_eq!(suggestion.base_fee, U256::from(9_000_000_000u64));
+        assert_eq!(suggestion.max_priority_fee, U256::from(1_500_000_000u64));
+        assert_eq!(suggestion.max_fee, U256::from(10_500_000_000u64));
+    }
+
+    #[test]
+    fn test_gas_price_suggestion_without_base_fee() {
+        let block: Block<H256> = Block::default();
+        assert!(GasPriceSuggestion::from_block(&block, U256::zero()).is_err());
+    }
+
+    #[test]
+    fn test_ordered_receipt() {
+        let tx_hash = H256::repeat_byte(7);
+        assert!(ordered_receipt(tx_hash, None).is_err());
+        let reverted = TransactionReceipt {
+            transaction_hash: tx_hash,
+            status: Some(U64::zero()),
+            ..Default::default()
+        };
+        assert!(ordered_receipt(tx_hash, Some(reverted)).is_err());
+        let mined = TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(U64::from(9)),
+            status: Some(U64::one()),
+            ..Default::default()
+        };
+        let receipt = ordered_receipt(tx_hash, Some(mined)).unwrap();
+        assert_eq!(receipt.transaction_hash, tx_hash);
+        assert_eq!(receipt.block_number, Some(U64::from(9)));
+    }
+
+    #[test]
+    fn test_log_chunks() {
+        let chunks = log_chunks(U64::from(100), U64::from(4_500), 2_000);
+        assert_eq!(
+            chunks,
+            vec![
+                (U64::from(100), U64::from(2_099)),
+                (U64::from(2_100), U64::from(4_099)),
+                (U64::from(4_100), U64::from(4_500)),
+            ]
+        );
+        assert_eq!(
+            log_chunks(U64::from(7), U64::from(7), 2_000),
+            vec![(U64::from(7), U64::from(7))]
+        );
+        assert!(log_chunks(U64::from(8), U64::from(7), 2_000).is_empty());
+    }
+
+    #[test]
+    fn test_is_log_limit_error() {
+        for message in [
+            "(code: -32000, message: exceed maximum block range: 2000, data: None)",
+            "(code: -32005, message: query returned more than 10000 results, data: None)",
+            "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range",
+            "eth_getLogs is limited to a 10,000 range: block range too large",
+        ] {
+            assert!(is_log_limit_error(message), "{message}");
+        }
+        assert!(!is_log_limit_error("connection refused"));
+        assert!(!is_log_limit_error("execution reverted"));
+    }
+
+    #[test]
+    fn test_chronological_orders_by_block_and_log_index() {
+        let log = |block: u64, index: u64| {
+            (
+                Log {
+                    block_number: Some(U64::from(block)),
+                    log_index: Some(U256::from(index)),
+                    ..Default::default()
+                },
+                format!("{block}:{index}"),
+            )
+        };
+        // as walked: newest block first, each block's logs newest first
+        let walked = vec![log(12, 7), log(12, 3), log(10, 9), log(10, 1), log(11, 0)];
+        let messages: Vec<String> = chronological(walked)
+            .into_iter()
+            .map(|(_, message)| message)
+            .collect();
+        assert_eq!(messages, ["10:1", "10:9", "11:0", "12:3", "12:7"]);
+    }
+
+    #[test]
+    fn test_rewind_windows() {
+        let windows = rewind_windows(U256::from(1000), 100, 3);
+        assert_eq!(
+            windows,
+            vec![
+                (U256::from(901), U256::from(1000)),
+                (U256::from(801), U256::from(900)),
+                (U256::from(701), U256::from(800)),
+            ]
+        );
+        assert_eq!(
+            rewind_windows(U256::from(42), 1, 1),
+            vec![(U256::from(42), U256::from(42))]
+        );
+    }
+
+    #[test]
+    fn test_rewind_queries() {
+        let blocks: Vec<U256> = [1000, 990, 950, 700, 650]
+            .into_iter()
+            .map(U256::from)
+            .collect();
+        // 1000 alone, then 691-990 in three windows, then 351-650 in three more
+        assert_eq!(rewind_queries(&blocks, 100, 3), 7);
+        assert_eq!(rewind_queries(&blocks[..1], 100, 3), 1);
+        assert_eq!(rewind_queries(&[], 100, 3), 0);
+    }
+
+    #[test]
+    fn test_rewind_windows_stop_at_genesis() {
+        let windows = rewind_windows(U256::from(150), 100, 4);
+        assert_eq!(
+            windows,
+            vec![
+                (U256::from(51), U256::from(150)),
+                (U256::from(1), U256::from(50)),
+            ]
+        );
+        assert!(rewind_windows(U256::zero(), 100, 4).is_empty());
+    }
+
+    #[test]
+    fn test_block_filter_single_block() {
+        let conversation_id = ConversationId::from("test");
+        let contract_addr = SENDER_CONTRACT.parse::<Address>().unwrap();
+        let filter = block_filter(
+            contract_addr,
+            PayloadSentFilter::signature(),
+            conversation_id,
+            U256::from(42),
+        );
+        assert_eq!(filter.get_from_block(), Some(U64::from(42)));
+        assert_eq!(filter.get_to_block(), Some(U64::from(42)));
+        assert_eq!(
+            filter.topics[1],
+            Some(vec![conversation_id.as_topic()].into())
+        );
+    }
+
+    #[test]
+    fn test_payload_filter_has_no_block_range() {
+        let contract_addr = SENDER_CONTRACT.parse::<Address>().unwrap();
+        let topic = H256::repeat_byte(3);
+        let filter = payload_filter(contract_addr, PayloadSentFilter::signature(), vec![topic]);
+        assert_eq!(filter.get_from_block(), None);
+        assert_eq!(filter.get_to_block(), None);
+        assert_eq!(
+            filter.topics[0],
+            Some(PayloadSentFilter::signature().into())
+        );
+        assert_eq!(filter.topics[1], Some(vec![topic].into()));
+    }
+
+    fn log_at(block: u64, index: u64) -> Log {
+        Log {
+            block_number: Some(U64::from(block)),
+            log_index: Some(U256::from(index)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_after_without_backfill() {
+        assert!(is_after(&log_at(10, 0), &None));
+    }
+
+    #[test]
+    fn test_previous_message_block_leaves_through_oldest_log() {
+        let log = |block, index, previous: u64| Log {
+            data: ethabi::encode(&[Token::Bytes(Vec::new()), Token::Uint(U256::from(previous))])
+                .into(),
+            ..log_at(block, index)
+        };
+        // the second message of block 7 points back at block 7 itself
+        let logs = [log(7, 0, 3), log(7, 1, 7)];
+        assert_eq!(previous_message_block(&logs).unwrap(), U256::from(3));
+        assert_eq!(
+            previous_message_block(&[log(3, 0, 0)]).unwrap(),
+            U256::zero()
+        );
+        assert!(previous_message_block(&[log_at(7, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_is_after_drops_seam_duplicates() {
+        let last_seen = log_position(&log_at(10, 2));
+        assert!(!is_after(&log_at(9, 5), &last_seen));
+        assert!(!is_after(&log_at(10, 1), &last_seen));
+        assert!(!is_after(&log_at(10, 2), &last_seen));
+        assert!(is_after(&log_at(10, 3), &last_seen));
+        assert!(is_after(&log_at(11, 0), &last_seen));
+    }
+
+    #[test]
+    fn test_any_conversation_message() {
+        let data = ethabi::encode(&[
+            Token::String(String::from("hello")),
+            Token::Uint(U256::zero()),
+        ]);
+        let log = payload_log(data);
+        assert_eq!(
+            any_conversation_message(&log, None),
+            Some((
+                String::from("0x36f028580bb02cc8272a9a020f4200e346e276ae664e45ee80745574e2f5ab80"),
+                String::from("hello")
+            ))
+        );
+        let mut log = payload_log(Vec::new());
+        assert_eq!(any_conversation_message(&log, None), None);
+        log.topics.truncate(1);
+        assert_eq!(any_conversation_message(&log, None), None);
+    }
+
+    #[test]
+    fn test_conversations_filter() {
+        let topics = conversation_topics(&[String::from("alpha")], IdScheme::default()).unwrap();
+        let contract_addr = SENDER_CONTRACT.parse::<Address>().unwrap();
+        let filter = conversations_filter(contract_addr, PayloadSentFilter::signature(), &topics);
+        let topic = H256::from(to_conversation_id(&String::from("alpha")).unwrap());
+        assert_eq!(filter.topics[1], Some(vec![topic].into()));
+        assert_eq!(filter.get_from_block(), None);
+        assert_eq!(filter.get_to_block(), None);
+    }
+
+    #[test]
+    fn test_log_conversation_routes_by_topic() {
+        let conversations = vec![String::from("alpha"), String::from("beta")];
+        let topics = conversation_topics(&conversations, IdScheme::default()).unwrap();
+        for conversation in conversations.iter() {
+            let topic = H256::from(to_conversation_id(conversation).unwrap());
+            let log = Log {
+                topics: vec![H256::zero(), topic],
+                ..Default::default()
+            };
+            assert_eq!(log_conversation(&log, &topics), *conversation);
+        }
+    }
+
+    #[test]
+    fn test_log_conversation_unknown_topic() {
+        let topics = conversation_topics(&[String::from("alpha")], IdScheme::default()).unwrap();
+        let topic = H256::repeat_byte(0xab);
+        let log = Log {
+            topics: vec![H256::zero(), topic],
+            ..Default::default()
+        };
+        assert_eq!(log_conversation(&log, &topics), hex::encode(topic));
+    }
+
+    #[test]
+    fn test_is_behind() {
+        let synced_block = U64::from(100);
+        assert!(!is_behind(&[], synced_block));
+        assert!(!is_behind(&[U256::zero(), U256::from(100)], synced_block));
+        assert!(is_behind(&[U256::from(90), U256::from(101)], synced_block));
+    }
+
+    #[test]
+    fn test_crossed_threshold() {
+        assert!(crossed_threshold(90, 100, 100));
+        assert!(crossed_threshold(90, 250, 100));
+        assert!(!crossed_threshold(100, 199, 100));
+        assert!(!crossed_threshold(0, 0, 100));
+        assert!(crossed_threshold(0, 1, 1));
+    }
+
+    #[test]
+    fn test_subscription_error() {
+        let err = subscription_error("method not found");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ConversationError::Subscription { reason }) if reason == "method not found"
+        ));
+    }
+
+    #[test]
+    fn test_is_removed() {
+        assert!(!is_removed(&Log::default()));
+        let mut log = log_at(10, 0);
+        log.removed = Some(false);
+        assert!(!is_removed(&log));
+        log.removed = Some(true);
+        assert!(is_removed(&log));
+    }
+
+    #[test]
+    fn test_is_indexed() {
+        let rewind = |message: &[&str], last_change: u64| MessageRewind {
+            message: message.iter().map(|m| m.to_string()).collect(),
+            last_change: U256::from(last_change),
+        };
+        let block = Some(U64::from(100));
+        assert!(!is_indexed(&rewind(&[], 0), "hello", block));
+        assert!(!is_indexed(&rewind(&["older"], 99), "hello", block));
+        assert!(is_indexed(&rewind(&["hello"], 100), "hello", block));
+        // a later message means ours was indexed too
+        assert!(is_indexed(&rewind(&["newer"], 101), "hello", block));
+        assert!(is_indexed(&rewind(&["hello"], 100), "hello", None));
+        assert!(!is_indexed(&rewind(&["other"], 100), "hello", None));
+    }
+
+    #[test]
+    fn test_new_message_once() {
+        let conversation = String::from("test");
+        let topics =
+            conversation_topics(std::slice::from_ref(&conversation), IdScheme::default()).unwrap();
+        let log = Log {
+            topics: vec![
+                PayloadSentFilter::signature(),
+                H256::from(to_conversation_id(&conversation).unwrap()),
+            ],
+            data: ethabi::encode(&[
+                Token::String(String::from("hello")),
+                Token::Uint(U256::from(7)),
+            ])
+            .into(),
+            block_number: Some(U64::from(123)),
+            transaction_hash: Some(H256::repeat_byte(1)),
+            log_index: Some(U256::from(3)),
+            ..Default::default()
+        };
+        let mut recent = RecentLogs::new(DEDUP_WINDOW);
+        let mut sampler = LogSampler::new("follow", 1, None);
+        let delivered = new_message(
+            &log,
+            &topics,
+            &mut recent,
+            &mut sampler,
+            &DecodeErrors::new(DecodePolicy::Fail),
+            None,
+            1,
+        )
+        .unwrap();
+        assert!(new_message(
+            &log,
+            &topics,
+            &mut recent,
+            &mut sampler,
+            &DecodeErrors::new(DecodePolicy::Fail),
+            None,
+            1
+        )
+        .unwrap()
+        .is_none());
+
+        let (delivered_conversation, record) = delivered.unwrap();
+        assert_eq!(delivered_conversation, conversation);
+        assert_eq!(record.message, "hello");
+        assert_eq!(record.transaction_hash, log.transaction_hash);
+        assert_eq!(record.log_index, log.log_index);
+        assert_eq!(record.block_number, Some(U64::from(123)));
+        assert_eq!(record.conversation_topic, log.topics.get(1).copied());
+    }
+
+    /// A PayloadSent log whose data is cut off inside the payload.
+    fn truncated_log() -> (HashMap<H256, String>, Log) {
+        let conversation = String::from("test");
+        let topics =
+            conversation_topics(std::slice::from_ref(&conversation), IdScheme::default()).unwrap();
+        let data = ethabi::encode(&[
+            Token::String(String::from("hello")),
+            Token::Uint(U256::from(7)),
+        ]);
+        let mut log = payload_log(data[..64].to_vec());
+        log.transaction_hash = Some(H256::repeat_byte(5));
+        log.log_index = Some(U256::zero());
+        (topics, log)
+    }
+
+    #[test]
+    fn test_new_message_decode_policy_fail() {
+        let (topics, log) = truncated_log();
+        let mut recent = RecentLogs::new(DEDUP_WINDOW);
+        let mut sampler = LogSampler::new("follow", 1, None);
+        assert!(new_message(
+            &log,
+            &topics,
+            &mut recent,
+            &mut sampler,
+            &DecodeErrors::new(DecodePolicy::Fail),
+            None,
+            1
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_new_message_decode_policy_skip() {
+        let (topics, log) = truncated_log();
+        let mut recent = RecentLogs::new(DEDUP_WINDOW);
+        let mut sampler = LogSampler::new("follow", 1, None);
+        let decode_errors = DecodeErrors::new(DecodePolicy::Skip);
+        let message = new_message(
+            &log,
+            &topics,
+            &mut recent,
+            &mut sampler,
+            &decode_errors,
+            None,
+            1,
+        )
+        .unwrap();
+        assert!(message.is_none());
+        assert_eq!(decode_errors.skipped(), 1);
+    }
+
+    #[test]
+    fn test_new_message_decode_policy_deliver() {
+        let (topics, log) = truncated_log();
+        let mut recent = RecentLogs::new(DEDUP_WINDOW);
+        let mut sampler = LogSampler::new("follow", 1, None);
+        let (conversation, record) = new_message(
+            &log,
+            &topics,
+            &mut recent,
+            &mut sampler,
+            &DecodeErrors::new(DecodePolicy::Deliver),
+            None,
+            1,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(conversation, "test");
+        assert!(record.message.is_empty());
+        assert_eq!(record.undecoded, Some(log.data.clone()));
+        assert_eq!(record.transaction_hash, log.transaction_hash);
+    }
+
+    #[test]
+    fn test_raw_last_message() {
+        let data = ethabi::encode(&[
+            Token::String(String::from("hello")),
+            Token::Uint(U256::from(1234)),
+        ]);
+        assert_eq!(raw_last_message(&data), Some(U256::from(1234)));
+        // a truncated payload still carries the back pointer
+        assert_eq!(raw_last_message(&data[..64]), Some(U256::from(1234)));
+        assert!(decode_payload_sent(&payload_log(data[..64].to_vec())).is_err());
+        assert_eq!(raw_last_message(&data[..40]), None);
+    }
+
+    #[test]
+    fn test_group_conversation_id_order_independent() {
+        let (a, b, c) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        );
+        assert_eq!(
+            group_conversation_id(vec![a, b, c]),
+            group_conversation_id(vec![c, a, b])
+        );
+    }
+
+    #[test]
+    fn test_group_conversation_id_membership_changes_id() {
+        let (a, b, c) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        );
+        assert_ne!(
+            group_conversation_id(vec![a, b]),
+            group_conversation_id(vec![a, b, c])
+        );
+    }
+
+    #[test]
+    fn test_group_conversation_id_self_pair() {
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        assert_eq!(
+            group_conversation_id(vec![a]),
+            group_conversation_id(vec![a, a])
+        );
+        assert_ne!(
+            group_conversation_id(vec![a]),
+            group_conversation_id(vec![a, b])
+        );
+    }
+
+    /// A PayloadSent log for the "test" conversation with the given data.
+    fn payload_log(data: Vec<u8>) -> Log {
+        Log {
+            topics: vec![
+                PayloadSentFilter::signature(),
+                H256::from(to_conversation_id(&String::from("test")).unwrap()),
+            ],
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_payload_sent_event_matches_abi() {
+        assert_eq!(PayloadSentFilter::abi_signature(), PAYLOAD_SENT_EVENT);
+        assert_eq!(
+            PayloadSentFilter::signature(),
+            H256::from(keccak256(PAYLOAD_SENT_EVENT))
+        );
+    }
+
+    #[test]
+    fn test_version_call_matches_abi() {
+        use ethers::contract::EthCall;
+        assert_eq!(VersionCall::abi_signature(), "version()");
+        assert_eq!(VersionCall::selector(), keccak256("version()")[..4]);
+    }
+
+    #[test]
+    fn test_decode_payload_sent() {
+        let data = ethabi::encode(&[
+            Token::String(String::from("hello")),
+            Token::Uint(U256::from(1234)),
+        ]);
+        let payload = decode_payload_sent(&payload_log(data)).unwrap();
+        assert_eq!(payload.message, Bytes::from(b"hello".to_vec()));
+        assert_eq!(payload.last_change, U256::from(1234));
+        assert_eq!(payload.message_utf8(), Some(String::from("hello")));
+    }
+
+    #[test]
+    fn test_event_topic() {
+        let config = MessageSenderConfig::default();
+        assert_eq!(config.event_topic(), PayloadSentFilter::signature());
+        let config = MessageSenderConfig {
+            event_signature: None,
+            ..Default::default()
+        };
+        assert_eq!(config.event_topic(), PayloadSentFilter::signature());
+        let config = MessageSenderConfig {
+            event_signature: Some("MessageSent(bytes32,uint256,bytes)"),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.event_topic(),
+            H256::from(keccak256("MessageSent(bytes32,uint256,bytes)"))
+        );
+    }
+
+    #[test]
+    fn test_normalize_custom_event_log() {
+        let config = MessageSenderConfig {
+            event_signature: Some("MessageSent(bytes32,uint256,bytes)"),
+            param_types: Some(vec![ParamType::Uint(256), ParamType::Bytes]),
+            payload_param: 1,
+            last_message_param: Some(0),
+            ..Default::default()
+        };
+        let mut log = payload_log(ethabi::encode(&[
+            Token::Uint(U256::from(1234)),
+            Token::Bytes(b"hello".to_vec()),
+        ]));
+        log.topics[0] = config.event_topic();
+        let payload = decode_payload_sent(&config.normalize_log(log.clone())).unwrap();
+        assert_eq!(payload.message, Bytes::from(b"hello".to_vec()));
+        assert_eq!(payload.last_change, U256::from(1234));
+
+        // data not laid out as param_types is left to fail decoding
+        log.data = Bytes::from(vec![1u8; 10]);
+        assert_eq!(config.normalize_log(log.clone()), log);
+        // as are logs of other events
+        let other = payload_log(ethabi::encode(&[
+            Token::Bytes(b"hello".to_vec()),
+            Token::Uint(U256::zero()),
+        ]));
+        assert_eq!(config.normalize_log(other.clone()), other);
+        assert_eq!(
+            MessageSenderConfig::default().normalize_log(other.clone()),
+            other
+        );
+    }
+
+    #[test]
+    fn test_normalize_event_params_by_index() {
+        // a timestamp comes before the back pointer, and an extra bytes after the payload
+        let config = MessageSenderConfig {
+            event_signature: Some("Posted(bytes32,uint64,bytes,uint256,bytes)"),
+            param_types: Some(vec![
+                ParamType::Uint(64),
+                ParamType::Bytes,
+                ParamType::Uint(256),
+                ParamType::Bytes,
+            ]),
+            payload_param: 1,
+            last_message_param: Some(2),
+            ..Default::default()
+        };
+        config.check_event_params().unwrap();
+        let mut log = payload_log(ethabi::encode(&[
+            Token::Uint(U256::from(1_700_000_000)),
+            Token::Bytes(b"hello".to_vec()),
+            Token::Uint(U256::from(1234)),
+            Token::Bytes(b"extra".to_vec()),
+        ]));
+        log.topics[0] = config.event_topic();
+        let payload = decode_payload_sent(&config.normalize_log(log.clone())).unwrap();
+        assert_eq!(payload.message, Bytes::from(b"hello".to_vec()));
+        assert_eq!(payload.last_change, U256::from(1234));
+
+        // without a back pointer each message ends the rewind
+        let config = MessageSenderConfig {
+            last_message_param: None,
+            ..config
+        };
+        let payload = decode_payload_sent(&config.normalize_log(log)).unwrap();
+        assert_eq!(payload.last_change, U256::zero());
+
+        for (payload_param, last_message_param) in [(0, Some(2)), (1, Some(3)), (4, None)] {
+            let config = MessageSenderConfig {
+                payload_param,
+                last_message_param,
+                ..config.clone()
+            };
+            assert!(config.check_event_params().is_err());
+        }
+        // the PayloadSent layout needs no parameters
+        MessageSenderConfig::default().check_event_params().unwrap();
+    }
+
+    #[test]
+    fn test_payload_sent_event_from_log() {
+        let data = ethabi::encode(&[
+            Token::Bytes(b"hello".to_vec()),
+            Token::Uint(U256::from(1234)),
+        ]);
+        let mut log = payload_log(data);
+        log.block_number = Some(U64::from(1300));
+        log.transaction_hash = Some(H256::repeat_byte(7));
+        let event = PayloadSentEvent::try_from(log.clone()).unwrap();
+        assert_eq!(H256::from(event.conversation_id), log.topics[1]);
+        assert_eq!(event.message, Bytes::from(b"hello".to_vec()));
+        assert_eq!(event.prev_change, U256::from(1234));
+        assert_eq!(event.block_number, 1300);
+        assert_eq!(event.tx_hash, H256::repeat_byte(7));
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PayloadSentEvent>(&json).unwrap(),
+            event
+        );
+        log.topics.truncate(1);
+        assert!(PayloadSentEvent::try_from(log).is_err());
+    }
+
+    #[test]
+    fn test_decode_payload_sent_short_data() {
+        assert!(decode_payload_sent(&payload_log(Vec::new())).is_err());
+        assert!(decode_payload_sent(&payload_log(vec![0u8; 40])).is_err());
+    }
+
+    #[test]
+    fn test_decode_payload_sent_swapped_types() {
+        let data = ethabi::encode(&[
+            Token::Uint(U256::from(1234)),
+            Token::String(String::from("hello")),
+        ]);
+        assert!(decode_payload_sent(&payload_log(data)).is_err());
+    }
+
+    #[test]
+    fn test_decode_payload_sent_wrong_event() {
+        let data = ethabi::encode(&[
+            Token::String(String::from("hello")),
+            Token::Uint(U256::from(1234)),
+        ]);
+        let mut log = payload_log(data);
+        log.topics[0] = H256::repeat_byte(2);
+        assert!(decode_payload_sent(&log).is_err());
+    }
+
+    #[test]
+    fn test_decode_text_payload_invalid_utf8() {
+        let data = ethabi::encode(&[Token::Bytes(vec![0xff, 0xfe]), Token::Uint(U256::zero())]);
+        let log = payload_log(data);
+        let payload = decode_payload_sent(&log).unwrap();
+        assert_eq!(payload.message_utf8(), None);
+        assert!(decode_text_payload(&log, None).is_err());
+    }
+
+    #[test]
+    fn test_decode_payload_sent_log_data() {
+        let data = ethabi::encode(&[
+            Token::String(String::from("hello")),
+            Token::Uint(U256::from(1234)),
+        ]);
+        assert_eq!(
+            decode_payload_sent_log(&data).unwrap(),
+            (String::from("hello"), U256::from(1234))
+        );
+        assert_eq!(
+            decode_payload_sent_raw_bytes(&data).unwrap(),
+            (Bytes::from(b"hello".to_vec()), U256::from(1234))
+        );
+
+        let typed = content::seal("text/markdown", b"*hello*").unwrap();
+        let data = ethabi::encode(&[Token::Bytes(typed.to_vec()), Token::Uint(U256::from(7))]);
+        assert_eq!(
+            decode_payload_sent_log(&data).unwrap(),
+            (String::from("*hello*"), U256::from(7))
+        );
+        assert_eq!(
+            decode_payload_sent_raw_bytes(&data).unwrap(),
+            (Bytes::from(b"*hello*".to_vec()), U256::from(7))
+        );
+    }
+
+    #[test]
+    fn test_decode_payload_sent_log_data_errors() {
+        assert!(matches!(
+            decode_payload_sent_raw_bytes(&[0u8; 40]),
+            Err(ConversationError::InvalidPayloadData {
+                data_length: 40,
+                ..
+            })
+        ));
+        let data = ethabi::encode(&[Token::Bytes(vec![0xff, 0xfe]), Token::Uint(U256::zero())]);
+        assert_eq!(
+            decode_payload_sent_raw_bytes(&data).unwrap().0,
+            Bytes::from(vec![0xff, 0xfe])
+        );
+        assert!(matches!(
+            decode_payload_sent_log(&data),
+            Err(ConversationError::PayloadNotUtf8 { .. })
+        ));
+    }
+
+    #[test]
+    fn test_find_payload_log() {
+        let contract_addr = SENDER_CONTRACT.parse::<Address>().unwrap();
+        let event_topic = PayloadSentFilter::signature();
+        let other_contract = Log {
+            address: Address::repeat_byte(1),
+            topics: vec![event_topic],
+            ..Default::default()
+        };
+        let other_event = Log {
+            address: contract_addr,
+            topics: vec![H256::repeat_byte(2)],
+            ..Default::default()
+        };
+        let payload = Log {
+            address: contract_addr,
+            topics: vec![event_topic],
+            log_index: Some(U256::from(2)),
+            ..Default::default()
+        };
+        assert!(find_payload_log(
+            contract_addr,
+            event_topic,
+            &[other_contract.clone(), other_event.clone()]
+        )
+        .is_none());
+        let logs = [other_contract, other_event, payload];
+        assert_eq!(
+            find_payload_log(contract_addr, event_topic, &logs)
+                .unwrap()
+                .log_index,
+            Some(U256::from(2))
+        );
+    }
+
+    #[test]
+    fn test_is_after_pending_log() {
+        let last_seen = log_position(&log_at(10, 2));
+        assert!(is_after(&Log::default(), &last_seen));
     }
 }
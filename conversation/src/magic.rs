@@ -0,0 +1,83 @@
+use ethers::types::Bytes;
+
+// Every layer a payload can be wrapped in is marked by a two byte prefix starting
+// with 0xff.  0xff never occurs in UTF-8, so no text payload can be mistaken for a
+// wrapped one.  A payload that need not be text, such as a codec's output, is
+// escaped when it starts with 0xff, so it cannot be mistaken for one either.
+
+/// Prefix marking a compressed payload.
+pub(crate) const COMPRESSED_MAGIC: [u8; 2] = [0xff, 0x7a];
+/// Prefix marking a payload with a content type header.
+pub(crate) const CONTENT_TYPE_MAGIC: [u8; 2] = [0xff, 0x63];
+/// Prefix marking an encrypted payload.
+pub(crate) const ENCRYPTED_MAGIC: [u8; 2] = [0xff, 0x65];
+/// Prefix marking a signed payload.
+pub(crate) const SIGNED_MAGIC: [u8; 2] = [0xff, 0x73];
+/// Prefix marking a payload that is not text and itself starts with 0xff.
+pub(crate) const ESCAPED_MAGIC: [u8; 2] = [0xff, 0x00];
+
+/*
+ * Escape a payload that need not be text, so it cannot be read as a wrapped one.
+ * payload: the payload
+ * Returns the payload, behind ESCAPED_MAGIC if it starts with 0xff.
+ */
+pub(crate) fn escape(payload: Bytes) -> Bytes {
+    match payload.first() {
+        Some(0xff) => Bytes::from([&ESCAPED_MAGIC[..], &payload].concat()),
+        _ => payload,
+    }
+}
+
+/*
+ * Undo escape, passing any other payload through.
+ * payload: the payload once every layer is opened
+ * Returns the payload as it was before escaping.
+ */
+pub(crate) fn unescape(payload: Bytes) -> Bytes {
+    match payload.strip_prefix(&ESCAPED_MAGIC) {
+        Some(escaped) => Bytes::from(escaped.to_vec()),
+        None => payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefixes_are_distinct() {
+        let prefixes = [
+            COMPRESSED_MAGIC,
+            CONTENT_TYPE_MAGIC,
+            ENCRYPTED_MAGIC,
+            SIGNED_MAGIC,
+            ESCAPED_MAGIC,
+        ];
+        for (i, prefix) in prefixes.iter().enumerate() {
+            assert_eq!(prefix[0], 0xff);
+            assert!(!prefixes[i + 1..].contains(prefix));
+        }
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        for payload in [
+            vec![],
+            b"text".to_vec(),
+            vec![0x80, 0x00],
+            ENCRYPTED_MAGIC.to_vec(),
+            [&SIGNED_MAGIC[..], b"not signed"].concat(),
+            ESCAPED_MAGIC.to_vec(),
+        ] {
+            let escaped = escape(Bytes::from(payload.clone()));
+            assert_eq!(escaped != payload, payload.first() == Some(&0xff));
+            assert_eq!(unescape(escaped), payload);
+        }
+    }
+
+    #[test]
+    fn test_escape_leaves_text() {
+        let text = Bytes::from(b"hello".to_vec());
+        assert_eq!(escape(text.clone()), text);
+    }
+}
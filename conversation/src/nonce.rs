@@ -0,0 +1,160 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use ethers::{
+    middleware::nonce_manager::NonceManagerError,
+    providers::{Middleware, MiddlewareError, PendingTransaction},
+    types::{transaction::eip2718::TypedTransaction, Address, BlockId, BlockNumber, U256},
+};
+use tokio::sync::Mutex;
+
+/// Hands out consecutive nonces locally, as ethers' NonceManagerMiddleware does, so
+/// concurrent sends need not wait for each other to reach the mempool.  Unlike it,
+/// the counter can be resynced from the chain, so a nonce taken by a send that
+/// never reached the mempool is used again instead of leaving a gap that every
+/// later transaction waits behind.
+#[derive(Debug)]
+pub(crate) struct NonceManager<M> {
+    inner: M,
+    address: Address,
+    /// held while the counter is set from the chain
+    sync_guard: Mutex<()>,
+    initialized: AtomicBool,
+    nonce: AtomicU64,
+}
+
+impl<M: Middleware> NonceManager<M> {
+    /*
+     * Wrap a middleware sending from an address.
+     * inner: the middleware, which signs the transactions
+     * address: the account the transactions are sent from
+     */
+    pub(crate) fn new(inner: M, address: Address) -> NonceManager<M> {
+        NonceManager {
+            inner,
+            address,
+            sync_guard: Mutex::new(()),
+            initialized: AtomicBool::new(false),
+            nonce: AtomicU64::new(0),
+        }
+    }
+
+    /*
+     * Take the next nonce, advancing the counter.
+     * Returns the nonce.
+     */
+    pub(crate) fn next(&self) -> U256 {
+        U256::from(self.nonce.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /*
+     * Read the account's nonce from the chain the first time it is needed.
+     * Returns Ok(U256) the next nonce, without taking it.
+     */
+    pub(crate) async fn initialize_nonce(&self) -> Result<U256, NonceManagerError<M>> {
+        if self.initialized.load(Ordering::SeqCst) {
+            return Ok(U256::from(self.nonce.load(Ordering::SeqCst)));
+        }
+        let _guard = self.sync_guard.lock().await;
+        // another task may have initialized it while this one waited
+        if self.initialized.load(Ordering::SeqCst) {
+            return Ok(U256::from(self.nonce.load(Ordering::SeqCst)));
+        }
+        self.store_pending_count().await
+    }
+
+    /*
+     * Reset the counter to the account's pending transaction count, after a send
+     * that took a nonce failed to reach the mempool.
+     * Returns Ok(U256) the next nonce, without taking it.
+     */
+    pub(crate) async fn resync(&self) -> Result<U256, NonceManagerError<M>> {
+        let _guard = self.sync_guard.lock().await;
+        let nonce = self.store_pending_count().await?;
+        tracing::debug!("nonce resynced to {nonce}");
+        Ok(nonce)
+    }
+
+    /*
+     * Set the counter to the account's pending transaction count.  The caller holds
+     * sync_guard.
+     * Returns Ok(U256) the count.
+     */
+    async fn store_pending_count(&self) -> Result<U256, NonceManagerError<M>> {
+        let nonce = self
+            .inner
+            .get_transaction_count(self.address, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(MiddlewareError::from_err)?;
+        self.nonce.store(nonce.as_u64(), Ordering::SeqCst);
+        self.initialized.store(true, Ordering::SeqCst);
+        Ok(nonce)
+    }
+
+    /*
+     * Take the next nonce, reading it from the chain the first time.
+     * Returns Ok(U256) the nonce.
+     */
+    async fn take(&self) -> Result<U256, NonceManagerError<M>> {
+        self.initialize_nonce().await?;
+        Ok(self.next())
+    }
+}
+
+#[async_trait]
+impl<M: Middleware> Middleware for NonceManager<M> {
+    type Error = NonceManagerError<M>;
+    type Provider = M::Provider;
+    type Inner = M;
+
+    fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    async fn fill_transaction(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<(), Self::Error> {
+        if tx.nonce().is_none() {
+            tx.set_nonce(self.take().await?);
+        }
+        self.inner
+            .fill_transaction(tx, block)
+            .await
+            .map_err(MiddlewareError::from_err)
+    }
+
+    /// Send a transaction, taking the next nonce if it has none.  If a send with a
+    /// taken nonce fails the counter is resynced, and the send is retried once with
+    /// a fresh nonce if the one taken was out of step with the chain.  Callers that
+    /// set the nonce themselves resync on failure.
+    async fn send_transaction<'a, T: Into<TypedTransaction> + Send + Sync>(
+        &'a self,
+        tx: T,
+        block: Option<BlockId>,
+    ) -> Result<PendingTransaction<'a, Self::Provider>, Self::Error> {
+        let mut tx = tx.into();
+        let taken = tx.nonce().is_none();
+        if taken {
+            tx.set_nonce(self.take().await?);
+        }
+        match self.inner.send_transaction(tx.clone(), block).await {
+            Ok(pending) => Ok(pending),
+            Err(err) if taken => {
+                let nonce = self.resync().await?;
+                if tx.nonce() == Some(&nonce) {
+                    // the nonce was right, so it is free for the next send
+                    return Err(MiddlewareError::from_err(err));
+                }
+                tracing::debug!("retrying send with nonce {nonce}: {err}");
+                tx.set_nonce(self.next());
+                self.inner
+                    .send_transaction(tx, block)
+                    .await
+                    .map_err(MiddlewareError::from_err)
+            }
+            Err(err) => Err(MiddlewareError::from_err(err)),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Error;
+use ethers::types::{U256, U64};
+
+use crate::{MessageCallback, MessageRewind, MessageRewindOrder, MessageSender, PayloadSentEvent};
+
+/// A read-only handle on the XPS Sender contract, forked from a MessageSender with
+/// clone_readonly.  It shares the sender's provider connection and exposes only the
+/// methods that read the chain.
+#[derive(Clone)]
+pub struct MessageSenderReadOnly {
+    sender: MessageSender,
+}
+
+impl MessageSenderReadOnly {
+    pub(crate) fn new(sender: MessageSender) -> MessageSenderReadOnly {
+        MessageSenderReadOnly { sender }
+    }
+
+    /**
+     * Rewind the conversation to the last n messages.
+     * See MessageSender::rewind.
+     */
+    pub async fn rewind(&self, conversation: &str, n: u32) -> Result<MessageRewind, Error> {
+        self.sender.rewind(conversation, n).await
+    }
+
+    /**
+     * Rewind the conversation to the last n messages in the given order.
+     * See MessageSender::rewind_ordered.
+     */
+    pub async fn rewind_ordered(
+        &self,
+        conversation: &str,
+        n: u32,
+        order: MessageRewindOrder,
+    ) -> Result<MessageRewind, Error> {
+        self.sender.rewind_ordered(conversation, n, order).await
+    }
+
+    /**
+     * Follow the conversation and call the callback function for each new message.
+     * See MessageSender::follow_messages.
+     */
+    pub async fn follow_messages(
+        &self,
+        conversation: &String,
+        start_block: &U256,
+        callback: MessageCallback,
+    ) -> Result<(), Error> {
+        self.sender
+            .follow_messages(conversation, start_block, callback)
+            .await
+    }
+
+    /**
+     * Get the block of the last message sent to the conversation.
+     * See MessageSender::last_message_block.
+     */
+    pub async fn last_message_block(&self, conversation: &str) -> Result<U256, Error> {
+        self.sender.last_message_block(conversation).await
+    }
+
+    /**
+     * Check whether any message has been sent to the conversation.
+     * See MessageSender::conversation_exists.
+     */
+    pub async fn conversation_exists(&self, conversation: &str) -> Result<bool, Error> {
+        self.sender.conversation_exists(conversation).await
+    }
+
+    /**
+     * Get the version string reported by the XPS Sender contract.
+     * See MessageSender::get_contract_version.
+     */
+    pub async fn get_contract_version(&self) -> Result<String, Error> {
+        self.sender.get_contract_version().await
+    }
+
+    /**
+     * Fetch every PayloadSent event of a conversation in a block range.
+     * See MessageSender::get_events_in_range.
+     */
+    pub async fn get_events_in_range(
+        &self,
+        conversation: &str,
+        from: U64,
+        to: U64,
+    ) -> Result<Vec<PayloadSentEvent>, Error> {
+        self.sender
+            .get_events_in_range(conversation, from, to)
+            .await
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::Error;
+use ethers::{
+    abi::{self, ParamType},
+    contract::ContractError,
+    providers::{Middleware, MiddlewareError},
+    types::U256,
+};
+
+use crate::ConversationError;
+
+/// Selector of the Error(string) revert raised by require and revert.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of the Panic(uint256) revert raised by failed asserts and arithmetic.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+/// Prefix of the JSON-RPC error message nodes return for a revert.
+const EXECUTION_REVERTED: &str = "execution reverted";
+
+/*
+ * Turn a contract call error into a ConversationError::Reverted carrying the
+ * decoded reason if the call reverted, or pass it through unchanged otherwise.
+ * err: the contract error
+ * Returns the error to return to the caller.
+ */
+pub(crate) fn contract_error<M: Middleware + 'static>(err: ContractError<M>) -> Error {
+    match revert_reason(&err) {
+        Some(reason) => ConversationError::Reverted { reason }.into(),
+        None => err.into(),
+    }
+}
+
+/*
+ * Find the revert reason in a contract call error.  Nodes either return the revert
+ * data, which is decoded, or only an "execution reverted: reason" message.
+ * err: the contract error
+ * Returns Some(reason) if the call reverted.
+ */
+fn revert_reason<M: Middleware>(err: &ContractError<M>) -> Option<String> {
+    if let Some(data) = err.as_revert() {
+        return Some(decode_revert_data(data));
+    }
+    let response = err.as_middleware_error()?.as_error_response()?;
+    if let Some(data) = response.as_revert_data().filter(|data| !data.is_empty()) {
+        return Some(decode_revert_data(&data));
+    }
+    let reason = response.message.strip_prefix(EXECUTION_REVERTED)?;
+    match reason.trim_start_matches(':').trim() {
+        "" => Some(String::from("no reason given")),
+        reason => Some(reason.to_string()),
+    }
+}
+
+/*
+ * Decode revert data into a readable reason.
+ * data: the revert data returned by the node
+ * Returns the Error(string) message, a description of the Panic(uint256) code, or
+ * the raw data in hex for custom errors.
+ */
+pub(crate) fn decode_revert_data(data: &[u8]) -> String {
+    if data.is_empty() {
+        return String::from("no reason given");
+    }
+    let (selector, args) = data.split_at(data.len().min(4));
+    if selector == ERROR_SELECTOR {
+        if let Ok(tokens) = abi::decode(&[ParamType::String], args) {
+            if let Some(reason) = tokens.into_iter().next().and_then(|t| t.into_string()) {
+                return reason;
+            }
+        }
+    } else if selector == PANIC_SELECTOR {
+        if let Ok(tokens) = abi::decode(&[ParamType::Uint(256)], args) {
+            if let Some(code) = tokens.into_iter().next().and_then(|t| t.into_uint()) {
+                return format!("panic 0x{code:02x}: {}", panic_description(code));
+            }
+        }
+    }
+    format!("custom error 0x{}", hex::encode(data))
+}
+
+/*
+ * Describe a Solidity panic code.
+ * code: the Panic(uint256) argument
+ * Returns the description.
+ */
+fn panic_description(code: U256) -> &'static str {
+    if code > U256::from(u8::MAX) {
+        return "unknown panic";
+    }
+    match code.as_u32() {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array encoding",
+        0x31 => "pop on an empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to an uninitialized function",
+        _ => "unknown panic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{
+        abi::Token,
+        providers::{Http, HttpClientError, JsonRpcError, Provider, ProviderError},
+        types::Bytes,
+    };
+
+    #[test]
+    fn test_decode_error_string() {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(abi::encode(&[Token::String(String::from(
+            "conversation is closed",
+        ))]));
+        assert_eq!(decode_revert_data(&data), "conversation is closed");
+    }
+
+    #[test]
+    fn test_decode_panic_code() {
+        let mut data = PANIC_SELECTOR.to_vec();
+        data.extend(abi::encode(&[Token::Uint(U256::from(0x11))]));
+        assert_eq!(
+            decode_revert_data(&data),
+            "panic 0x11: arithmetic overflow or underflow"
+        );
+    }
+
+    #[test]
+    fn test_decode_custom_and_empty() {
+        assert_eq!(decode_revert_data(&[]), "no reason given");
+        assert_eq!(
+            decode_revert_data(&[0xde, 0xad, 0xbe, 0xef]),
+            "custom error 0xdeadbeef"
+        );
+    }
+
+    #[test]
+    fn test_contract_error_is_reverted() {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(abi::encode(&[Token::String(String::from("not allowed"))]));
+        let err = contract_error(ContractError::<Provider<Http>>::Revert(Bytes::from(data)));
+        match err.downcast_ref::<ConversationError>() {
+            Some(ConversationError::Reverted { reason }) => assert_eq!(reason, "not allowed"),
+            other => panic!("unexpected error: {other:?}"),
+        }
+        let response = JsonRpcError {
+            code: 3,
+            message: String::from("execution reverted: sender is not a member"),
+            data: None,
+        };
+        let err = contract_error(ContractError::<Provider<Http>>::MiddlewareError {
+            e: ProviderError::JsonRpcClientError(Box::new(HttpClientError::JsonRpcError(response))),
+        });
+        assert!(err.to_string().contains("sender is not a member"));
+        let err = contract_error(ContractError::<Provider<Http>>::ContractNotDeployed);
+        assert!(err.downcast_ref::<ConversationError>().is_none());
+    }
+}
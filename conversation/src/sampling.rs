@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+use ethers::types::U64;
+
+/// Thins the per-message trace events of a follow or rewind hot loop and
+/// periodically logs the messages handled since the last report, so a busy
+/// conversation can be traced without tracing every message.
+pub(crate) struct LogSampler {
+    operation: &'static str,
+    every: u64,
+    report_interval: Option<Duration>,
+    seen: u64,
+    since_report: u64,
+    last_block: Option<U64>,
+    last_report: Instant,
+}
+
+impl LogSampler {
+    /**
+     * Create a new LogSampler.
+     * operation: the loop being sampled, named in the progress report
+     * every: trace one message in every this many, zero or one traces them all
+     * report_interval: how often to log progress at info, None to not report
+     */
+    pub(crate) fn new(
+        operation: &'static str,
+        every: u64,
+        report_interval: Option<Duration>,
+    ) -> LogSampler {
+        LogSampler {
+            operation,
+            every: every.max(1),
+            report_interval,
+            seen: 0,
+            since_report: 0,
+            last_block: None,
+            last_report: Instant::now(),
+        }
+    }
+
+    /**
+     * Count a message, logging progress if the report interval has passed.
+     * block: the block of the message
+     * Returns true if the message should be traced.
+     */
+    pub(crate) fn sample(&mut self, block: Option<U64>) -> bool {
+        self.seen += 1;
+        self.since_report += 1;
+        if block.is_some() {
+            self.last_block = block;
+        }
+        if let Some(interval) = self.report_interval {
+            if self.last_report.elapsed() >= interval {
+                self.report();
+            }
+        }
+        (self.seen - 1).is_multiple_of(self.every)
+    }
+
+    /*
+     * Log the messages handled since the last report.
+     */
+    fn report(&mut self) {
+        match self.last_block {
+            Some(block) => tracing::info!(
+                "{} received {} messages, last block {block}",
+                self.operation,
+                self.since_report
+            ),
+            None => tracing::info!("{} received {} messages", self.operation, self.since_report),
+        }
+        self.since_report = 0;
+        self.last_report = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_every_nth() {
+        let mut sampler = LogSampler::new("follow", 3, None);
+        let sampled: Vec<bool> = (0..7).map(|_| sampler.sample(None)).collect();
+        assert_eq!(sampled, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn test_sample_all_by_default() {
+        for every in [0, 1] {
+            let mut sampler = LogSampler::new("rewind", every, None);
+            assert!((0..5).all(|_| sampler.sample(Some(U64::from(7)))));
+        }
+    }
+
+    #[test]
+    fn test_report_resets_count() {
+        let mut sampler = LogSampler::new("follow", 1, Some(Duration::ZERO));
+        sampler.sample(Some(U64::from(1234)));
+        assert_eq!(sampler.since_report, 0);
+        assert_eq!(sampler.last_block, Some(U64::from(1234)));
+        assert_eq!(sampler.seen, 1);
+    }
+}
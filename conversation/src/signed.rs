@@ -0,0 +1,193 @@
+use anyhow::Error;
+use ethabi::{ParamType, Token};
+use ethers::{
+    types::{Address, Bytes, RecoveryMessage, Signature, H256, U256},
+    utils::{hash_message, keccak256},
+};
+
+use crate::{magic::SIGNED_MAGIC, WalletType};
+
+/// The sender attribution carried by a payload sent with send_signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedEnvelope {
+    /// the address the sender claims to be
+    pub sender: Address,
+    /// a value unique to the sender's message, so a signature cannot be replayed
+    pub nonce: u64,
+    /// the EIP-191 signature over the conversation ID, payload and nonce
+    pub signature: Signature,
+}
+
+impl SignedEnvelope {
+    /**
+     * Recover the signer of a payload and check it is the claimed sender.
+     * conversation_id: the conversation the payload was sent to
+     * payload: the payload inside the envelope
+     * Returns Some(Address) the sender if the signature is theirs, None otherwise.
+     */
+    pub fn verify(&self, conversation_id: H256, payload: &[u8]) -> Option<Address> {
+        let digest = signing_digest(conversation_id, payload, self.nonce);
+        match self.signature.recover(RecoveryMessage::Hash(digest)) {
+            Ok(signer) if signer == self.sender => Some(signer),
+            Ok(signer) => {
+                tracing::warn!(
+                    "payload signed by {signer:?} claims sender {:?}",
+                    self.sender
+                );
+                None
+            }
+            Err(err) => {
+                tracing::warn!("payload signature does not recover: {err}");
+                None
+            }
+        }
+    }
+}
+
+/*
+ * Sign a payload and wrap it in a signed envelope.
+ * wallet: the sender's wallet
+ * conversation_id: the conversation the payload is sent to
+ * payload: the payload
+ * nonce: a value unique to this message
+ * Returns Ok(Bytes) the envelope to send.
+ */
+pub(crate) fn seal(
+    wallet: &WalletType,
+    conversation_id: H256,
+    payload: &[u8],
+    nonce: u64,
+) -> Result<Bytes, Error> {
+    let digest = signing_digest(conversation_id, payload, nonce);
+    let signature = wallet.sign_hash(digest)?;
+    let encoded = ethabi::encode(&[
+        Token::Address(ethers::signers::Signer::address(wallet)),
+        Token::Uint(U256::from(nonce)),
+        Token::Bytes(signature.to_vec()),
+        Token::Bytes(payload.to_vec()),
+    ]);
+    Ok(Bytes::from([&SIGNED_MAGIC[..], &encoded].concat()))
+}
+
+/*
+ * Unwrap a signed envelope, passing any other payload through.
+ * payload: the payload as sent
+ * Returns Ok((payload, envelope)) with the inner payload and its envelope, if any.
+ */
+pub(crate) fn open(payload: Bytes) -> Result<(Bytes, Option<SignedEnvelope>), Error> {
+    let encoded = match payload.strip_prefix(&SIGNED_MAGIC) {
+        Some(encoded) => encoded,
+        None => return Ok((payload, None)),
+    };
+    let tokens = ethabi::decode(
+        &[
+            ParamType::Address,
+            ParamType::Uint(64),
+            ParamType::Bytes,
+            ParamType::Bytes,
+        ],
+        encoded,
+    )
+    .map_err(|err| anyhow::anyhow!("malformed signed envelope: {err}"))?;
+    match tokens.as_slice() {
+        [Token::Address(sender), Token::Uint(nonce), Token::Bytes(signature), Token::Bytes(inner)] =>
+        {
+            // ethabi reads the whole word for Uint(64) without checking its range
+            if *nonce > U256::from(u64::MAX) {
+                return Err(anyhow::anyhow!(
+                    "malformed signed envelope: nonce {nonce} is out of range"
+                ));
+            }
+            let envelope = SignedEnvelope {
+                sender: *sender,
+                nonce: nonce.as_u64(),
+                signature: Signature::try_from(signature.as_slice())?,
+            };
+            Ok((Bytes::from(inner.clone()), Some(envelope)))
+        }
+        _ => Err(anyhow::anyhow!("malformed signed envelope")),
+    }
+}
+
+/*
+ * The EIP-191 hash signed for a payload.
+ * conversation_id: the conversation the payload is sent to
+ * payload: the payload
+ * nonce: the message nonce
+ * Returns the hash to sign.
+ */
+fn signing_digest(conversation_id: H256, payload: &[u8], nonce: u64) -> H256 {
+    let encoded = ethabi::encode(&[
+        Token::FixedBytes(conversation_id.as_bytes().to_vec()),
+        Token::Bytes(payload.to_vec()),
+        Token::Uint(U256::from(nonce)),
+    ]);
+    hash_message(keccak256(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_wallet;
+
+    const KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn sealed(payload: &[u8]) -> (WalletType, Bytes) {
+        let wallet = parse_wallet(KEY).unwrap();
+        let envelope = seal(&wallet, H256::repeat_byte(1), payload, 42).unwrap();
+        (wallet, envelope)
+    }
+
+    #[test]
+    fn test_seal_open_verify() {
+        let (wallet, envelope) = sealed(b"hello");
+        assert!(envelope.starts_with(&SIGNED_MAGIC));
+        let (payload, signed) = open(envelope).unwrap();
+        let signed = signed.unwrap();
+        assert_eq!(payload, Bytes::from(b"hello".to_vec()));
+        assert_eq!(signed.nonce, 42);
+        assert_eq!(
+            signed.verify(H256::repeat_byte(1), &payload),
+            Some(ethers::signers::Signer::address(&wallet))
+        );
+    }
+
+    #[test]
+    fn test_tampered_payload_fails_verification() {
+        let (_, envelope) = sealed(b"hello");
+        let (_, signed) = open(envelope).unwrap();
+        let signed = signed.unwrap();
+        assert_eq!(signed.verify(H256::repeat_byte(1), b"hellO"), None);
+        // the same payload replayed into another conversation
+        assert_eq!(signed.verify(H256::repeat_byte(2), b"hello"), None);
+    }
+
+    #[test]
+    fn test_forged_sender_fails_verification() {
+        let (_, envelope) = sealed(b"hello");
+        let (payload, signed) = open(envelope).unwrap();
+        let forged = SignedEnvelope {
+            sender: Address::repeat_byte(9),
+            ..signed.unwrap()
+        };
+        assert_eq!(forged.verify(H256::repeat_byte(1), &payload), None);
+    }
+
+    #[test]
+    fn test_open_unsigned_payload() {
+        let (payload, signed) = open(Bytes::from(b"hello".to_vec())).unwrap();
+        assert_eq!(payload, Bytes::from(b"hello".to_vec()));
+        assert!(signed.is_none());
+        assert!(open(Bytes::from(SIGNED_MAGIC.to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_open_rejects_oversized_nonce() {
+        let (_, envelope) = sealed(b"hello");
+        let mut envelope = envelope.to_vec();
+        // the nonce is the second head word after the magic
+        let nonce = SIGNED_MAGIC.len() + 32;
+        envelope[nonce..nonce + 24].fill(0xff);
+        assert!(open(Bytes::from(envelope)).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use crate::MessageRecord;
+
+/// A destination for messages received while following conversations.
+pub trait MessageSink {
+    /**
+     * Deliver one message.
+     * conversation: the conversation ID the message was sent to
+     * message: the message
+     * Returns Ok(()) if the message was delivered.
+     */
+    fn deliver(&self, conversation: &str, message: &MessageRecord) -> Result<(), Error>;
+}
+
+/// Writes each message to the tracing log at INFO.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+/// Writes each message to stdout as a line of JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+/// Appends each message to a file as a line of JSON.
+#[derive(Debug)]
+pub struct FileSink {
+    file: File,
+}
+
+impl MessageSink for LogSink {
+    fn deliver(&self, conversation: &str, message: &MessageRecord) -> Result<(), Error> {
+        tracing::info!(
+            "{conversation} block {} tx {}: {}",
+            or_unknown(message.block_number.map(|block| block.to_string())),
+            or_unknown(message.transaction_hash.map(|tx| format!("{tx:#x}"))),
+            message.message
+        );
+        Ok(())
+    }
+}
+
+impl MessageSink for StdoutSink {
+    fn deliver(&self, conversation: &str, message: &MessageRecord) -> Result<(), Error> {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", to_json(conversation, message))?;
+        Ok(())
+    }
+}
+
+impl FileSink {
+    /**
+     * Open a file for appending, creating it if necessary.
+     * path: the file to write to
+     * Returns Ok(FileSink) if the file could be opened.
+     */
+    pub fn open(path: impl AsRef<Path>) -> Result<FileSink, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink { file })
+    }
+}
+
+impl MessageSink for FileSink {
+    fn deliver(&self, conversation: &str, message: &MessageRecord) -> Result<(), Error> {
+        let line = format!("{}\n", to_json(conversation, message));
+        // one write per line so concurrent appenders do not interleave records
+        (&self.file).write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/*
+ * Render a message as a JSON object.  Every key is always present, null when unknown.
+ * conversation: the conversation ID
+ * message: the message
+ * Returns the JSON value.
+ */
+pub(crate) fn to_json(conversation: &str, message: &MessageRecord) -> Value {
+    json!({
+        "conversation": conversation,
+        "message": message.message,
+        "block_number": message.block_number.map(|block| block.as_u64()),
+        "transaction_hash": message.transaction_hash.map(|tx| format!("{tx:#x}")),
+        "log_index": message.log_index.map(|index| index.to_string()),
+        "undecoded": message.undecoded.as_ref().map(|data| data.to_string()),
+        "signed": message.is_signed(),
+        "verified_sender": message.verify_sender().map(|sender| format!("{sender:#x}")),
+        "decrypt_error": message.decrypt_error,
+        "content_type": message.content_type(),
+    })
+}
+
+fn or_unknown(value: Option<String>) -> String {
+    value.unwrap_or_else(|| String::from("unknown"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_CONTENT_TYPE;
+    use ethers::types::{H256, U256, U64};
+
+    fn record() -> MessageRecord {
+        MessageRecord {
+            message: String::from("hello"),
+            block_number: Some(U64::from(12)),
+            transaction_hash: Some(H256::repeat_byte(0xab)),
+            log_index: Some(U256::from(2)),
+            conversation_topic: None,
+            undecoded: None,
+            signed: None,
+            decrypt_error: None,
+            content_type: None,
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let value = to_json("conv", &record());
+        assert_eq!(value["conversation"], "conv");
+        assert_eq!(value["message"], "hello");
+        assert_eq!(value["block_number"], 12);
+        assert_eq!(value["transaction_hash"], format!("0x{}", "ab".repeat(32)));
+        assert_eq!(value["log_index"], "2");
+        assert_eq!(value["signed"], false);
+        assert!(value["verified_sender"].is_null());
+        assert_eq!(value["content_type"], DEFAULT_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn test_to_json_pending() {
+        let record = MessageRecord {
+            message: String::from("hello"),
+            block_number: None,
+            transaction_hash: None,
+            log_index: None,
+            conversation_topic: None,
+            undecoded: None,
+            signed: None,
+            decrypt_error: None,
+            content_type: None,
+        };
+        let value = to_json("conv", &record);
+        assert!(value["block_number"].is_null());
+        assert!(value["transaction_hash"].is_null());
+        assert!(value["log_index"].is_null());
+    }
+
+    #[test]
+    fn test_file_sink_appends_lines() {
+        let path = std::env::temp_dir().join(format!("file_sink_{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = FileSink::open(&path).unwrap();
+        sink.deliver("a", &record()).unwrap();
+        sink.deliver("b", &record()).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["conversation"], "a");
+        assert_eq!(lines[1]["conversation"], "b");
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use ethers::types::{H256, U256};
+
+/// Counter of messages sent and confirmed.
+pub const MESSAGES_SENT_TOTAL: &str = "messages_sent_total";
+/// Counter of sends that failed or were dropped before confirming.
+pub const SEND_FAILURES_TOTAL: &str = "send_failures_total";
+/// Histogram of the time from submitting a message to its confirmation, in seconds.
+pub const SEND_LATENCY_SECONDS: &str = "send_latency_seconds";
+/// Histogram of the gas used by each confirmed message transaction.
+pub const GAS_USED: &str = "gas_used";
+/// Counter of messages delivered by a follow.
+pub const MESSAGES_RECEIVED_TOTAL: &str = "messages_received_total";
+/// Histogram of the time taken by each rewind, in seconds.
+pub const REWIND_DURATION_SECONDS: &str = "rewind_duration_seconds";
+/// Counter of provider connection retries and follow resubscriptions.
+pub const RECONNECTS_TOTAL: &str = "reconnects_total";
+
+/// Label carrying the first 8 hex characters of the conversation ID.  Every metric
+/// except reconnects_total has it.
+pub const CONVERSATION_LABEL: &str = "conversation";
+/// Label carrying the chain ID.  Every metric has it.
+pub const CHAIN_ID_LABEL: &str = "chain_id";
+
+/*
+ * Record a confirmed send.
+ * conversation: the conversation ID
+ * chain_id: the chain the message was sent on
+ * latency: the time from submitting to confirmation
+ * gas_used: the gas used, if the receipt reports it
+ */
+pub(crate) fn send_succeeded(
+    conversation: H256,
+    chain_id: u64,
+    latency: Duration,
+    gas_used: Option<U256>,
+) {
+    #[cfg(feature = "metrics")]
+    {
+        let labels = labels(conversation, chain_id);
+        metrics::counter!(MESSAGES_SENT_TOTAL, &labels).increment(1);
+        metrics::histogram!(SEND_LATENCY_SECONDS, &labels).record(latency.as_secs_f64());
+        if let Some(gas_used) = gas_used {
+            metrics::histogram!(GAS_USED, &labels).record(gas_used.as_u128() as f64);
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (conversation, chain_id, latency, gas_used);
+}
+
+/*
+ * Record a failed send.
+ * conversation: the conversation ID
+ * chain_id: the chain the message was sent on
+ */
+pub(crate) fn send_failed(conversation: H256, chain_id: u64) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(SEND_FAILURES_TOTAL, &labels(conversation, chain_id)).increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (conversation, chain_id);
+}
+
+/*
+ * Record a message delivered by a follow.
+ * conversation: the conversation ID from the log, if it has one
+ * chain_id: the chain followed
+ */
+pub(crate) fn message_received(conversation: Option<H256>, chain_id: u64) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        MESSAGES_RECEIVED_TOTAL,
+        &labels(conversation.unwrap_or_default(), chain_id)
+    )
+    .increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (conversation, chain_id);
+}
+
+/*
+ * Record a completed rewind.
+ * conversation: the conversation ID
+ * chain_id: the chain read
+ * duration: the time the rewind took
+ */
+pub(crate) fn rewind_finished(conversation: H256, chain_id: u64, duration: Duration) {
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(REWIND_DURATION_SECONDS, &labels(conversation, chain_id))
+        .record(duration.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = (conversation, chain_id, duration);
+}
+
+/*
+ * Record a connection retry or resubscription.
+ * chain_id: the chain connected to, zero before the chain is known
+ */
+pub(crate) fn reconnected(chain_id: u64) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(RECONNECTS_TOTAL, CHAIN_ID_LABEL => chain_id.to_string()).increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = chain_id;
+}
+
+/*
+ * The labels of a per-conversation metric.  The conversation ID is truncated to
+ * keep label values short while still telling conversations apart.
+ * conversation: the conversation ID
+ * chain_id: the chain ID
+ * Returns the label pairs.
+ */
+#[cfg(feature = "metrics")]
+fn labels(conversation: H256, chain_id: u64) -> [(&'static str, String); 2] {
+    [
+        (
+            CONVERSATION_LABEL,
+            hex::encode(&conversation.as_bytes()[..4]),
+        ),
+        (CHAIN_ID_LABEL, chain_id.to_string()),
+    ]
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use metrics_util::{
+        debugging::{DebugValue, DebuggingRecorder},
+        MetricKind,
+    };
+
+    #[test]
+    fn test_recorders_are_invoked() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let conversation = H256::repeat_byte(0xab);
+        metrics::with_local_recorder(&recorder, || {
+            send_succeeded(
+                conversation,
+                11155111,
+                Duration::from_millis(1500),
+                Some(U256::from(21_000)),
+            );
+            send_failed(conversation, 11155111);
+            message_received(Some(conversation), 11155111);
+            message_received(Some(conversation), 11155111);
+            rewind_finished(conversation, 11155111, Duration::from_secs(2));
+            reconnected(11155111);
+        });
+
+        let metrics = snapshotter.snapshot().into_vec();
+        let find = |name: &str| {
+            metrics
+                .iter()
+                .find(|(key, ..)| key.key().name() == name)
+                .unwrap_or_else(|| panic!("{name} not recorded"))
+        };
+        let (key, _, _, value) = find(MESSAGES_RECEIVED_TOTAL);
+        assert_eq!(key.kind(), MetricKind::Counter);
+        assert_eq!(value, &DebugValue::Counter(2));
+        let labels: Vec<(&str, &str)> = key
+            .key()
+            .labels()
+            .map(|label| (label.key(), label.value()))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                (CONVERSATION_LABEL, "abababab"),
+                (CHAIN_ID_LABEL, "11155111")
+            ]
+        );
+        for name in [
+            MESSAGES_SENT_TOTAL,
+            SEND_FAILURES_TOTAL,
+            SEND_LATENCY_SECONDS,
+            GAS_USED,
+            REWIND_DURATION_SECONDS,
+            RECONNECTS_TOTAL,
+        ] {
+            find(name);
+        }
+        let (_, _, _, latency) = find(SEND_LATENCY_SECONDS);
+        match latency {
+            DebugValue::Histogram(values) => assert_eq!(values[0].into_inner(), 1.5),
+            other => panic!("latency is not a histogram: {other:?}"),
+        }
+    }
+}
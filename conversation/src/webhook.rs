@@ -0,0 +1,309 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Error;
+use serde_json::Value;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+
+use crate::{sink::to_json, MessageRecord, MessageSink, RetryPolicy};
+
+/// default number of messages waiting to be posted before new ones are dropped
+pub const WEBHOOK_QUEUE_CAPACITY: usize = 256;
+/// default time allowed for each webhook request
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options for a WebhookSink.
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// number of messages waiting to be posted before new ones are dropped, so a
+    /// slow endpoint never stalls the follow
+    pub queue_capacity: usize,
+    /// time allowed for each request
+    pub timeout: Duration,
+    /// how a request that fails or returns a non-2xx status is retried
+    pub retry: RetryPolicy,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            queue_capacity: WEBHOOK_QUEUE_CAPACITY,
+            timeout: WEBHOOK_TIMEOUT,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+/// Delivery counts for a WebhookSink.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WebhookStats {
+    /// messages the endpoint accepted
+    pub delivered: u64,
+    /// messages that still failed after every retry
+    pub failed: u64,
+    /// messages dropped because the queue was full
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// POSTs each message as JSON to a URL from a background task.
+#[derive(Debug)]
+pub struct WebhookSink {
+    queue: mpsc::Sender<Value>,
+    counters: Arc<Counters>,
+    worker: JoinHandle<()>,
+}
+
+impl WebhookSink {
+    /**
+     * Start posting messages to a webhook.  Must be called within a tokio runtime.
+     * url: the URL to POST each message to
+     * config: the queue, timeout and retry options
+     * Returns Ok(WebhookSink) if the HTTP client could be built.
+     */
+    pub fn new(url: String, config: WebhookConfig) -> Result<WebhookSink, Error> {
+        let client = reqwest::Client::builder().timeout(config.timeout).build()?;
+        let (queue, mut pending) = mpsc::channel::<Value>(config.queue_capacity.max(1));
+        let counters = Arc::new(Counters::default());
+        let worker_counters = counters.clone();
+        let worker = tokio::spawn(async move {
+            while let Some(body) = pending.recv().await {
+                let counter = match post(&client, &url, &body, &config.retry).await {
+                    Ok(()) => &worker_counters.delivered,
+                    Err(err) => {
+                        tracing::error!("webhook delivery failed: {err}");
+                        &worker_counters.failed
+                    }
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        Ok(WebhookSink {
+            queue,
+            counters,
+            worker,
+        })
+    }
+
+    /**
+     * The delivery counts so far.
+     * Returns the WebhookStats.
+     */
+    pub fn stats(&self) -> WebhookStats {
+        WebhookStats {
+            delivered: self.counters.delivered.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /**
+     * Stop accepting messages and wait for the queued ones to be posted.
+     * Returns the final WebhookStats.
+     */
+    pub async fn finish(self) -> WebhookStats {
+        let WebhookSink {
+            queue,
+            counters,
+            worker,
+        } = self;
+        drop(queue);
+        if let Err(err) = worker.await {
+            tracing::error!("webhook worker failed: {err}");
+        }
+        WebhookStats {
+            delivered: counters.delivered.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+            dropped: counters.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl MessageSink for WebhookSink {
+    fn deliver(&self, conversation: &str, message: &MessageRecord) -> Result<(), Error> {
+        match self.queue.try_send(to_json(conversation, message)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("webhook queue full, dropping message");
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Closed(_)) => Err(anyhow::anyhow!("webhook worker has stopped")),
+        }
+    }
+}
+
+/*
+ * POST a body to the webhook, retrying failures and non-2xx responses.
+ * client: the HTTP client
+ * url: the webhook URL
+ * body: the JSON body
+ * retry: the retry policy
+ * Returns Ok(()) once the endpoint accepts the body.
+ */
+async fn post(
+    client: &reqwest::Client,
+    url: &str,
+    body: &Value,
+    retry: &RetryPolicy,
+) -> Result<(), Error> {
+    let mut attempt = 1;
+    loop {
+        let err = match client.post(url).json(body).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => anyhow::anyhow!("webhook returned {}", response.status()),
+            Err(err) => err.into(),
+        };
+        if attempt >= retry.max_attempts {
+            return Err(err);
+        }
+        let delay = retry.delay(attempt);
+        tracing::warn!("webhook attempt {attempt} failed: {err}, retrying in {delay:?}");
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::U64;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    /// Serve one request per status, returning the request bodies received.
+    fn serve(statuses: Vec<u16>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_webhook_retries_until_accepted() {
+        let (url, server) = serve(vec![500, 503, 200]);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let stats = runtime.block_on(async {
+            let config = WebhookConfig {
+                retry: RetryPolicy {
+                    max_attempts: 3,
+                    initial_delay: Duration::from_millis(1),
+                    max_delay: Duration::from_millis(1),
+                },
+                ..Default::default()
+            };
+            let sink = WebhookSink::new(url, config).unwrap();
+            let record = MessageRecord {
+                message: String::from("hello"),
+                block_number: Some(U64::from(7)),
+                transaction_hash: None,
+                log_index: None,
+                conversation_topic: None,
+                undecoded: None,
+                signed: None,
+                decrypt_error: None,
+                content_type: None,
+            };
+            sink.deliver("conv", &record).unwrap();
+            sink.finish().await
+        });
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 3);
+        let body: Value = serde_json::from_str(&bodies[2]).unwrap();
+        assert_eq!(body["conversation"], "conv");
+        assert_eq!(body["message"], "hello");
+        assert_eq!(
+            stats,
+            WebhookStats {
+                delivered: 1,
+                failed: 0,
+                dropped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_webhook_gives_up_after_max_attempts() {
+        let (url, server) = serve(vec![500, 500]);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let stats = runtime.block_on(async {
+            let config = WebhookConfig {
+                retry: RetryPolicy {
+                    max_attempts: 2,
+                    initial_delay: Duration::from_millis(1),
+                    max_delay: Duration::from_millis(1),
+                },
+                ..Default::default()
+            };
+            let sink = WebhookSink::new(url, config).unwrap();
+            let record = MessageRecord {
+                message: String::from("hello"),
+                block_number: None,
+                transaction_hash: None,
+                log_index: None,
+                conversation_topic: None,
+                undecoded: None,
+                signed: None,
+                decrypt_error: None,
+                content_type: None,
+            };
+            sink.deliver("conv", &record).unwrap();
+            sink.finish().await
+        });
+
+        assert_eq!(server.join().unwrap().len(), 2);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.delivered, 0);
+    }
+}
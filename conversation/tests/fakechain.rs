@@ -0,0 +1,759 @@
+use std::time::Duration;
+
+use conversation::{
+    fakechain::{FakeChain, FAKE_CHAIN_PRIVATE_KEY},
+    ConversationError, ConversationStats, FollowOptions, FollowStop, MessageSender,
+    MessageSenderConfig, PayloadCodec, SpeedUpPolicy, DEFAULT_CONTENT_TYPE,
+};
+use ethers::types::{Bytes, Transaction};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+async fn connect(chain: &FakeChain) -> MessageSender {
+    let config = MessageSenderConfig {
+        provider_interval: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    MessageSender::new_with_config(chain.url(), FAKE_CHAIN_PRIVATE_KEY.to_string(), config)
+        .await
+        .unwrap()
+}
+
+/// Payloads as the bytes themselves, which need not be text.
+struct RawCodec;
+
+impl PayloadCodec<Vec<u8>> for RawCodec {
+    fn encode(&self, value: &Vec<u8>) -> Result<Bytes, anyhow::Error> {
+        Ok(Bytes::from(value.clone()))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(data.to_vec())
+    }
+}
+
+#[test]
+fn test_send_rewind_follow() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversation = String::from("fakechain");
+        let sent: Vec<String> = ["one", "two", "three"].map(String::from).to_vec();
+        for message in sent.iter() {
+            let receipt = sender.send_message(&conversation, message).await.unwrap();
+            assert!(receipt.block_number.is_some());
+        }
+        assert_eq!(chain.block_number(), 3);
+
+        let rewind = sender.rewind(&conversation, 3).await.unwrap();
+        assert_eq!(rewind.message, sent);
+        let rewind = sender.rewind(&conversation, 2).await.unwrap();
+        assert_eq!(rewind.message, sent[1..]);
+
+        let (tx, mut rx) = mpsc::channel(4);
+        let options = FollowOptions {
+            max_messages: Some(1),
+            max_duration: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let start_block = rewind.follow_start_block();
+        let follow = sender.follow_to_channel(&conversation, &start_block, &options, tx);
+        let send = async {
+            // let the follow subscribe before the message is mined
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            sender
+                .send_message(&conversation, &String::from("four"))
+                .await
+        };
+        let (summary, receipt) = futures::join!(follow, send);
+        let receipt = receipt.unwrap();
+        assert_eq!(summary.unwrap().stopped_by, FollowStop::MaxMessages);
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.message, "four");
+        assert_eq!(record.block_number, receipt.block_number);
+    });
+}
+
+#[test]
+fn test_conversations_are_separate() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let (first, second) = (String::from("first"), String::from("second"));
+        sender
+            .send_message(&first, &String::from("a"))
+            .await
+            .unwrap();
+        sender
+            .send_message(&second, &String::from("b"))
+            .await
+            .unwrap();
+        sender
+            .send_message(&first, &String::from("c"))
+            .await
+            .unwrap();
+
+        let rewind = sender.rewind(&first, 10).await.unwrap();
+        assert_eq!(rewind.message, ["a", "c"]);
+        assert_eq!(rewind.last_change.as_u64(), 3);
+        let rewind = sender.rewind(&second, 10).await.unwrap();
+        assert_eq!(rewind.message, ["b"]);
+        assert_eq!(chain.logs().len(), 3);
+
+        let stats = sender.conversation_history_size(&first).await.unwrap();
+        assert_eq!(stats.message_count, 2);
+        assert_eq!(stats.total_bytes, 2);
+        let stats = sender.conversation_history_size("empty").await.unwrap();
+        assert_eq!(stats, ConversationStats::default());
+    });
+}
+
+/*
+ * Send one message, then two mined into one block, then one more, so the second of
+ * the shared block's logs points back at its own block.
+ */
+async fn send_same_block(chain: &FakeChain, sender: &MessageSender, conversation: &str) {
+    for message in ["one", "two", "three", "four"] {
+        match message {
+            "two" => chain.batch_sends(true),
+            "four" => chain.batch_sends(false),
+            _ => {}
+        }
+        sender
+            .send_message(&conversation.to_string(), &message.to_string())
+            .await
+            .unwrap();
+    }
+    let blocks: Vec<u64> = chain
+        .logs()
+        .iter()
+        .map(|log| log.block_number.unwrap().as_u64())
+        .collect();
+    assert_eq!(blocks, [1, 2, 2, 3]);
+}
+
+#[test]
+fn test_same_block_messages() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversation = "busy";
+        send_same_block(&chain, &sender, conversation).await;
+
+        let walk = Duration::from_secs(60);
+        let count = tokio::time::timeout(walk, sender.message_count(conversation, None));
+        assert_eq!(count.await.unwrap().unwrap(), 4);
+        let count = tokio::time::timeout(walk, sender.message_count(conversation, Some(3)));
+        assert_eq!(count.await.unwrap().unwrap(), 3);
+        let rewind = sender.rewind(conversation, 10).await.unwrap();
+        assert_eq!(rewind.message, ["one", "two", "three", "four"]);
+
+        for (index, message) in [(1, Some("two")), (2, Some("three")), (4, None)] {
+            let found =
+                tokio::time::timeout(walk, sender.get_message_at_index(conversation, index));
+            assert_eq!(found.await.unwrap().unwrap().as_deref(), message);
+        }
+
+        let stats = tokio::time::timeout(walk, sender.conversation_history_size(conversation));
+        assert_eq!(
+            stats.await.unwrap().unwrap(),
+            ConversationStats {
+                message_count: 4,
+                total_bytes: 15,
+            }
+        );
+    });
+}
+
+#[test]
+fn test_send_content_types() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversation = "typed";
+        sender
+            .send_content(conversation, "application/json", br#"{"n":1}"#)
+            .await
+            .unwrap();
+        sender
+            .send_message(&conversation.to_string(), &String::from("plain"))
+            .await
+            .unwrap();
+        sender
+            .send_content(conversation, "application/octet-stream", &[0xff, 0x00])
+            .await
+            .unwrap();
+
+        let content = sender.rewind_content(conversation, 3).await.unwrap();
+        let content: Vec<(&str, &[u8])> = content
+            .iter()
+            .map(|(content_type, payload)| (content_type.as_str(), payload.as_ref()))
+            .collect();
+        assert_eq!(
+            content,
+            [
+                ("application/json", &br#"{"n":1}"#[..]),
+                (DEFAULT_CONTENT_TYPE, b"plain"),
+                ("application/octet-stream", &[0xff, 0x00]),
+            ]
+        );
+    });
+}
+
+#[test]
+fn test_size_threshold() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversation = String::from("sized");
+        for message in ["abc", "def"] {
+            sender
+                .send_message(&conversation, &message.to_string())
+                .await
+                .unwrap();
+        }
+
+        let sizes = sender
+            .subscribe_conversation_size_threshold(&conversation, 4)
+            .await
+            .unwrap();
+        let send = async {
+            for message in ["gh", "ijk"] {
+                sender
+                    .send_message(&conversation, &message.to_string())
+                    .await
+                    .unwrap();
+            }
+        };
+        // 6 bytes of history, so the first message takes the total past 8
+        let (sizes, _) = futures::join!(
+            tokio::time::timeout(Duration::from_secs(60), sizes.take(1).collect::<Vec<_>>()),
+            send
+        );
+        assert_eq!(sizes.unwrap(), [8]);
+        assert!(sender
+            .subscribe_conversation_size_threshold(&conversation, 0)
+            .await
+            .is_err());
+
+        // a history with messages sharing a block is measured before the stream starts
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let (conversation, message) = (String::from("busy"), String::from("x"));
+        send_same_block(&chain, &sender, &conversation).await;
+        let sizes = tokio::time::timeout(
+            Duration::from_secs(60),
+            sender.subscribe_conversation_size_threshold(&conversation, 4),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let send = sender.send_message(&conversation, &message);
+        // 15 bytes of history, so the message takes the total to 16
+        let (sizes, _) = futures::join!(
+            tokio::time::timeout(Duration::from_secs(60), sizes.take(1).collect::<Vec<_>>()),
+            send
+        );
+        assert_eq!(sizes.unwrap(), [16]);
+    });
+}
+
+#[test]
+fn test_failed_ordered_send_leaves_no_nonce_gap() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversation = String::from("ordered");
+        // the second message's nonce is refused before reaching the mempool
+        chain.reject_nonce(1);
+        let messages = ["a", "b", "c"].map(String::from);
+        assert!(sender
+            .send_messages_ordered(&conversation, &messages)
+            .await
+            .is_err());
+
+        let message = String::from("d");
+        let send = sender.send_message(&conversation, &message);
+        let receipt = tokio::time::timeout(Duration::from_secs(10), send).await;
+        assert!(receipt.unwrap().is_ok());
+        assert!(chain.pending().is_empty());
+        let rewind = sender.rewind(&conversation, 10).await.unwrap();
+        assert_eq!(rewind.message, ["a", "d"]);
+    });
+}
+
+#[test]
+fn test_broadcast() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversations = ["first", "second", "third", "fourth"];
+        chain.revert_conversation(sender.conversation_id("third").as_topic(), "muted");
+
+        let results = sender
+            .broadcast(&conversations, "announcement")
+            .await
+            .unwrap();
+        assert_eq!(results.len(), conversations.len());
+        for conversation in conversations {
+            let result = &results[conversation];
+            let rewind = sender.rewind(conversation, 10).await.unwrap();
+            if conversation == "third" {
+                match result.as_ref().unwrap_err().downcast_ref() {
+                    Some(ConversationError::Reverted { reason }) => assert_eq!(reason, "muted"),
+                    other => panic!("unexpected error: {other:?}"),
+                }
+                assert!(rewind.message.is_empty());
+            } else {
+                assert!(result.is_ok());
+                assert_eq!(rewind.message, ["announcement"]);
+            }
+        }
+        // the failed send spent its nonce, so later sends are not held behind it
+        assert!(chain.pending().is_empty());
+        let (conversation, message) = (String::from("first"), String::from("after"));
+        let send = sender.send_message(&conversation, &message);
+        let receipt = tokio::time::timeout(Duration::from_secs(10), send).await;
+        assert!(receipt.unwrap().is_ok());
+
+        assert!(sender
+            .broadcast(&["first", "second", "first"], "twice")
+            .await
+            .is_err());
+    });
+}
+
+#[test]
+fn test_get_logs_paginated() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversation = String::from("paginated");
+        let start = chain.block_number() + 1;
+        // each send is mined in a block of its own
+        let messages = ["a", "b", "c", "d", "e"].map(String::from);
+        for message in &messages {
+            sender.send_message(&conversation, message).await.unwrap();
+        }
+        let head = chain.block_number();
+        assert_eq!(head - start + 1, messages.len() as u64);
+
+        let paginate = |from: u64, to: Option<u64>, chunk_size: u64| {
+            let filter = sender
+                .conversation_filter(&conversation, Some(from.into()), to.map(Into::into))
+                .unwrap();
+            sender
+                .get_logs_paginated(filter, chunk_size)
+                .map(|log| log.unwrap().block_number.unwrap().as_u64())
+                .collect::<Vec<u64>>()
+        };
+        let all: Vec<u64> = (start..=head).collect();
+        // pages of one block, pages ending on the last block and a page past it
+        for chunk_size in [1, 2, 5, 100] {
+            assert_eq!(paginate(start, Some(head), chunk_size).await, all);
+        }
+        // without a to_block the range ends at the head
+        assert_eq!(paginate(start, None, 2).await, all);
+        // a range starting mid page keeps its first block
+        assert_eq!(paginate(start + 1, Some(head - 1), 2).await, all[1..4]);
+        // a range before the messages, and an inverted one, have no logs
+        assert!(paginate(1, Some(start - 1), 2).await.is_empty());
+        assert!(paginate(head, Some(start), 2).await.is_empty());
+    });
+}
+
+#[test]
+fn test_replay_stops_at_failed_send() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let (source, target) = (String::from("source"), String::from("target"));
+        for message in ["a", "b", "c"] {
+            sender
+                .send_message(&source, &message.to_string())
+                .await
+                .unwrap();
+        }
+        // the second replayed message is refused; the third is not sent after it
+        chain.reject_nonce(4);
+        let results = sender.replay(&source, &target, 3).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        let rewind = sender.rewind(&target, 10).await.unwrap();
+        assert_eq!(rewind.message, ["a"]);
+    });
+}
+
+#[test]
+fn test_send_reverted_reason() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        chain.revert_sends(Some("sender not allowed"));
+        let conversation = String::from("reverted");
+        let message = String::from("refused");
+        let err = sender
+            .send_message(&conversation, &message)
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<ConversationError>() {
+            Some(ConversationError::Reverted { reason }) => {
+                assert_eq!(reason, "sender not allowed")
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+        assert!(chain.logs().is_empty());
+
+        chain.revert_sends(None);
+        assert!(sender.send_message(&conversation, &message).await.is_ok());
+        assert_eq!(chain.logs().len(), 1);
+    });
+}
+
+#[test]
+fn test_follow_typed_binary_payloads() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let conversation = String::from("binary");
+        // none is valid UTF-8 but the last, and the 0xff ones look like the
+        // encrypted and signed layer prefixes
+        let sent = vec![
+            vec![0x80, 0x00, 0x01],
+            vec![0xc3],
+            vec![0xff, 0x65, 0x01],
+            vec![0xff, 0x73],
+            b"text".to_vec(),
+        ];
+
+        let start_block = 1.into();
+        let received = std::sync::Mutex::new(Vec::new());
+        let options = FollowOptions {
+            max_messages: Some(sent.len() as u64),
+            max_duration: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let follow = sender.follow_typed(
+            &conversation,
+            &start_block,
+            &options,
+            &RawCodec,
+            |value, _| received.lock().unwrap().push(value),
+        );
+        let send = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            for value in sent.iter() {
+                sender
+                    .send_typed(&conversation, value, &RawCodec)
+                    .await
+                    .unwrap();
+            }
+        };
+        let (summary, _) = futures::join!(follow, send);
+        let summary = summary.unwrap();
+        assert_eq!(summary.stopped_by, FollowStop::MaxMessages);
+        assert_eq!(summary.skipped, 0);
+        assert_eq!(received.into_inner().unwrap(), sent);
+    });
+}
+
+/*
+ * Wait for a follow to query the history held by hold_logs, send a message while it
+ * is held, then release it and send one more.
+ */
+async fn send_across_seam(chain: &FakeChain, sender: &MessageSender, conversation: &String) {
+    while chain.held_log_queries() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    for message in ["three", "four"] {
+        sender
+            .send_message(conversation, &message.to_string())
+            .await
+            .unwrap();
+        chain.hold_logs(false);
+    }
+}
+
+static COMPLETE_RECEIVED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+#[test]
+fn test_follow_complete_seam() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let reader = connect(&chain).await;
+        let conversation = String::from("seam");
+        for message in ["one", "two"] {
+            sender
+                .send_message(&conversation, &message.to_string())
+                .await
+                .unwrap();
+        }
+
+        chain.hold_logs(true);
+        let options = FollowOptions {
+            max_messages: Some(4),
+            max_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let follow = reader.follow_messages_complete(&conversation, 10, &options, |message| {
+            COMPLETE_RECEIVED
+                .lock()
+                .unwrap()
+                .push(message.message.clone())
+        });
+        let (summary, _) = futures::join!(follow, send_across_seam(&chain, &sender, &conversation));
+        assert_eq!(summary.unwrap().stopped_by, FollowStop::MaxMessages);
+        // the message sent while the history was read arrives once, live
+        assert_eq!(
+            *COMPLETE_RECEIVED.lock().unwrap(),
+            ["one", "two", "three", "four"]
+        );
+    });
+}
+
+#[test]
+fn test_follow_resubscribes_stalled() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let config = MessageSenderConfig {
+            provider_interval: Some(Duration::from_millis(10)),
+            idle_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let reader =
+            MessageSender::new_with_config(chain.url(), FAKE_CHAIN_PRIVATE_KEY.to_string(), config)
+                .await
+                .unwrap();
+        let conversation = String::from("stalled");
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let options = FollowOptions {
+            max_messages: Some(3),
+            max_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let start_block = 1.into();
+        let follow = reader.follow_to_channel(&conversation, &start_block, &options, tx);
+        let send = async {
+            while chain.live_subscriptions() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            sender
+                .send_message(&conversation, &String::from("one"))
+                .await
+                .unwrap();
+            // the subscription goes quiet while lastMessage keeps advancing
+            chain.stall_subscriptions();
+            assert_eq!(chain.live_subscriptions(), 0);
+            for message in ["two", "three"] {
+                sender
+                    .send_message(&conversation, &message.to_string())
+                    .await
+                    .unwrap();
+            }
+        };
+        let (summary, _) = futures::join!(follow, send);
+        assert_eq!(summary.unwrap().stopped_by, FollowStop::MaxMessages);
+        let mut received = Vec::new();
+        while let Ok(record) = rx.try_recv() {
+            received.push(record.message);
+        }
+        // the later two arrive only by resubscribing once the idle timeout finds the
+        // follow behind
+        assert_eq!(received, ["one", "two", "three"]);
+    });
+}
+
+#[test]
+fn test_follow_from_rewind() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let reader = connect(&chain).await;
+        let (history, empty) = (String::from("history"), String::from("empty"));
+        for message in ["one", "two"] {
+            sender
+                .send_message(&history, &message.to_string())
+                .await
+                .unwrap();
+        }
+        let options = FollowOptions {
+            max_messages: Some(1),
+            max_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+
+        // an empty conversation is followed from the head, as a zero start block, so
+        // it subscribes without backfilling and the first message arrives live
+        let rewind = reader.rewind(&empty, 10).await.unwrap();
+        assert!(rewind.message.is_empty());
+        let start_block = rewind.follow_start_block();
+        assert!(start_block.is_zero());
+        let (tx, mut rx) = mpsc::channel(8);
+        let follow = reader.follow_to_channel(&empty, &start_block, &options, tx);
+        let send = async {
+            while chain.live_subscriptions() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            sender
+                .send_message(&empty, &String::from("first"))
+                .await
+                .unwrap();
+        };
+        let (summary, _) = futures::join!(follow, send);
+        assert_eq!(summary.unwrap().stopped_by, FollowStop::MaxMessages);
+        assert_eq!(rx.try_recv().unwrap().message, "first");
+
+        // a conversation with history is followed from after its last message, so
+        // only what was sent since the rewind is delivered
+        let rewind = reader.rewind(&history, 10).await.unwrap();
+        assert_eq!(rewind.message, ["one", "two"]);
+        sender
+            .send_message(&history, &String::from("three"))
+            .await
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let start_block = rewind.follow_start_block();
+        let summary = reader
+            .follow_to_channel(&history, &start_block, &options, tx)
+            .await
+            .unwrap();
+        assert_eq!(summary.stopped_by, FollowStop::MaxMessages);
+        assert_eq!(rx.try_recv().unwrap().message, "three");
+    });
+}
+
+#[test]
+fn test_follow_backfill_seam() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect(&chain).await;
+        let reader = connect(&chain).await;
+        let conversation = String::from("seam");
+        for message in ["one", "two"] {
+            sender
+                .send_message(&conversation, &message.to_string())
+                .await
+                .unwrap();
+        }
+
+        chain.hold_logs(true);
+        let (tx, mut rx) = mpsc::channel(8);
+        let options = FollowOptions {
+            max_messages: Some(4),
+            max_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let start_block = 1.into();
+        let follow = reader.follow_to_channel(&conversation, &start_block, &options, tx);
+        let (summary, _) = futures::join!(follow, send_across_seam(&chain, &sender, &conversation));
+        assert_eq!(summary.unwrap().stopped_by, FollowStop::MaxMessages);
+        let mut received = Vec::new();
+        while let Ok(record) = rx.try_recv() {
+            received.push(record.message);
+        }
+        assert_eq!(received, ["one", "two", "three", "four"]);
+    });
+}
+
+async fn connect_speed_up(chain: &FakeChain) -> MessageSender {
+    let config = MessageSenderConfig {
+        provider_interval: Some(Duration::from_millis(10)),
+        speed_up: Some(SpeedUpPolicy {
+            after: Duration::from_millis(200),
+            bump_percent: 12,
+            max_replacements: 3,
+        }),
+        ..Default::default()
+    };
+    MessageSender::new_with_config(chain.url(), FAKE_CHAIN_PRIVATE_KEY.to_string(), config)
+        .await
+        .unwrap()
+}
+
+/*
+ * Wait for the held send to be replaced in the mempool.
+ * Returns the original transaction and its replacement.
+ */
+async fn await_replacement(chain: &FakeChain) -> (Transaction, Transaction) {
+    let original = loop {
+        if let Some(tx) = chain.pending().pop() {
+            break tx;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    loop {
+        match chain.pending().pop() {
+            Some(tx) if tx.hash != original.hash => return (original, tx),
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+}
+
+#[test]
+fn test_speed_up_mines_replacement() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect_speed_up(&chain).await;
+        let conversation = String::from("stuck");
+        chain.hold_sends(true);
+        let message = String::from("one");
+        let send = sender.send_message(&conversation, &message);
+        let replace = async {
+            let (original, replacement) = await_replacement(&chain).await;
+            chain.hold_sends(false);
+            (original, replacement)
+        };
+        let (receipt, (original, replacement)) = futures::join!(send, replace);
+
+        assert_eq!(replacement.nonce, original.nonce);
+        assert!(replacement.max_fee_per_gas > original.max_fee_per_gas);
+        // the receipt is the replacement's, the only one of the two mined
+        assert_eq!(receipt.unwrap().transaction_hash, replacement.hash);
+        let logs = chain.logs();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].transaction_hash, Some(replacement.hash));
+        assert!(chain.pending().is_empty());
+    });
+}
+
+#[test]
+fn test_speed_up_dropped() {
+    runtime().block_on(async {
+        let chain = FakeChain::start().await.unwrap();
+        let sender = connect_speed_up(&chain).await;
+        let conversation = String::from("stuck");
+        chain.hold_sends(true);
+        let message = String::from("one");
+        let send = sender.send_message(&conversation, &message);
+        let evict = async {
+            await_replacement(&chain).await;
+            chain.drop_pending();
+        };
+        let (receipt, _) = futures::join!(send, evict);
+        let err = receipt.unwrap_err();
+        assert!(err.to_string().contains("dropped"), "{err}");
+        assert!(chain.logs().is_empty());
+
+        // the dropped nonce is free for the next send
+        chain.hold_sends(false);
+        let message = String::from("two");
+        let send = sender.send_message(&conversation, &message);
+        let receipt = tokio::time::timeout(Duration::from_secs(10), send).await;
+        let receipt = receipt.unwrap().unwrap();
+        assert_eq!(
+            chain.logs()[0].transaction_hash,
+            Some(receipt.transaction_hash)
+        );
+    });
+}
@@ -1,10 +1,185 @@
 use anyhow::Error;
-use std::cmp::max;
+use clap::{Parser, Subcommand};
+use ethers::{types::U256, utils::format_ether};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use serde::Serialize;
+use std::{
+    cmp::max,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    time::MissedTickBehavior,
+};
 
 use lipsum::lipsum_words;
 
-use appenv::{init, printenv};
-use conversation::MessageSender;
+use appenv::{init, init_tracing, printenv, printenv_json};
+use conversation::{
+    check_key_pair, wallet_from_mnemonic, wallet_key_hex, AesGcmEncryptor, Envelope, JsonCodec,
+    MessageReceipt, MessageSender, MessageSenderConfig, DEFAULT_HD_PATH,
+};
+
+/// Send generated messages, or lines read from stdin, to a conversation.
+#[derive(Parser)]
+struct Args {
+    /// maximum number of sends in flight at once
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+    /// also print the run report as JSON
+    #[arg(long)]
+    report_json: bool,
+    /// print the resolved configuration as JSON, with secrets redacted
+    #[arg(long)]
+    print_config_json: bool,
+    /// send each message as a JSON envelope with the sender, sequence number and timestamp
+    #[arg(long)]
+    envelopes: bool,
+    /// compress payloads with deflate at this level, 0 to 9
+    #[arg(long)]
+    compression: Option<u32>,
+    /// compress only payloads larger than this many bytes
+    #[arg(long)]
+    compression_threshold: Option<usize>,
+    /// keep sending after a failed message instead of stopping at the first failure
+    #[arg(long)]
+    continue_on_error: bool,
+    /// send each line read from stdin until EOF instead of generated messages
+    #[arg(long)]
+    stdin: bool,
+    /// text added before each message, with {seq} replaced by its sequence number
+    #[arg(long, default_value = "")]
+    prefix: String,
+    /// text added after each message, with {seq} replaced by its sequence number
+    #[arg(long, default_value = "")]
+    suffix: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// check the configuration, RPC connection, contract and wallet balance, then exit
+    Check,
+}
+
+/// A summary of a producer run.
+#[derive(Serialize)]
+struct RunReport {
+    attempted: usize,
+    succeeded: usize,
+    failed: usize,
+    gas_used: String,
+    cost_ether: String,
+    duration_secs: f64,
+}
+
+impl RunReport {
+    fn new(results: &[Result<MessageReceipt, Error>], started: Instant) -> RunReport {
+        let receipts: Vec<&MessageReceipt> = results.iter().flatten().collect();
+        let gas_used = receipts
+            .iter()
+            .filter_map(|receipt| receipt.gas_used)
+            .fold(U256::zero(), |total, gas| total + gas);
+        let cost = receipts
+            .iter()
+            .filter_map(|receipt| receipt.cost())
+            .fold(U256::zero(), |total, cost| total + cost);
+        RunReport {
+            attempted: results.len(),
+            succeeded: receipts.len(),
+            failed: results.len() - receipts.len(),
+            gas_used: gas_used.to_string(),
+            cost_ether: format_ether(cost),
+            duration_secs: started.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+/// Connect and check the chain, contract and wallet without sending, printing a
+/// pass or fail line for each.  Exits with status 1 if any check fails.
+async fn check(
+    rpc_url: String,
+    private_key: String,
+    mut config: MessageSenderConfig,
+) -> Result<(), Error> {
+    println!("PASS configuration");
+    // report a missing contract as a failed check rather than a connection error
+    config.verify_contract = false;
+    let report = match MessageSender::new_with_config(rpc_url, private_key, config).await {
+        Ok(sender) => sender.check_setup().await,
+        Err(err) => Err(err),
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(err) => {
+            println!("FAIL provider: {err}");
+            std::process::exit(1);
+        }
+    };
+    println!("PASS connected to chain {}", report.chain_id);
+    let mut passed = report.contract_deployed();
+    if passed {
+        println!(
+            "PASS contract {:?} deployed, {} bytes, version {}",
+            report.contract_address,
+            report.contract_code_bytes,
+            report.contract_version.as_deref().unwrap_or("unknown")
+        );
+    } else {
+        println!("FAIL no contract deployed at {:?}", report.contract_address);
+    }
+    // sending needs gas
+    if report.balance.is_zero() {
+        passed = false;
+        println!("FAIL account {:?} has no balance", report.account);
+    } else {
+        println!(
+            "PASS account {:?} balance {} ether",
+            report.account,
+            format_ether(report.balance)
+        );
+    }
+    if !passed {
+        println!("check failed");
+        std::process::exit(1);
+    }
+    println!("check passed");
+    Ok(())
+}
+
+/// Read newline separated messages from stdin until EOF, skipping empty lines.
+fn stdin_messages() -> impl Stream<Item = String> {
+    let lines = BufReader::new(tokio::io::stdin()).lines();
+    stream::unfold(lines, |mut lines| async move {
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) if line.is_empty() => continue,
+                Ok(Some(line)) => return Some((line, lines)),
+                Ok(None) => return None,
+                Err(err) => {
+                    tracing::error!("stopped reading stdin: {err}");
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+/// Wrap a message in the --prefix and --suffix text, so the run and sequence that
+/// sent it can be told from the message itself.
+fn wrap_message(args: &Args, message: &str, sequence: usize) -> String {
+    let seq = sequence.to_string();
+    format!(
+        "{}{message}{}",
+        args.prefix.replace("{seq}", &seq),
+        args.suffix.replace("{seq}", &seq)
+    )
+}
 
 fn lipsum_message(size: usize) -> String {
     let mut message = String::new();
@@ -20,21 +195,174 @@ fn lipsum_message(size: usize) -> String {
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
+    let args = Args::parse();
+    let tracing_handle = init_tracing();
     init();
-    let env = appenv::environment();
+    let env = match appenv::try_environment().and_then(appenv::Environment::require_signer) {
+        Ok(env) => env,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
+    tracing_handle.apply(&env);
     printenv(&env);
-    let message_sender = MessageSender::new(env.rpc_url, env.private_key).await?;
-    let message = lipsum_message(env.message_size as usize);
-    for _ in 0..env.message_count {
-        tracing::info!("Conversation: {}", env.conversation_id);
-        tracing::info!("Sending message bytes: {}", message.len());
-        tracing::debug!("Sending message: {}", message);
-        message_sender
-            .send_message(&env.conversation_id, &message)
-            .await?;
+    if args.print_config_json {
+        println!("{}", printenv_json(&env));
+    }
+    let mut config = MessageSenderConfig::default();
+    if let Some(contract_address) = &env.contract_address {
+        config.contract_address = contract_address.parse()?;
+    }
+    if let Some(gas_limit) = env.gas_limit {
+        config.gas_limit = gas_limit;
+    }
+    if let Some(confirmations) = env.confirmations {
+        config.required_confirmations = confirmations;
+    }
+    if let Some(level) = args.compression {
+        config = config.with_compression(level);
+    }
+    if let Some(bytes) = args.compression_threshold {
+        config = config.with_compression_threshold(bytes);
+    }
+    if env.use_flashbots {
+        #[cfg(feature = "flashbots")]
+        {
+            config.flashbots_relay = Some(conversation::FLASHBOTS_RELAY.to_string());
+        }
+        #[cfg(not(feature = "flashbots"))]
+        tracing::warn!("USE_FLASHBOTS is ignored without the flashbots feature");
+    }
+    if let Some(payload_key) = &env.payload_key {
+        config = config.with_encryptor(Arc::new(AesGcmEncryptor::from_hex(payload_key)?));
+    }
+    let private_key = match &env.mnemonic {
+        Some(phrase) => {
+            let hd_path = env.hd_path.as_deref().unwrap_or(DEFAULT_HD_PATH);
+            wallet_key_hex(&wallet_from_mnemonic(phrase, hd_path)?)
+        }
+        None => env.private_key.clone(),
+    };
+    // refuse to send from an account other than the configured one
+    if let Some(public_key) = &env.public_key {
+        if let Err(err) = check_key_pair(&private_key, public_key) {
+            if matches!(args.command, Some(Command::Check)) {
+                println!("FAIL configuration: {err}");
+                std::process::exit(1);
+            }
+            return Err(err.into());
+        }
+    }
+    if matches!(args.command, Some(Command::Check)) {
+        return check(env.rpc_url, private_key, config).await;
+    }
+    let message_sender = MessageSender::new_with_config(env.rpc_url, private_key, config).await?;
+    tracing::info!("Sending account: {:?}", message_sender.address());
+    let messages: BoxStream<String> = if args.stdin {
+        stdin_messages().boxed()
+    } else {
+        let message = lipsum_message(env.message_size as usize);
+        stream::iter(std::iter::repeat_n(message, env.message_count as usize)).boxed()
+    };
+    // space the starts of the sends, however long each takes to confirm
+    let messages = match env.message_interval_ms.filter(|ms| *ms > 0) {
+        Some(ms) => {
+            let mut ticker = tokio::time::interval(Duration::from_millis(ms));
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let ticks = stream::unfold(ticker, |mut ticker| async move {
+                ticker.tick().await;
+                Some(((), ticker))
+            });
+            messages.zip(ticks).map(|(message, _)| message).boxed()
+        }
+        None => messages,
+    };
+    for conversation_id in &env.conversation_ids {
+        tracing::info!(
+            "Conversation {} has id {}",
+            conversation_id,
+            message_sender.conversation_id(conversation_id)
+        );
+    }
+    let sender = message_sender.address();
+    let codec = JsonCodec::<Envelope>::new();
+    let started = Instant::now();
+    // set by the first failure unless continuing on error, so no further sends start
+    let stopped = AtomicBool::new(false);
+    let results: Vec<Option<Result<MessageReceipt, Error>>> = messages
+        .enumerate()
+        .map(|(sequence, message)| {
+            // the wrapped message is what is sent, and what counts towards the gas used
+            let message = wrap_message(&args, &message, sequence);
+            // round-robin the messages across the conversations
+            let conversation_id = &env.conversation_ids[sequence % env.conversation_ids.len()];
+            tracing::info!("Conversation: {}", conversation_id);
+            tracing::info!("Sending message bytes: {}", message.len());
+            tracing::debug!("Sending message: {}", message);
+            let message_sender = &message_sender;
+            let codec = &codec;
+            let stopped = &stopped;
+            async move {
+                if stopped.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let result = async {
+                    if !args.envelopes {
+                        return message_sender.send_message(conversation_id, &message).await;
+                    }
+                    let envelope = Envelope {
+                        sender,
+                        sequence: sequence as u64,
+                        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                        body: message.clone(),
+                    };
+                    message_sender
+                        .send_typed(conversation_id, &envelope, codec)
+                        .await
+                }
+                .await;
+                if let Err(err) = &result {
+                    tracing::error!("message {sequence} failed: {err:#}");
+                    if !args.continue_on_error {
+                        stopped.store(true, Ordering::Relaxed);
+                    }
+                }
+                Some(result)
+            }
+        })
+        .buffer_unordered(max(1, args.concurrency))
+        .collect()
+        .await;
+    let total = results.len();
+    let results: Vec<Result<MessageReceipt, Error>> = results.into_iter().flatten().collect();
+    let report = RunReport::new(&results, started);
+    tracing::info!(
+        "{}/{} sent, {} failed",
+        report.succeeded,
+        total,
+        report.failed
+    );
+    if report.attempted < total {
+        tracing::warn!(
+            "stopped after the first failure, {} messages not sent; pass --continue-on-error to keep going",
+            total - report.attempted
+        );
+    }
+    tracing::info!(
+        "Run report: attempted {}, succeeded {}, failed {}, gas used {}, cost {} ether, duration {:.3}s",
+        report.attempted,
+        report.succeeded,
+        report.failed,
+        report.gas_used,
+        report.cost_ether,
+        report.duration_secs
+    );
+    if args.report_json {
+        println!("{}", serde_json::to_string(&report)?);
+    }
+    if report.failed > 0 {
+        return Err(anyhow::anyhow!("{} messages failed", report.failed));
     }
     Ok(())
 }